@@ -0,0 +1,106 @@
+use eyre::Result;
+use std::time::Instant;
+
+use lopdf::{Document, Object, Stream, dictionary};
+use rasterizer::{FontCache, RenderSettings, draw_doc};
+use vello::Scene;
+
+const GLYPHS: usize = 10_000;
+const RUNS: u32 = 20;
+const PAGES: usize = 20;
+const SIZE: (u32, u32) = (612, 792);
+
+// Builds `PAGES` letter sized pages of Helvetica, sharing one font, with
+// `GLYPHS` glyphs on each
+fn text_doc() -> Result<Document> {
+    let line = "The quick brown fox jumps over the lazy dog. ";
+    let per_line = 100;
+    let lines = GLYPHS / per_line;
+
+    let mut doc = Document::with_version("1.5");
+    let font = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let pages = doc.new_object_id();
+    let mut kids: Vec<Object> = Vec::new();
+    for page in 0..PAGES {
+        let mut content = String::from("BT /F1 6 Tf 7 TL 20 770 Td\n");
+        let text: String = line.chars().cycle().skip(page).take(per_line).collect();
+        for _ in 0..lines {
+            content.push_str(&format!("({text}) Tj T*\n"));
+        }
+        content.push_str("ET\n");
+
+        let contents = doc.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages,
+            "MediaBox" => vec![0.into(), 0.into(), SIZE.0.into(), SIZE.1.into()],
+            "Contents" => contents,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } },
+        });
+        kids.push(page.into());
+    }
+    doc.objects.insert(
+        pages,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => PAGES as i64,
+        }),
+    );
+    let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages });
+    doc.trailer.set("Root", catalog);
+    Ok(doc)
+}
+
+// Times building the scene for a text heavy page, which is where glyph
+// outlining happens, then for a run of pages sharing a font cache; GPU work
+// isn't included
+fn main() -> Result<()> {
+    let doc = text_doc()?;
+    let settings = RenderSettings::default();
+
+    let mut scene = Scene::new();
+    draw_doc(
+        &doc,
+        &mut scene,
+        SIZE.0,
+        SIZE.1,
+        1,
+        &settings,
+        &FontCache::new(&settings),
+    )?;
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        scene.reset();
+        draw_doc(
+            &doc,
+            &mut scene,
+            SIZE.0,
+            SIZE.1,
+            1,
+            &settings,
+            &FontCache::new(&settings),
+        )?;
+    }
+    let elapsed = start.elapsed() / RUNS;
+    println!("{GLYPHS} glyphs: {elapsed:?} per page");
+
+    let fonts = FontCache::new(&settings);
+    let start = Instant::now();
+    for page in 1..=PAGES as u32 {
+        scene.reset();
+        draw_doc(&doc, &mut scene, SIZE.0, SIZE.1, page, &settings, &fonts)?;
+    }
+    let elapsed = start.elapsed() / PAGES as u32;
+    let (lookups, built) = fonts.glyph_stats();
+    let hit_rate = 100. * (lookups - built) as f64 / lookups.max(1) as f64;
+    println!(
+        "{PAGES} pages sharing fonts: {elapsed:?} per page, {hit_rate:.2}% of glyph outlines cached"
+    );
+    Ok(())
+}
@@ -1,24 +1,50 @@
 use eyre::{Result, WrapErr, eyre};
 use image::{ImageBuffer, Rgba, RgbaImage};
-use rasterizer::RenderSettings;
+use rasterizer::{RenderSettings, load_encrypted};
 use std::fs;
 use std::{env, process::ExitCode};
 
-use lopdf::Document;
 use pdfium_render::prelude::*;
 use rasterizer::offscreen::pdf_to_rgba_image;
 
-const PAGE: u16 = 1;
+const DEFAULT_PAGE: u16 = 1;
 const DEFAULT_SCALE: f32 = 3.0;
+// Renders scoring below this SSIM fail
+const DEFAULT_MIN_SSIM: f64 = 0.98;
+// SSIM compares the images in windows this many pixels square, this far apart
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STRIDE: u32 = 4;
 
-async fn compare_pdf_renderers(pdf_path: &str) -> Result<()> {
+/// How far our render is from pdfium's.
+struct Diff {
+    /// Structural similarity from 0 to 1, where 1 is identical.
+    ssim: f64,
+    different_pixels: u64,
+    percent_different: f64,
+    avg_diff: f64,
+    max_diff: u8,
+}
+
+impl Diff {
+    fn json(&self) -> String {
+        format!(
+            r#"{{"ssim": {}, "different_pixels": {}, "percent_different": {}, "avg_diff": {}, "max_diff": {}}}"#,
+            self.ssim, self.different_pixels, self.percent_different, self.avg_diff, self.max_diff
+        )
+    }
+}
+
+async fn compare_pdf_renderers(pdf_path: &str, page: u16, password: &str) -> Result<Diff> {
     let bytes =
         fs::read(pdf_path).wrap_err_with(|| eyre!("Failed to read PDF file: {}", pdf_path))?;
 
     // Render with our rasterizer
-    let doc = Document::load_mem(&bytes).wrap_err("Failed to parse PDF document")?;
-    let render_settings = RenderSettings { anti_alias: true };
-    let our_image = pdf_to_rgba_image(&doc, PAGE as u32, DEFAULT_SCALE, &render_settings).await?;
+    let doc = load_encrypted(&bytes, password).wrap_err("Failed to parse PDF document")?;
+    let render_settings = RenderSettings {
+        anti_alias: true,
+        ..Default::default()
+    };
+    let our_image = pdf_to_rgba_image(&doc, page as u32, DEFAULT_SCALE, &render_settings).await?;
     our_image
         .save("actual.png")
         .wrap_err("Failed to save actual.png")?;
@@ -28,17 +54,24 @@ async fn compare_pdf_renderers(pdf_path: &str) -> Result<()> {
     );
 
     let document = pdfium
-        .load_pdf_from_byte_slice(&bytes, None)
+        .load_pdf_from_byte_slice(&bytes, Some(password))
         .wrap_err("Failed to load PDF with pdfium")?;
 
     let page = document
         .pages()
-        .get(PAGE - 1)
+        .get(page - 1)
         .wrap_err("Failed to get page from pdfium document")?;
 
-    let width = (page.width().value * DEFAULT_SCALE) as u32;
-    let height = (page.height().value * DEFAULT_SCALE) as u32;
+    let scale = render_settings.scale(DEFAULT_SCALE);
+    let width = (page.width().value * scale) as u32;
+    let height = (page.height().value * scale) as u32;
 
+    // Both renderers paint the same background so only the page content differs
+    let [r, g, b, a] = render_settings
+        .background
+        .unwrap_or(peniko::Color::TRANSPARENT)
+        .to_rgba8()
+        .to_u8_array();
     let render_config = PdfRenderConfig::new()
         .set_target_width(width as i32)
         .set_target_height(height as i32)
@@ -47,7 +80,8 @@ async fn compare_pdf_renderers(pdf_path: &str) -> Result<()> {
         .set_path_smoothing(render_settings.anti_alias)
         .set_image_smoothing(render_settings.anti_alias)
         .set_text_smoothing(render_settings.anti_alias)
-        .set_format(PdfBitmapFormat::BGRx)
+        .set_clear_color(PdfColor::new(r, g, b, a))
+        .set_format(PdfBitmapFormat::BGRA)
         .disable_native_text_rendering(true);
 
     let pdfium_image = page
@@ -59,32 +93,37 @@ async fn compare_pdf_renderers(pdf_path: &str) -> Result<()> {
     pdfium_image
         .save("expected.png")
         .wrap_err("Failed to save expected.png")?;
-    compare_images(&our_image, &pdfium_image)?;
-    Ok(())
+    compare_images(&our_image, &pdfium_image)
 }
 
-fn compare_images(actual_img: &RgbaImage, expected_img: &RgbaImage) -> Result<()> {
+fn compare_images(actual_img: &RgbaImage, expected_img: &RgbaImage) -> Result<Diff> {
     let (actual_width, actual_height) = actual_img.dimensions();
     let (expected_width, expected_height) = expected_img.dimensions();
 
     if actual_width != expected_width || actual_height != expected_height {
-        return Ok(());
+        return Err(eyre!(
+            "Rendered {actual_width}x{actual_height}, but pdfium rendered {expected_width}x{expected_height}"
+        ));
     }
 
     let mut diff_img: RgbaImage = ImageBuffer::new(actual_width, actual_height);
-    let mut _total_diff = 0u64;
+    let mut total_diff = 0u64;
     let mut max_diff = 0u8;
+    let mut different_pixels = 0u64;
 
     for (x, y, actual_pixel) in actual_img.enumerate_pixels() {
         let expected_pixel = expected_img.get_pixel(x, y);
 
-        let r_diff = (actual_pixel[0] as i16 - expected_pixel[0] as i16).abs() as u8;
-        let g_diff = (actual_pixel[1] as i16 - expected_pixel[1] as i16).abs() as u8;
-        let b_diff = (actual_pixel[2] as i16 - expected_pixel[2] as i16).abs() as u8;
+        let r_diff = actual_pixel[0].abs_diff(expected_pixel[0]);
+        let g_diff = actual_pixel[1].abs_diff(expected_pixel[1]);
+        let b_diff = actual_pixel[2].abs_diff(expected_pixel[2]);
 
         let pixel_diff = r_diff.max(g_diff).max(b_diff);
         max_diff = max_diff.max(pixel_diff);
-        _total_diff += pixel_diff as u64;
+        total_diff += pixel_diff as u64;
+        if pixel_diff > 0 {
+            different_pixels += 1;
+        }
 
         // Scale difference for visibility (multiply by 3 to make differences more apparent)
         // let scaled_diff = (pixel_diff as u16 * 3).min(255) as u8;
@@ -96,20 +135,146 @@ fn compare_images(actual_img: &RgbaImage, expected_img: &RgbaImage) -> Result<()
         .save("difference.png")
         .wrap_err("Failed to save difference.png")?;
 
-    Ok(())
+    let pixels = (actual_width as u64 * actual_height as u64).max(1) as f64;
+    Ok(Diff {
+        ssim: ssim(actual_img, expected_img),
+        different_pixels,
+        percent_different: different_pixels as f64 * 100. / pixels,
+        avg_diff: total_diff as f64 / pixels,
+        max_diff,
+    })
+}
+
+// The mean SSIM of the images' luma over overlapping windows, which cares
+// about structure more than the exact value of antialiased edge pixels
+fn ssim(actual: &RgbaImage, expected: &RgbaImage) -> f64 {
+    const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+    const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+    let luma = |image: &RgbaImage, x: u32, y: u32| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+    };
+
+    let (width, height) = actual.dimensions();
+    let window = SSIM_WINDOW.min(width).min(height);
+    if window == 0 {
+        return 1.;
+    }
+    let (mut total, mut windows) = (0., 0);
+    for y0 in (0..=height - window).step_by(SSIM_STRIDE as usize) {
+        for x0 in (0..=width - window).step_by(SSIM_STRIDE as usize) {
+            let (mut sum_a, mut sum_e, mut sum_aa, mut sum_ee, mut sum_ae) = (0., 0., 0., 0., 0.);
+            for y in y0..y0 + window {
+                for x in x0..x0 + window {
+                    let (a, e) = (luma(actual, x, y), luma(expected, x, y));
+                    sum_a += a;
+                    sum_e += e;
+                    sum_aa += a * a;
+                    sum_ee += e * e;
+                    sum_ae += a * e;
+                }
+            }
+            let n = (window * window) as f64;
+            let (mean_a, mean_e) = (sum_a / n, sum_e / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_e = sum_ee / n - mean_e * mean_e;
+            let covariance = sum_ae / n - mean_a * mean_e;
+            total += ((2. * mean_a * mean_e + C1) * (2. * covariance + C2))
+                / ((mean_a * mean_a + mean_e * mean_e + C1) * (var_a + var_e + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
 }
 
 fn main() -> Result<ExitCode> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    let json = match args.iter().position(|arg| arg == "--json") {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    };
+    let min_ssim = match args.iter().position(|arg| arg == "--min-ssim") {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| eyre!("--min-ssim needs a score from 0 to 1"))?;
+            let min_ssim: f64 = value
+                .parse()
+                .wrap_err_with(|| eyre!("Invalid SSIM: {}", value))?;
+            args.drain(i..i + 2);
+            min_ssim
+        }
+        None => DEFAULT_MIN_SSIM,
+    };
+    let threshold = match args.iter().position(|arg| arg == "--threshold") {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| eyre!("--threshold needs a percentage"))?;
+            let threshold: f64 = value
+                .parse()
+                .wrap_err_with(|| eyre!("Invalid threshold: {}", value))?;
+            args.drain(i..i + 2);
+            Some(threshold)
+        }
+        None => None,
+    };
+    let password = match args.iter().position(|arg| arg == "--password") {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| eyre!("--password needs a value"))?
+                .clone();
+            args.drain(i..i + 2);
+            value
+        }
+        None => String::new(),
+    };
 
     match args.len() {
-        2 => {
+        2 | 3 => {
             let pdf_path = &args[1];
-            pollster::block_on(compare_pdf_renderers(pdf_path))?;
-            Ok(ExitCode::SUCCESS)
+            let page = match args.get(2) {
+                Some(page) => page
+                    .parse()
+                    .wrap_err_with(|| eyre!("Invalid page number: {}", page))?,
+                None => DEFAULT_PAGE,
+            };
+            if page == 0 {
+                return Err(eyre!("Pages are numbered from 1"));
+            }
+            let diff = pollster::block_on(compare_pdf_renderers(pdf_path, page, &password))?;
+            let pass = diff.ssim >= min_ssim
+                && threshold.is_none_or(|threshold| diff.percent_different <= threshold);
+            if json {
+                println!("{}", diff.json());
+            } else {
+                println!(
+                    "{}: SSIM {:.4} (at least {min_ssim} passes)",
+                    if pass { "PASS" } else { "FAIL" },
+                    diff.ssim
+                );
+                println!(
+                    "{} pixels differ ({:.2}%), average difference {:.2}, maximum {}",
+                    diff.different_pixels, diff.percent_different, diff.avg_diff, diff.max_diff
+                );
+            }
+            // A failing exit code lets CI gate on the comparison
+            Ok(if pass {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            })
         }
         _ => {
-            eprintln!("Usage: {} <pdf_file>", args[0]);
+            eprintln!(
+                "Usage: {} <pdf_file> [page] [--json] [--min-ssim SCORE] [--threshold PERCENT] [--password PASSWORD]",
+                args[0]
+            );
             Ok(ExitCode::FAILURE)
         }
     }
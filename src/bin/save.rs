@@ -1,47 +1,119 @@
 use eyre::{Result, WrapErr, eyre};
-use std::fs;
+use image::{ImageFormat, RgbImage, RgbaImage, codecs::jpeg::JpegEncoder};
+use std::fs::File;
+use std::io::BufWriter;
 use std::{env, process::ExitCode};
 
-use lopdf::Document;
-use rasterizer::RenderSettings;
+use peniko::Color;
 use rasterizer::offscreen::pdf_to_rgba_image;
+use rasterizer::{RenderSettings, open_encrypted};
 
-const PAGE: u32 = 1;
+const DEFAULT_PAGE: u32 = 1;
 const DEFAULT_SCALE: f32 = 2.0;
+const DEFAULT_QUALITY: u8 = 90;
 
-async fn save_pdf_to_png(pdf_path: &str, output_path: &str) -> Result<()> {
-    let bytes =
-        fs::read(pdf_path).wrap_err_with(|| eyre!("Failed to read PDF file: {}", pdf_path))?;
-    let doc = Document::load_mem(&bytes).wrap_err("Failed to parse PDF document")?;
+async fn save_pdf(
+    pdf_path: &str,
+    output_path: &str,
+    page: u32,
+    quality: Option<u8>,
+    password: &str,
+) -> Result<()> {
+    let doc = open_encrypted(pdf_path, password)?;
 
-    let image = pdf_to_rgba_image(&doc, PAGE, DEFAULT_SCALE, &RenderSettings::default()).await?;
+    let settings = RenderSettings::default();
+    let image = pdf_to_rgba_image(&doc, page, DEFAULT_SCALE, &settings).await?;
 
-    image
-        .save(output_path)
-        .wrap_err_with(|| eyre!("Failed to save PNG file: {}", output_path))?;
+    let format = ImageFormat::from_path(output_path)
+        .wrap_err_with(|| eyre!("Unknown image format for {}", output_path))?;
+    match format {
+        ImageFormat::Jpeg => {
+            let background = settings.background.unwrap_or(Color::WHITE);
+            let file = File::create(output_path)
+                .wrap_err_with(|| eyre!("Failed to create {}", output_path))?;
+            let encoder = JpegEncoder::new_with_quality(
+                BufWriter::new(file),
+                quality.unwrap_or(DEFAULT_QUALITY),
+            );
+            flatten(&image, background).write_with_encoder(encoder)?;
+        }
+        _ => {
+            if quality.is_some() {
+                eprintln!("--quality only applies to JPEG output; ignoring it");
+            }
+            image
+                .save_with_format(output_path, format)
+                .wrap_err_with(|| eyre!("Failed to save image file: {}", output_path))?;
+        }
+    }
 
     Ok(())
 }
 
+// JPEG has no alpha channel, so composite the page onto its background
+fn flatten(image: &RgbaImage, background: Color) -> RgbImage {
+    let [br, bg, bb, _] = background.to_rgba8().to_u8_array();
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let over =
+            |c: u8, back: u8| ((c as u16 * a as u16 + back as u16 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([over(r, br), over(g, bg), over(b, bb)])
+    })
+}
+
 fn main() -> Result<ExitCode> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
 
-    match args.len() {
-        2 => {
-            let pdf_path = &args[1];
-            let output_path = "out.png";
-            pollster::block_on(save_pdf_to_png(pdf_path, output_path))?;
-            Ok(ExitCode::SUCCESS)
+    let quality = match args.iter().position(|arg| arg == "--quality") {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| eyre!("--quality needs a value from 1 to 100"))?;
+            let quality: u8 = value
+                .parse()
+                .ok()
+                .filter(|q| (1..=100).contains(q))
+                .ok_or_else(|| eyre!("Invalid quality: {}", value))?;
+            args.drain(i..i + 2);
+            Some(quality)
         }
-        3 => {
+        None => None,
+    };
+    let password = match args.iter().position(|arg| arg == "--password") {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| eyre!("--password needs a value"))?
+                .clone();
+            args.drain(i..i + 2);
+            value
+        }
+        None => String::new(),
+    };
+
+    match args.len() {
+        2..=4 => {
             let pdf_path = &args[1];
-            let output_path = &args[2];
-            pollster::block_on(save_pdf_to_png(pdf_path, output_path))?;
+            let output_path = args.get(2).map_or("out.png", |s| s.as_str());
+            let page = match args.get(3) {
+                Some(page) => page
+                    .parse()
+                    .wrap_err_with(|| eyre!("Invalid page number: {}", page))?,
+                None => DEFAULT_PAGE,
+            };
+            pollster::block_on(save_pdf(pdf_path, output_path, page, quality, &password))?;
             Ok(ExitCode::SUCCESS)
         }
         _ => {
-            eprintln!("Usage: {} <pdf_file> [output.png]", args[0]);
+            eprintln!(
+                "Usage: {} <pdf_file> [output.png] [page] [--quality 1-100] [--password PASSWORD]",
+                args[0]
+            );
             eprintln!("If output file is not specified, defaults to 'out.png'");
+            eprintln!("The output format follows its extension: png, jpg, webp, tiff, ...");
+            eprintln!("--quality sets JPEG quality, {DEFAULT_QUALITY} by default");
+            eprintln!("--password unlocks encrypted documents");
+            eprintln!("Pages are numbered from 1, which is the default");
             Ok(ExitCode::FAILURE)
         }
     }
@@ -1,12 +1,11 @@
 use eyre::{Result, WrapErr, eyre};
-use std::fs;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{env, process::ExitCode};
 
 use lopdf::Document;
 use rasterizer::*;
-use vello::{AaConfig, Renderer, RendererOptions, Scene};
+use vello::{Renderer, RendererOptions, Scene};
 use wgpu::{Device, Queue, Surface};
 use winit::dpi::PhysicalSize;
 use winit::event::WindowEvent;
@@ -16,6 +15,9 @@ use winit::{application::ApplicationHandler, event_loop::EventLoop};
 struct App {
     size: PhysicalSize<u32>,
     doc: Document,
+    settings: RenderSettings,
+    fonts: FontCache,
+    page: u32,
     renderer: Option<Mutex<AppRenderer>>,
 }
 
@@ -30,7 +32,13 @@ struct AppRenderer {
 }
 
 impl AppRenderer {
-    fn draw(&mut self, doc: &Document) -> Result<()> {
+    fn draw(
+        &mut self,
+        doc: &Document,
+        settings: &RenderSettings,
+        fonts: &FontCache,
+        page: u32,
+    ) -> Result<()> {
         let size = self.window.inner_size();
 
         if self.intermediate_texture.width() != size.width
@@ -58,21 +66,24 @@ impl AppRenderer {
 
         use kurbo::{Affine, Rect};
         use peniko::Color;
-        scene.fill(
-            peniko::Fill::NonZero,
-            Affine::IDENTITY,
-            Color::WHITE,
-            None,
-            &Rect::new(0.0, 0.0, size.width as f64, size.height as f64),
-        );
+        if let Some(background) = settings.background {
+            scene.fill(
+                peniko::Fill::NonZero,
+                Affine::IDENTITY,
+                background,
+                None,
+                &Rect::new(0.0, 0.0, size.width as f64, size.height as f64),
+            );
+        }
 
         draw_doc(
             doc,
             &mut scene,
             size.width,
             size.height,
-            PAGE,
-            &RenderSettings::default(),
+            page,
+            settings,
+            fonts,
         )?;
 
         let intermediate_view = self
@@ -80,10 +91,10 @@ impl AppRenderer {
             .create_view(&wgpu::TextureViewDescriptor::default());
 
         let render_params = vello::RenderParams {
-            base_color: Color::WHITE,
+            base_color: settings.background.unwrap_or(Color::TRANSPARENT),
             width: size.width,
             height: size.height,
-            antialiasing_method: AaConfig::Msaa16,
+            antialiasing_method: settings.aa_config(),
         };
 
         self.renderer
@@ -303,7 +314,7 @@ async fn start(window: Arc<Window>) -> Result<AppRenderer> {
     let renderer = Renderer::new(
         &device,
         RendererOptions {
-            use_cpu: false,
+            use_cpu: RenderSettings::default().use_cpu,
             antialiasing_support: vello::AaSupport::all(),
             num_init_threads: None,
             pipeline_cache: None,
@@ -312,11 +323,11 @@ async fn start(window: Arc<Window>) -> Result<AppRenderer> {
     .map_err(|e| eyre!("Failed to create renderer: {:?}", e))?;
 
     Ok(AppRenderer {
-        window: window,
-        renderer: renderer,
-        queue: queue,
-        surface: surface,
-        device: device,
+        window,
+        renderer,
+        queue,
+        surface,
+        device,
         intermediate_texture,
         intermediate_format,
     })
@@ -347,35 +358,36 @@ impl ApplicationHandler for App {
             }
             WindowEvent::RedrawRequested => {
                 let renderer = self.renderer.as_mut().unwrap().get_mut().unwrap();
-                renderer.draw(&self.doc).unwrap();
+                renderer
+                    .draw(&self.doc, &self.settings, &self.fonts, self.page)
+                    .unwrap();
             }
             _ => (),
         }
     }
 }
 
-const PAGE: u32 = 1;
+const DEFAULT_PAGE: u32 = 1;
 
 const DEFAULT_SCALE: f32 = 2.;
 
-fn go(path: &str, scale: f32) -> Result<()> {
-    let bytes = fs::read(path)?;
-    let doc = Document::load_mem(&bytes)?;
+fn go(path: &str, page_number: u32, scale: f32) -> Result<()> {
+    let doc = open(path)?;
 
-    let page_id = doc
-        .get_pages()
-        .get(&PAGE)
-        .ok_or_else(|| eyre!("expected page"))?
-        .clone();
+    let page_id = validate_page(&doc, page_number)?;
 
     let page = doc.get_dictionary(page_id)?;
-    let size = dimensions(page)?;
+    let size = dimensions(&doc, page)?;
 
     let event_loop = EventLoop::new()?;
 
+    let settings = RenderSettings::default();
     let mut app = App {
         renderer: None,
-        doc: doc,
+        doc,
+        fonts: FontCache::new(&settings),
+        settings,
+        page: page_number,
         size: PhysicalSize {
             width: (size.0 * scale) as u32,
             height: (size.1 * scale) as u32,
@@ -390,11 +402,17 @@ fn go(path: &str, scale: f32) -> Result<()> {
 
 fn main() -> Result<ExitCode> {
     let mut args = env::args().skip(1);
-    if let (Some(file), None) = (args.next(), args.next()) {
-        go(&file, DEFAULT_SCALE)?;
+    if let (Some(file), page, None) = (args.next(), args.next(), args.next()) {
+        let page = match page {
+            Some(page) => page
+                .parse()
+                .wrap_err_with(|| eyre!("Invalid page number: {}", page))?,
+            None => DEFAULT_PAGE,
+        };
+        go(&file, page, DEFAULT_SCALE)?;
         Ok(ExitCode::SUCCESS)
     } else {
-        eprintln!("Usage: [filename]");
+        eprintln!("Usage: [filename] [page]");
         Ok(ExitCode::FAILURE)
     }
 }
@@ -0,0 +1,143 @@
+use eyre::Result;
+use kurbo::{Affine, BezPath, Rect, Shape, Stroke};
+use lopdf::Document;
+use peniko::{BlendMode, BrushRef, Compose, Fill, Image};
+
+use crate::{
+    FontCache, RasterError, RenderSettings, canvas::Canvas, dimensions, draw_doc, validate_page,
+};
+
+/// The bounds of everything a 1-based page paints, in pixels of a render at
+/// scale 1 with the origin at the top left, or `None` if it paints nothing.
+/// Paths are measured rather than rasterized, so strokes and clips make the
+/// box a little larger than the ink can be.
+pub fn content_bbox(doc: &Document, page: u32) -> Result<Option<Rect>, RasterError> {
+    let size = dimensions(doc, doc.get_dictionary(validate_page(doc, page)?)?)?;
+    let (width, height) = (size.0 as u32, size.1 as u32);
+    let page_rect = Rect::new(0., 0., width as f64, height as f64);
+
+    let settings = RenderSettings::default();
+    let mut canvas = BoundsCanvas {
+        bounds: None,
+        layers: vec![Some(page_rect)],
+    };
+    draw_doc(
+        doc,
+        &mut canvas,
+        width,
+        height,
+        page,
+        &settings,
+        &FontCache::new(&settings),
+    )?;
+    Ok(canvas.bounds)
+}
+
+struct BoundsCanvas {
+    bounds: Option<Rect>,
+    /// Each layer's clip in device space, or `None` for layers whose
+    /// content isn't painted as it is, like soft masks
+    layers: Vec<Option<Rect>>,
+}
+
+impl BoundsCanvas {
+    fn paint(&mut self, rect: Rect) {
+        let Some(Some(clip)) = self.layers.last() else {
+            return;
+        };
+        let rect = rect.intersect(*clip);
+        if rect.width() <= 0. || rect.height() <= 0. {
+            return;
+        }
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => bounds.union(rect),
+            None => rect,
+        });
+    }
+}
+
+impl Canvas for BoundsCanvas {
+    fn fill(&mut self, _rule: Fill, transform: Affine, _brush: BrushRef<'_>, shape: &BezPath) {
+        self.paint((transform * shape).bounding_box());
+    }
+
+    fn stroke(&mut self, style: &Stroke, transform: Affine, _brush: BrushRef<'_>, shape: &BezPath) {
+        // Miters can reach further than half the width, but rarely by much,
+        // and hairlines are a pixel wide whatever their width
+        let rect = shape
+            .bounding_box()
+            .inflate(style.width / 2., style.width / 2.);
+        self.paint(transform.transform_rect_bbox(rect).inflate(0.5, 0.5));
+    }
+
+    fn push_layer(&mut self, blend: BlendMode, _alpha: f32, transform: Affine, clip: &BezPath) {
+        let outer = self.layers.last().copied().flatten();
+        let layer = outer
+            .filter(|_| matches!(blend.compose, Compose::SrcOver))
+            .map(|outer| outer.intersect((transform * clip).bounding_box()));
+        self.layers.push(layer);
+    }
+
+    fn pop_layer(&mut self) {
+        if self.layers.len() > 1 {
+            self.layers.pop();
+        }
+    }
+
+    fn draw_image(&mut self, image: &Image, transform: Affine) {
+        let rect = Rect::new(0., 0., image.width as f64, image.height as f64);
+        self.paint(transform.transform_rect_bbox(rect));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::Dictionary;
+
+    use super::*;
+    use crate::testing::*;
+
+    fn bbox(content: &str) -> Option<Rect> {
+        content_bbox(&page(Dictionary::new(), content), 1).unwrap()
+    }
+
+    #[test]
+    fn content_bbox_covers_what_the_page_paints() {
+        assert_eq!(bbox(""), None);
+        assert_eq!(
+            bbox("10 20 30 40 re f"),
+            Some(Rect::new(10., 40., 40., 80.))
+        );
+        assert_eq!(
+            bbox("10 20 30 40 re f 70 70 10 10 re f"),
+            Some(Rect::new(10., 20., 80., 80.))
+        );
+        // Strokes reach half their width past the path, and half a pixel more
+        assert_eq!(
+            bbox("4 w 10 10 m 20 10 l S"),
+            Some(Rect::new(7.5, 87.5, 22.5, 92.5))
+        );
+    }
+
+    #[test]
+    fn content_bbox_is_clipped() {
+        assert_eq!(
+            bbox("0 0 50 50 re W n 10 10 80 80 re f"),
+            Some(Rect::new(10., 50., 50., 90.))
+        );
+        assert_eq!(
+            bbox("-50 -50 500 500 re f"),
+            Some(Rect::new(0., 0., 100., 100.))
+        );
+        assert_eq!(bbox("0 0 10 10 re W n 50 50 10 10 re f"), None);
+    }
+
+    #[test]
+    fn content_bbox_leaves_out_soft_masks() {
+        let doc = soft_mask_page("20 20 10 10 re f");
+        assert_eq!(
+            content_bbox(&doc, 1).unwrap(),
+            Some(Rect::new(20., 70., 30., 80.))
+        );
+    }
+}
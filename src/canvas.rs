@@ -0,0 +1,106 @@
+use kurbo::{Affine, BezPath, Stroke};
+use peniko::{BlendMode, BrushRef, Fill, Image};
+use vello::Scene;
+
+/// What the content stream interpreter paints onto, so that pages can be
+/// drawn somewhere other than a vello `Scene`. Paths are in device space
+/// once `transform` is applied, and a layer's content is composited onto
+/// what's beneath it when the layer is popped. Layers are always balanced
+/// within a page.
+pub trait Canvas {
+    fn fill(&mut self, rule: Fill, transform: Affine, brush: BrushRef<'_>, shape: &BezPath);
+
+    fn stroke(&mut self, style: &Stroke, transform: Affine, brush: BrushRef<'_>, shape: &BezPath);
+
+    /// Starts a layer clipped to `clip` by the nonzero rule, blended with
+    /// `blend` at `alpha` once popped.
+    fn push_layer(&mut self, blend: BlendMode, alpha: f32, transform: Affine, clip: &BezPath);
+
+    fn pop_layer(&mut self);
+
+    fn draw_image(&mut self, image: &Image, transform: Affine);
+}
+
+impl Canvas for Scene {
+    fn fill(&mut self, rule: Fill, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        Scene::fill(self, rule, transform, brush, None, shape);
+    }
+
+    fn stroke(&mut self, style: &Stroke, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        Scene::stroke(self, style, transform, brush, None, shape);
+    }
+
+    fn push_layer(&mut self, blend: BlendMode, alpha: f32, transform: Affine, clip: &BezPath) {
+        Scene::push_layer(self, blend, alpha, transform, clip);
+    }
+
+    fn pop_layer(&mut self) {
+        Scene::pop_layer(self);
+    }
+
+    fn draw_image(&mut self, image: &Image, transform: Affine) {
+        Scene::draw_image(self, image, transform);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::Shape;
+    use lopdf::Dictionary;
+
+    use super::*;
+    use crate::{FontCache, RenderSettings, paint_page, testing::*};
+
+    // Records what's painted, as the operation and its device space bounds
+    #[derive(Default)]
+    struct Recording(Vec<(&'static str, kurbo::Rect)>);
+
+    impl Canvas for Recording {
+        fn fill(&mut self, _: Fill, transform: Affine, _: BrushRef<'_>, shape: &BezPath) {
+            self.0
+                .push(("fill", (transform * shape.clone()).bounding_box()));
+        }
+
+        fn stroke(&mut self, _: &Stroke, transform: Affine, _: BrushRef<'_>, shape: &BezPath) {
+            self.0
+                .push(("stroke", (transform * shape.clone()).bounding_box()));
+        }
+
+        fn push_layer(&mut self, _: BlendMode, _: f32, transform: Affine, clip: &BezPath) {
+            self.0
+                .push(("push", (transform * clip.clone()).bounding_box()));
+        }
+
+        fn pop_layer(&mut self) {
+            self.0.push(("pop", kurbo::Rect::ZERO));
+        }
+
+        fn draw_image(&mut self, image: &Image, transform: Affine) {
+            let size = kurbo::Rect::new(0., 0., image.width as f64, image.height as f64);
+            self.0.push(("image", transform.transform_rect_bbox(size)));
+        }
+    }
+
+    #[test]
+    fn pages_are_painted_onto_any_canvas() {
+        let doc = page(
+            Dictionary::new(),
+            "q 0 0 50 50 re W n 10 10 20 20 re f Q 60 60 10 10 re S",
+        );
+        let mut canvas = Recording::default();
+        let settings = RenderSettings::default();
+        let fonts = FontCache::new(&settings);
+        paint_page(&doc, &mut canvas, 1, 100, 100, &settings, &fonts).unwrap();
+        let rect = kurbo::Rect::new;
+        assert_eq!(
+            canvas.0,
+            [
+                ("fill", rect(0., 0., 100., 100.)),
+                ("push", rect(0., 50., 50., 100.)),
+                ("fill", rect(10., 70., 30., 90.)),
+                ("pop", kurbo::Rect::ZERO),
+                ("stroke", rect(60., 30., 70., 40.)),
+            ]
+        );
+    }
+}
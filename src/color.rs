@@ -0,0 +1,246 @@
+use eyre::{Result, bail, eyre};
+use lopdf::{Dictionary, Document, Object};
+use peniko::Color;
+
+use crate::{FromPDF, RasterError, get};
+
+#[derive(Debug, Clone, Default)]
+pub enum ColorSpace {
+    #[default]
+    DeviceGray,
+    DeviceRGB,
+    DeviceCMYK,
+    Pattern,
+    Indexed {
+        base: Box<ColorSpace>,
+        hival: u8,
+        lookup: Vec<u8>,
+    },
+}
+
+impl ColorSpace {
+    pub fn components(&self) -> usize {
+        match self {
+            ColorSpace::DeviceGray => 1,
+            ColorSpace::DeviceRGB => 3,
+            ColorSpace::DeviceCMYK => 4,
+            ColorSpace::Pattern => 0,
+            ColorSpace::Indexed { .. } => 1,
+        }
+    }
+
+    pub fn initial_color(&self, alpha: f32) -> Color {
+        match self {
+            ColorSpace::Indexed { .. } => {
+                let [r, g, b] = self.to_rgb(&[0.]).unwrap_or([0., 0., 0.]);
+                Color::new([r, g, b, alpha])
+            }
+            _ => Color::new([0., 0., 0., alpha]),
+        }
+    }
+
+    pub fn to_color(&self, operands: &[Object], alpha: f32) -> Result<Color> {
+        let components = operands
+            .iter()
+            .map(|o| o.as_float())
+            .collect::<lopdf::Result<Vec<f32>>>()?;
+
+        // Fall back to the operand count when it disagrees with the active
+        // space, which happens when a file sets colors without `cs`.
+        let space = if components.len() == self.components() {
+            self
+        } else {
+            match components.len() {
+                1 => &ColorSpace::DeviceGray,
+                3 => &ColorSpace::DeviceRGB,
+                4 => &ColorSpace::DeviceCMYK,
+                n => bail!("Unexpected {n} color components for {:?}", self),
+            }
+        };
+
+        let [r, g, b] = space.to_rgb(&components)?;
+        Ok(Color::new([r, g, b, alpha]))
+    }
+
+    pub fn to_rgb(&self, components: &[f32]) -> Result<[f32; 3]> {
+        Ok(match (self, components) {
+            (ColorSpace::DeviceGray, [g]) => [*g, *g, *g],
+            (ColorSpace::DeviceRGB, [r, g, b]) => [*r, *g, *b],
+            (ColorSpace::DeviceCMYK, [c, m, y, k]) => [
+                (1. - c) * (1. - k),
+                (1. - m) * (1. - k),
+                (1. - y) * (1. - k),
+            ],
+            (
+                ColorSpace::Indexed {
+                    base,
+                    hival,
+                    lookup,
+                },
+                [index],
+            ) => {
+                let n = base.components();
+                let index = (index.round().max(0.) as usize).min(*hival as usize);
+                let entry = lookup
+                    .get(index * n..(index + 1) * n)
+                    .ok_or_else(|| eyre!("Index {index} out of range of color lookup table"))?;
+                let components: Vec<f32> = entry.iter().map(|c| *c as f32 / 255.).collect();
+                return base.to_rgb(&components);
+            }
+            (space, components) => bail!("Cannot convert {:?} in {:?}", components, space),
+        })
+    }
+}
+
+/// Whether a color's operands are CMYK with a component of 0, leaving an
+/// ink that overprinting with `OPM 1` doesn't paint.
+pub fn zero_ink(operands: &[Object]) -> bool {
+    operands.len() == 4 && operands.iter().any(|o| o.as_float().is_ok_and(|c| c == 0.))
+}
+
+impl FromPDF for ColorSpace {
+    fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
+        let (_, root) = doc.dereference(root)?;
+        match root {
+            Object::Name(name) => from_name(name),
+            Object::Array(items) => match &items[..] {
+                [Object::Name(family), ..] => match family.as_slice() {
+                    b"CalGray" => Ok(ColorSpace::DeviceGray),
+                    b"CalRGB" => Ok(ColorSpace::DeviceRGB),
+                    b"Pattern" => Ok(ColorSpace::Pattern),
+                    b"Indexed" | b"I" => match &items[1..] {
+                        [base, hival, lookup] => Ok(ColorSpace::Indexed {
+                            base: Box::new(ColorSpace::from_pdf(doc, base)?),
+                            hival: hival.as_i64()?.clamp(0, 255) as u8,
+                            lookup: get(doc, lookup)?,
+                        }),
+                        _ => bail!("Expected [/Indexed base hival lookup]"),
+                    },
+                    b"ICCBased" => match &items[1..] {
+                        [profile] => icc_based(doc, profile),
+                        _ => bail!("Expected [/ICCBased stream]"),
+                    },
+                    _ => from_name(family),
+                },
+                _ => bail!("Expected [/Family ...] color space, got {:?}", items),
+            },
+            other => bail!("Expected color space, got {:?}", other),
+        }
+    }
+}
+
+// ICC transforms aren't applied; profiles are treated as the device space
+// with the same number of components.
+fn icc_based(doc: &Document, profile: &Object) -> Result<ColorSpace> {
+    let dict = &doc.dereference(profile)?.1.as_stream()?.dict;
+    if let Ok(alternate) = dict.get(b"Alternate")
+        && let Ok(space) = ColorSpace::from_pdf(doc, alternate)
+    {
+        return Ok(space);
+    }
+    match dict.get(b"N")?.as_i64()? {
+        1 => Ok(ColorSpace::DeviceGray),
+        3 => Ok(ColorSpace::DeviceRGB),
+        4 => Ok(ColorSpace::DeviceCMYK),
+        n => bail!(RasterError::UnsupportedColorSpace(format!(
+            "ICCBased with {n} components"
+        ))),
+    }
+}
+
+fn from_name(name: &[u8]) -> Result<ColorSpace> {
+    match name {
+        b"DeviceGray" | b"G" | b"CalGray" => Ok(ColorSpace::DeviceGray),
+        b"DeviceRGB" | b"RGB" | b"CalRGB" => Ok(ColorSpace::DeviceRGB),
+        b"DeviceCMYK" | b"CMYK" => Ok(ColorSpace::DeviceCMYK),
+        b"Pattern" => Ok(ColorSpace::Pattern),
+        other => bail!(RasterError::UnsupportedColorSpace(
+            String::from_utf8_lossy(other).into_owned()
+        )),
+    }
+}
+
+pub fn resolve(doc: &Document, resources: &Dictionary, name: &[u8]) -> Result<ColorSpace> {
+    match from_name(name) {
+        Ok(space) => Ok(space),
+        Err(unsupported) => {
+            let Ok(space) = doc
+                .get_dict_in_dict(resources, b"ColorSpace")
+                .and_then(|spaces| spaces.get(name))
+            else {
+                return Err(unsupported);
+            };
+            ColorSpace::from_pdf(doc, space)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Stream, StringFormat, dictionary};
+
+    use super::*;
+
+    fn indexed() -> Object {
+        Object::Array(vec![
+            Object::Name(b"Indexed".to_vec()),
+            Object::Name(b"DeviceRGB".to_vec()),
+            1.into(),
+            Object::String(vec![255, 0, 0, 0, 255, 0], StringFormat::Hexadecimal),
+        ])
+    }
+
+    #[test]
+    fn indexed_colors_are_looked_up_in_the_palette() {
+        let space = ColorSpace::from_pdf(&Document::new(), &indexed()).unwrap();
+        assert_eq!(space.components(), 1);
+        assert_eq!(space.to_rgb(&[0.]).unwrap(), [1., 0., 0.]);
+        assert_eq!(space.to_rgb(&[1.]).unwrap(), [0., 1., 0.]);
+        // Indices past hival are clamped to it
+        assert_eq!(space.to_rgb(&[7.]).unwrap(), [0., 1., 0.]);
+        assert_eq!(space.initial_color(1.), Color::new([1., 0., 0., 1.]));
+    }
+
+    #[test]
+    fn icc_based_spaces_fall_back_to_their_component_count() {
+        let mut doc = Document::new();
+        let mut icc = |dict: Dictionary| {
+            let profile = doc.add_object(Stream::new(dict, Vec::new()));
+            Object::Array(vec![Object::Name(b"ICCBased".to_vec()), profile.into()])
+        };
+        let cmyk = icc(dictionary! { "N" => 4 });
+        let alternate = icc(dictionary! { "N" => 3, "Alternate" => "DeviceGray" });
+        let unknown = icc(dictionary! { "N" => 2 });
+
+        assert!(matches!(
+            ColorSpace::from_pdf(&doc, &cmyk).unwrap(),
+            ColorSpace::DeviceCMYK
+        ));
+        assert!(matches!(
+            ColorSpace::from_pdf(&doc, &alternate).unwrap(),
+            ColorSpace::DeviceGray
+        ));
+        let error = ColorSpace::from_pdf(&doc, &unknown).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(RasterError::UnsupportedColorSpace(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_color_spaces_are_unsupported() {
+        let resources = dictionary! {
+            "ColorSpace" => dictionary! { "CS0" => "DeviceRGB" },
+        };
+        let doc = Document::new();
+        assert!(matches!(
+            resolve(&doc, &resources, b"CS0").unwrap(),
+            ColorSpace::DeviceRGB
+        ));
+        let error = resolve(&doc, &resources, b"Lab").unwrap_err();
+        assert!(matches!(
+            RasterError::from(error),
+            RasterError::UnsupportedColorSpace(name) if name == "Lab"
+        ));
+    }
+}
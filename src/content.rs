@@ -0,0 +1,306 @@
+use eyre::{Result, eyre};
+use lopdf::{
+    Dictionary, Object, Stream,
+    content::{Content, Operation},
+};
+
+// lopdf only reads alphabetic operators, so the Type3 glyph metric operators
+// are renamed while it parses them
+const DIGIT_OPERATORS: [(&str, &str); 2] = [("d0", "dZero"), ("d1", "dOne")];
+
+/// Decodes a content stream, handling inline images (`BI ... ID ... EI`)
+/// ourselves since lopdf can't parse filtered ones. Each becomes a `BI`
+/// operation whose operand is the image as a stream with its keys expanded.
+pub fn decode_content(raw: &[u8]) -> Result<Content> {
+    let mut rest = Vec::with_capacity(raw.len());
+    let mut images = Vec::new();
+
+    let mut i = 0;
+    let mut copied = 0;
+    // How many dictionaries the scan is inside, where tokens aren't operators
+    let mut depth = 0usize;
+    while i < raw.len() {
+        match raw[i] {
+            b'(' => i = skip_string(raw, i),
+            b'<' if raw.get(i + 1) == Some(&b'<') => {
+                depth += 1;
+                i += 1;
+            }
+            b'>' if raw.get(i + 1) == Some(&b'>') => {
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            // Names, such as a resource called /d0, aren't operators either
+            b'/' => {
+                i += 1;
+                while i < raw.len() && is_regular(raw[i]) {
+                    i += 1;
+                }
+                continue;
+            }
+            b'<' => {
+                i += raw[i..]
+                    .iter()
+                    .position(|b| *b == b'>')
+                    .unwrap_or(raw.len() - i)
+            }
+            b'%' => {
+                i += raw[i..]
+                    .iter()
+                    .position(|b| *b == b'\n' || *b == b'\r')
+                    .unwrap_or(raw.len() - i)
+            }
+            b if is_regular(b) => {
+                let start = i;
+                while i < raw.len() && is_regular(raw[i]) {
+                    i += 1;
+                }
+                let token = &raw[start..i];
+                if depth > 0 {
+                    continue;
+                }
+                if token == b"BI" {
+                    let (image, end) = inline_image(raw, i)?;
+                    rest.extend_from_slice(&raw[copied..start]);
+                    rest.extend(format!("{} BI\n", images.len()).bytes());
+                    images.push(image);
+                    i = end;
+                    copied = end;
+                } else if let Some((_, renamed)) = DIGIT_OPERATORS
+                    .iter()
+                    .find(|(op, _)| op.as_bytes() == token)
+                {
+                    rest.extend_from_slice(&raw[copied..start]);
+                    rest.extend_from_slice(renamed.as_bytes());
+                    copied = i;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    rest.extend_from_slice(&raw[copied.min(raw.len())..]);
+
+    let mut content = Content::decode(&rest)?;
+    for operation in &mut content.operations {
+        if let ("BI", [Object::Integer(index)]) =
+            (operation.operator.as_str(), &operation.operands[..])
+        {
+            let image = images.get(*index as usize).cloned();
+            *operation = Operation::new("BI", image.map(Object::Stream).into_iter().collect());
+        }
+        if let Some((op, _)) = DIGIT_OPERATORS
+            .iter()
+            .find(|(_, renamed)| operation.operator == *renamed)
+        {
+            operation.operator = op.to_string();
+        }
+    }
+    Ok(content)
+}
+
+fn is_regular(b: u8) -> bool {
+    !b.is_ascii_whitespace() && !b"()<>[]{}/%".contains(&b) && b != 0
+}
+
+// Skips a literal string starting at `start`, returning the index of its closing parenthesis
+fn skip_string(raw: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < raw.len() {
+        match raw[i] {
+            b'\\' => i += 1,
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    raw.len()
+}
+
+// Parses an inline image whose dictionary starts at `start`, just after
+// `BI`, returning it and where its `EI` ends
+fn inline_image(raw: &[u8], start: usize) -> Result<(Stream, usize)> {
+    let id = find_id(raw, start).ok_or_else(|| eyre!("Inline image without ID"))?;
+    let mut dict_source = b"<<".to_vec();
+    dict_source.extend_from_slice(&raw[start..id]);
+    dict_source.extend_from_slice(b">> BI");
+    let dict = match Content::decode(&dict_source)?.operations.first() {
+        Some(Operation { operands, .. }) => match operands.first() {
+            Some(Object::Dictionary(dict)) => expand(dict),
+            _ => return Err(eyre!("Malformed inline image dictionary")),
+        },
+        None => return Err(eyre!("Malformed inline image dictionary")),
+    };
+
+    // A single whitespace byte separates ID from the data
+    let data_start = (id + 3).min(raw.len());
+    let known_end = unfiltered_length(&dict)
+        .map(|length| data_start + length)
+        .filter(|end| ends_with_ei(raw, *end));
+    let data = match known_end {
+        Some(end) => &raw[data_start..end],
+        None => {
+            let end = find_ei(raw, data_start).ok_or_else(|| eyre!("Inline image without EI"))?;
+            raw[data_start..end].trim_ascii_end()
+        }
+    };
+
+    let data_end = data_start + data.len();
+    let end = raw[data_end..]
+        .windows(2)
+        .position(|w| w == b"EI")
+        .map_or(raw.len(), |p| data_end + p + 2);
+    Ok((Stream::new(dict, data.to_vec()), end))
+}
+
+// Finds the ID keyword ending an inline image's dictionary
+fn find_id(raw: &[u8], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i + 1 < raw.len() {
+        match raw[i] {
+            b'(' => i = skip_string(raw, i),
+            b'I' if raw[i + 1] == b'D'
+                && (i == 0 || !is_regular(raw[i - 1]))
+                && raw.get(i + 2).is_none_or(|b| b.is_ascii_whitespace()) =>
+            {
+                return Some(i);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn ends_with_ei(raw: &[u8], end: usize) -> bool {
+    let Some(tail) = raw.get(end..) else {
+        return false;
+    };
+    let tail = tail.trim_ascii_start();
+    tail.starts_with(b"EI") && tail.get(2).is_none_or(|b| !is_regular(*b))
+}
+
+// Finds the EI ending image data when its length isn't known up front
+fn find_ei(raw: &[u8], start: usize) -> Option<usize> {
+    (start..raw.len().saturating_sub(1)).find(|&i| {
+        raw[i..].starts_with(b"EI")
+            && (i == start || raw[i - 1].is_ascii_whitespace())
+            && raw.get(i + 2).is_none_or(|b| !is_regular(*b))
+    })
+}
+
+// How many bytes of data an unfiltered inline image has, if its color space says
+fn unfiltered_length(dict: &Dictionary) -> Option<usize> {
+    if dict.has(b"Filter") {
+        return None;
+    }
+    let int = |key: &[u8]| dict.get(key).and_then(|v| v.as_i64()).ok();
+    let mask = dict
+        .get(b"ImageMask")
+        .and_then(|m| m.as_bool())
+        .unwrap_or(false);
+    let (components, bpc) = if mask {
+        (1, 1)
+    } else {
+        let components = match dict.get(b"ColorSpace").ok()? {
+            Object::Name(name) => match name.as_slice() {
+                b"DeviceGray" | b"CalGray" => 1,
+                b"DeviceRGB" | b"CalRGB" => 3,
+                b"DeviceCMYK" => 4,
+                _ => return None,
+            },
+            Object::Array(space) if space.first()?.as_name().ok()? == b"Indexed" => 1,
+            _ => return None,
+        };
+        (components, int(b"BitsPerComponent")?)
+    };
+    let row = (int(b"Width")? * components * bpc + 7) / 8;
+    Some((row * int(b"Height")?) as usize)
+}
+
+// Replaces the abbreviated keys and names inline images may use
+fn expand(dict: &Dictionary) -> Dictionary {
+    let mut expanded = Dictionary::new();
+    for (key, value) in dict.iter() {
+        let key: &[u8] = match key.as_slice() {
+            b"W" => b"Width",
+            b"H" => b"Height",
+            b"BPC" => b"BitsPerComponent",
+            b"CS" => b"ColorSpace",
+            b"F" => b"Filter",
+            b"DP" => b"DecodeParms",
+            b"D" => b"Decode",
+            b"IM" => b"ImageMask",
+            b"I" => b"Interpolate",
+            key => key,
+        };
+        expanded.set(key, expand_name(value));
+    }
+    expanded
+}
+
+fn expand_name(value: &Object) -> Object {
+    match value {
+        Object::Name(name) => Object::Name(
+            match name.as_slice() {
+                b"G" => b"DeviceGray".as_slice(),
+                b"RGB" => b"DeviceRGB",
+                b"CMYK" => b"DeviceCMYK",
+                b"I" => b"Indexed",
+                b"AHx" => b"ASCIIHexDecode",
+                b"A85" => b"ASCII85Decode",
+                b"LZW" => b"LZWDecode",
+                b"Fl" => b"FlateDecode",
+                b"RL" => b"RunLengthDecode",
+                b"CCF" => b"CCITTFaxDecode",
+                b"DCT" => b"DCTDecode",
+                name => name,
+            }
+            .to_vec(),
+        ),
+        Object::Array(items) => Object::Array(items.iter().map(expand_name).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operators(content: &Content) -> Vec<&str> {
+        content
+            .operations
+            .iter()
+            .map(|op| op.operator.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn glyph_metric_operators_are_parsed() {
+        let content = decode_content(b"500 0 d0 1000 0 0 0 1000 1000 d1").unwrap();
+        assert_eq!(operators(&content), ["d0", "d1"]);
+        assert_eq!(content.operations[1].operands.len(), 6);
+    }
+
+    #[test]
+    fn only_operators_are_renamed() {
+        let content =
+            decode_content(b"/Span <</ActualText (d0) /Alt /d1>> BDC /d1 gs EMC").unwrap();
+        assert_eq!(operators(&content), ["BDC", "gs", "EMC"]);
+        let properties = content.operations[0].operands[1].as_dict().unwrap();
+        assert_eq!(
+            properties.get(b"ActualText").unwrap().as_str().unwrap(),
+            b"d0"
+        );
+        assert_eq!(properties.get(b"Alt").unwrap().as_name().unwrap(), b"d1");
+        assert_eq!(content.operations[1].operands[0].as_name().unwrap(), b"d1");
+    }
+}
@@ -0,0 +1,377 @@
+use eyre::{Result, eyre};
+use image::RgbaImage;
+use kurbo::{Affine, BezPath, Cap, Join, PathEl, Stroke};
+use lopdf::Document;
+use peniko::{
+    BlendMode, BrushRef, Compose, Extend, Fill, GradientKind, Image, ImageQuality, Mix, color::Srgb,
+};
+use tiny_skia::{
+    FillRule, FilterQuality, GradientStop, LinearGradient, Mask, Paint, PathBuilder, Pixmap,
+    PixmapPaint, RadialGradient, Shader, SpreadMode, StrokeDash, Transform,
+};
+
+use crate::{
+    FontCache, RasterError, RenderSettings, canvas::Canvas, dimensions, paint_page, validate_page,
+};
+
+/// Renders a page into memory with tiny-skia rather than vello, for when
+/// there's no GPU and vello's CPU pipeline is too heavy.
+pub fn render_page_cpu(
+    doc: &Document,
+    page: u32,
+    scale: f32,
+    settings: &RenderSettings,
+) -> Result<RgbaImage, RasterError> {
+    let size = dimensions(doc, doc.get_dictionary(validate_page(doc, page)?)?)?;
+    let scale = settings.scale(scale);
+    let width = (size.0 * scale) as u32;
+    let height = (size.1 * scale) as u32;
+
+    let mut canvas = PixmapCanvas::new(width, height, settings.anti_alias)
+        .ok_or_else(|| eyre!("Can't render page {page} at {width}x{height} pixels"))?;
+    paint_page(
+        doc,
+        &mut canvas,
+        page,
+        width,
+        height,
+        settings,
+        &FontCache::new(settings),
+    )?;
+    Ok(canvas.into_image()?)
+}
+
+/// Paints into a tiny-skia pixmap. Layers that only clip narrow the mask
+/// drawing goes through, while any others get a pixmap of their own,
+/// composited through their clip when popped.
+struct PixmapCanvas {
+    page: Pixmap,
+    layers: Vec<Layer>,
+    anti_alias: bool,
+}
+
+enum Layer {
+    /// Every clip since the pixmap being drawn into, intersected
+    Clip(Mask),
+    Group {
+        pixmap: Pixmap,
+        clip: Mask,
+        blend: tiny_skia::BlendMode,
+        alpha: f32,
+    },
+}
+
+impl PixmapCanvas {
+    fn new(width: u32, height: u32, anti_alias: bool) -> Option<Self> {
+        Some(PixmapCanvas {
+            page: Pixmap::new(width, height)?,
+            layers: Vec::new(),
+            anti_alias,
+        })
+    }
+
+    // The pixmap to draw into and the mask to draw through
+    fn target(&mut self) -> (&mut Pixmap, Option<&Mask>) {
+        let mut mask = None;
+        for layer in self.layers.iter_mut().rev() {
+            match layer {
+                Layer::Clip(clip) => {
+                    mask = mask.or(Some(&*clip));
+                }
+                Layer::Group { pixmap, .. } => return (pixmap, mask),
+            }
+        }
+        (&mut self.page, mask)
+    }
+
+    fn paint(&self, brush: BrushRef<'_>) -> Option<Paint<'static>> {
+        let shader = match brush {
+            BrushRef::Solid(color) => Shader::SolidColor(color_of(color)),
+            BrushRef::Gradient(gradient) => {
+                let stops = gradient
+                    .stops
+                    .iter()
+                    .map(|stop| {
+                        GradientStop::new(
+                            stop.offset,
+                            color_of(stop.color.to_alpha_color::<Srgb>()),
+                        )
+                    })
+                    .collect();
+                let mode = match gradient.extend {
+                    Extend::Pad => SpreadMode::Pad,
+                    Extend::Repeat => SpreadMode::Repeat,
+                    Extend::Reflect => SpreadMode::Reflect,
+                };
+                match gradient.kind {
+                    GradientKind::Linear { start, end } => LinearGradient::new(
+                        point(start),
+                        point(end),
+                        stops,
+                        mode,
+                        Transform::identity(),
+                    )?,
+                    // tiny-skia's radial gradients start from a point, so a
+                    // starting circle is approximated by its center
+                    GradientKind::Radial {
+                        start_center,
+                        end_center,
+                        end_radius,
+                        ..
+                    } => RadialGradient::new(
+                        point(start_center),
+                        point(end_center),
+                        end_radius,
+                        stops,
+                        mode,
+                        Transform::identity(),
+                    )?,
+                    GradientKind::Sweep { .. } => return None,
+                }
+            }
+            BrushRef::Image(_) => return None,
+        };
+        Some(Paint {
+            shader,
+            anti_alias: self.anti_alias,
+            ..Default::default()
+        })
+    }
+
+    fn into_image(mut self) -> Result<RgbaImage> {
+        while !self.layers.is_empty() {
+            self.pop_layer();
+        }
+        let pixmap = &self.page;
+        let data = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let pixel = pixel.demultiply();
+                [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+            })
+            .collect();
+        RgbaImage::from_raw(pixmap.width(), pixmap.height(), data)
+            .ok_or_else(|| eyre!("Failed to create image buffer"))
+    }
+}
+
+impl Canvas for PixmapCanvas {
+    fn fill(&mut self, rule: Fill, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        let (Some(path), Some(paint)) = (path(shape), self.paint(brush)) else {
+            return;
+        };
+        let rule = match rule {
+            Fill::NonZero => FillRule::Winding,
+            Fill::EvenOdd => FillRule::EvenOdd,
+        };
+        let (pixmap, mask) = self.target();
+        pixmap.fill_path(&path, &paint, rule, transform_of(transform), mask);
+    }
+
+    fn stroke(&mut self, style: &Stroke, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        let (Some(path), Some(paint)) = (path(shape), self.paint(brush)) else {
+            return;
+        };
+        // tiny-skia wants an even number of dashes, which an odd pattern
+        // repeated twice gives
+        let mut dashes: Vec<f32> = style.dash_pattern.iter().map(|d| *d as f32).collect();
+        if dashes.len() % 2 == 1 {
+            dashes.extend_from_within(..);
+        }
+        let stroke = tiny_skia::Stroke {
+            width: style.width as f32,
+            miter_limit: style.miter_limit as f32,
+            line_cap: match style.start_cap {
+                Cap::Butt => tiny_skia::LineCap::Butt,
+                Cap::Round => tiny_skia::LineCap::Round,
+                Cap::Square => tiny_skia::LineCap::Square,
+            },
+            line_join: match style.join {
+                Join::Bevel => tiny_skia::LineJoin::Bevel,
+                Join::Miter => tiny_skia::LineJoin::Miter,
+                Join::Round => tiny_skia::LineJoin::Round,
+            },
+            dash: StrokeDash::new(dashes, style.dash_offset as f32),
+        };
+        let (pixmap, mask) = self.target();
+        pixmap.stroke_path(&path, &paint, &stroke, transform_of(transform), mask);
+    }
+
+    fn push_layer(&mut self, blend: BlendMode, alpha: f32, transform: Affine, clip: &BezPath) {
+        let anti_alias = self.anti_alias;
+        let (width, height) = (self.page.width(), self.page.height());
+        let (_, outer) = self.target();
+        let mut mask = match outer {
+            Some(outer) => outer.clone(),
+            None => {
+                let mut mask = Mask::new(width, height).expect("the page has a valid size");
+                mask.data_mut().fill(u8::MAX);
+                mask
+            }
+        };
+        match path(clip) {
+            Some(clip) => mask.intersect_path(
+                &clip,
+                FillRule::Winding,
+                anti_alias,
+                transform_of(transform),
+            ),
+            None => mask.clear(),
+        }
+
+        // Other layers are groups even when they blend normally, since soft
+        // masks are applied to what was drawn in them once they're popped
+        let layer = if blend.mix == Mix::Clip && alpha >= 1. {
+            Layer::Clip(mask)
+        } else {
+            Layer::Group {
+                pixmap: Pixmap::new(width, height).expect("the page has a valid size"),
+                clip: mask,
+                blend: blend_mode(blend),
+                alpha,
+            }
+        };
+        self.layers.push(layer);
+    }
+
+    fn pop_layer(&mut self) {
+        let Some(Layer::Group {
+            pixmap: group,
+            clip,
+            blend,
+            alpha,
+        }) = self.layers.pop()
+        else {
+            return;
+        };
+        let (pixmap, _) = self.target();
+        pixmap.draw_pixmap(
+            0,
+            0,
+            group.as_ref(),
+            &PixmapPaint {
+                opacity: alpha,
+                blend_mode: blend,
+                quality: FilterQuality::Nearest,
+            },
+            Transform::identity(),
+            Some(&clip),
+        );
+    }
+
+    fn draw_image(&mut self, image: &Image, transform: Affine) {
+        let data = image.data.data();
+        let Some(mut pixmap) = Pixmap::new(image.width, image.height) else {
+            return;
+        };
+        for (pixel, rgba) in pixmap.pixels_mut().iter_mut().zip(data.chunks_exact(4)) {
+            *pixel =
+                tiny_skia::ColorU8::from_rgba(rgba[0], rgba[1], rgba[2], rgba[3]).premultiply();
+        }
+        let quality = match image.quality {
+            ImageQuality::Low => FilterQuality::Nearest,
+            ImageQuality::Medium => FilterQuality::Bilinear,
+            ImageQuality::High => FilterQuality::Bicubic,
+        };
+        let (target, mask) = self.target();
+        target.draw_pixmap(
+            0,
+            0,
+            pixmap.as_ref(),
+            &PixmapPaint {
+                opacity: image.alpha,
+                quality,
+                ..Default::default()
+            },
+            transform_of(transform),
+            mask,
+        );
+    }
+}
+
+fn path(shape: &BezPath) -> Option<tiny_skia::Path> {
+    let mut builder = PathBuilder::new();
+    for element in shape.elements() {
+        match *element {
+            PathEl::MoveTo(p) => builder.move_to(p.x as f32, p.y as f32),
+            PathEl::LineTo(p) => builder.line_to(p.x as f32, p.y as f32),
+            PathEl::QuadTo(p1, p2) => {
+                builder.quad_to(p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32)
+            }
+            PathEl::CurveTo(p1, p2, p3) => builder.cubic_to(
+                p1.x as f32,
+                p1.y as f32,
+                p2.x as f32,
+                p2.y as f32,
+                p3.x as f32,
+                p3.y as f32,
+            ),
+            PathEl::ClosePath => builder.close(),
+        }
+    }
+    builder.finish()
+}
+
+fn transform_of(affine: Affine) -> Transform {
+    let [a, b, c, d, e, f] = affine.as_coeffs().map(|v| v as f32);
+    Transform::from_row(a, b, c, d, e, f)
+}
+
+fn point(p: kurbo::Point) -> tiny_skia::Point {
+    tiny_skia::Point::from_xy(p.x as f32, p.y as f32)
+}
+
+fn color_of(color: peniko::Color) -> tiny_skia::Color {
+    let [r, g, b, a] = color.to_rgba8().to_u8_array();
+    tiny_skia::Color::from_rgba8(r, g, b, a)
+}
+
+fn blend_mode(blend: BlendMode) -> tiny_skia::BlendMode {
+    use tiny_skia::BlendMode as B;
+    match blend.mix {
+        Mix::Normal | Mix::Clip => match blend.compose {
+            Compose::Clear => B::Clear,
+            Compose::Copy => B::Source,
+            Compose::Dest => B::Destination,
+            Compose::SrcOver => B::SourceOver,
+            Compose::DestOver => B::DestinationOver,
+            Compose::SrcIn => B::SourceIn,
+            Compose::DestIn => B::DestinationIn,
+            Compose::SrcOut => B::SourceOut,
+            Compose::DestOut => B::DestinationOut,
+            Compose::SrcAtop => B::SourceAtop,
+            Compose::DestAtop => B::DestinationAtop,
+            Compose::Xor => B::Xor,
+            Compose::Plus | Compose::PlusLighter => B::Plus,
+        },
+        Mix::Multiply => B::Multiply,
+        Mix::Screen => B::Screen,
+        Mix::Overlay => B::Overlay,
+        Mix::Darken => B::Darken,
+        Mix::Lighten => B::Lighten,
+        Mix::ColorDodge => B::ColorDodge,
+        Mix::ColorBurn => B::ColorBurn,
+        Mix::HardLight => B::HardLight,
+        Mix::SoftLight => B::SoftLight,
+        Mix::Difference => B::Difference,
+        Mix::Exclusion => B::Exclusion,
+        Mix::Hue => B::Hue,
+        Mix::Saturation => B::Saturation,
+        Mix::Color => B::Color,
+        Mix::Luminosity => B::Luminosity,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    #[test]
+    fn soft_masks_leave_the_page_outside_them_alone() {
+        let image = render(&soft_mask_page("0 0 1 rg 0 0 100 100 re f"));
+        assert_eq!(pixel(&image, 25, 50), [0, 0, 255, 255]);
+        // The background the mask hides the fill from
+        assert_eq!(pixel(&image, 75, 50), WHITE);
+    }
+}
@@ -0,0 +1,50 @@
+use thiserror::Error;
+
+/// Failures callers may want to tell apart, e.g. to skip a page rather than
+/// give up on the whole document. The public loading, rendering, SVG and
+/// extraction functions return these directly; anything else that goes
+/// wrong along the way is carried by [`RasterError::Other`].
+#[derive(Debug, Error)]
+pub enum RasterError {
+    #[error("Page {page} not found in a document with {count} page(s)")]
+    PageNotFound { page: u32, count: usize },
+    #[error("The document is encrypted and needs a password")]
+    PasswordRequired,
+    #[error("That's the document's owner password; decrypting needs the user password")]
+    OwnerPassword,
+    #[error("Incorrect password for the encrypted document")]
+    IncorrectPassword,
+    #[error("Unsupported font: {0}")]
+    UnsupportedFont(String),
+    #[error("Unsupported color space {0}")]
+    UnsupportedColorSpace(String),
+    #[error("Malformed content stream: {0}")]
+    MalformedContent(String),
+    #[error("{0:#}")]
+    Other(eyre::Report),
+}
+
+impl RasterError {
+    /// Adds context to a [`RasterError::Other`], leaving the variants
+    /// callers match on as they are.
+    pub(crate) fn wrap_err(self, context: String) -> Self {
+        match self {
+            RasterError::Other(report) => RasterError::Other(report.wrap_err(context)),
+            error => error,
+        }
+    }
+}
+
+// Internal code reports with eyre, bailing with one of the variants above
+// where it has one, so take that back out rather than nesting it in Other
+impl From<eyre::Report> for RasterError {
+    fn from(report: eyre::Report) -> Self {
+        report.downcast().unwrap_or_else(RasterError::Other)
+    }
+}
+
+impl From<lopdf::Error> for RasterError {
+    fn from(error: lopdf::Error) -> Self {
+        RasterError::Other(error.into())
+    }
+}
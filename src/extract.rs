@@ -0,0 +1,278 @@
+use eyre::Result;
+use kurbo::Affine;
+use lopdf::{Dictionary, Document, Object, content::Operation};
+
+use crate::{
+    CTM, Coord, DeviceScale, FontCache, MAX_FORM_DEPTH, RasterError, RenderSettings, Resources,
+    TextState, concat,
+    content::decode_content,
+    dimensions, get, page_content, page_ctm, page_resources,
+    text::{TextOperation, advance, text_operation},
+    validate_page,
+};
+
+/// A run of text shown by one string, placed on the page.
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: String,
+    /// Maps the run's text space, scaled to the font size, to device space at
+    /// one pixel per point. The origin is where the run starts on the baseline.
+    pub transform: Affine,
+    /// How far each glyph moves along the baseline, in the units of
+    /// `transform`, including character and word spacing.
+    pub advances: Vec<f32>,
+}
+
+/// Extracts the text shown on a page, in content stream order, separating
+/// runs with spaces and lines with newlines based on where they're placed.
+pub fn extract_text(doc: &Document, page: u32) -> Result<String, RasterError> {
+    Ok(extract(doc, page)?.text.trim_end().to_string())
+}
+
+/// Extracts the text shown on a page as positioned runs, for laying a
+/// selectable text layer over the rendered page. Vertical writing isn't
+/// handled yet, so every run advances horizontally.
+pub fn text_runs(doc: &Document, page: u32) -> Result<Vec<TextRun>, RasterError> {
+    Ok(extract(doc, page)?.runs)
+}
+
+fn extract(doc: &Document, page: u32) -> Result<Extractor<'_>, RasterError> {
+    let page_id = validate_page(doc, page)?;
+    let page_dict = doc.get_dictionary(page_id)?;
+    let (width, height) = dimensions(doc, page_dict)?;
+    let device = DeviceScale {
+        width: width as u32,
+        height: height as u32,
+        scale: 1.,
+    }
+    .affine(&page_ctm(doc, page_dict)?);
+
+    let default_dict = Dictionary::default();
+    let resource_dict = page_resources(doc, page_dict).unwrap_or(&default_dict);
+
+    let raw = page_content(doc, page_id)?;
+    let content =
+        decode_content(&raw).map_err(|e| RasterError::MalformedContent(format!("{e:#}")))?;
+
+    let mut extractor = Extractor {
+        doc,
+        fonts: FontCache::new(&RenderSettings::default()),
+        device,
+        text: String::new(),
+        runs: Vec::new(),
+        last: None,
+    };
+    let resources = Resources::new(doc, resource_dict, &extractor.fonts);
+    extractor.content(
+        &resources,
+        &content.operations,
+        CTM::default(),
+        &mut TextState::default(),
+        0,
+    )?;
+    Ok(extractor)
+}
+
+struct Extractor<'a> {
+    doc: &'a Document,
+    fonts: FontCache,
+    // Maps user space, before the page's own transform, to device space
+    device: Affine,
+    text: String,
+    runs: Vec<TextRun>,
+    // Where the previous run of text ended in user space, and its font size there
+    last: Option<(Coord, f32)>,
+}
+
+impl Extractor<'_> {
+    fn content(
+        &mut self,
+        resources: &Resources,
+        operations: &[Operation],
+        mut ctm: CTM,
+        ts: &mut TextState,
+        depth: usize,
+    ) -> Result<()> {
+        let mut stack = Vec::new();
+        for operation in operations {
+            match text_operation(ts, resources, operation)? {
+                TextOperation::Show(glyphs) => {
+                    self.show(&ctm, ts, glyphs);
+                    continue;
+                }
+                TextOperation::State => continue,
+                TextOperation::Other => {}
+            }
+            match (operation.operator.as_str(), &operation.operands[..]) {
+                ("q", []) => stack.push(ctm.clone()),
+                ("Q", []) => ctm = stack.pop().unwrap_or(ctm),
+                ("cm", [a, b, c, d, e, f]) => {
+                    let m = CTM {
+                        a: a.as_float()?,
+                        b: b.as_float()?,
+                        c: c.as_float()?,
+                        d: d.as_float()?,
+                        e: e.as_float()?,
+                        f: f.as_float()?,
+                    };
+                    ctm = concat(&ctm, &m);
+                }
+                ("Do", [Object::Name(name)]) if depth < MAX_FORM_DEPTH => {
+                    let Ok(xobject) = self
+                        .doc
+                        .get_dict_in_dict(resources.dict, b"XObject")
+                        .and_then(|x| x.get(name))
+                    else {
+                        continue;
+                    };
+                    let dict = &self.doc.dereference(xobject)?.1.as_stream()?.dict;
+                    if dict.get(b"Subtype")?.as_name()? != b"Form" {
+                        continue;
+                    }
+                    let raw: Vec<u8> = get(self.doc, xobject)?;
+                    let content = decode_content(&raw)?;
+                    let form_resources;
+                    let form_resources = match self.doc.get_dict_in_dict(dict, b"Resources") {
+                        Ok(form_dict) => {
+                            form_resources = Resources::new(self.doc, form_dict, &self.fonts);
+                            &form_resources
+                        }
+                        Err(_) => resources,
+                    };
+                    let matrix = match dict.get(b"Matrix") {
+                        Ok(matrix) => get(self.doc, matrix)?,
+                        Err(_) => CTM::default(),
+                    };
+                    self.content(
+                        form_resources,
+                        &content.operations,
+                        concat(&ctm, &matrix),
+                        &mut ts.clone(),
+                        depth + 1,
+                    )?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, ctm: &CTM, ts: &mut TextState, glyphs: &[Object]) {
+        let Some(font) = ts.font.clone() else {
+            return;
+        };
+        let matrix = concat(ctm, &ts.matrix);
+        let size = ts.size * matrix.b.hypot(matrix.d);
+
+        for glyph in glyphs {
+            match glyph {
+                Object::String(bytes, _) => {
+                    self.separate(pen(&matrix, ts), size);
+                    let h_scale = ts.h_scale / 100.;
+                    let mut run = TextRun {
+                        text: String::new(),
+                        transform: self.device
+                            * Affine::from(&matrix)
+                            * Affine::new([
+                                (ts.size * h_scale) as f64,
+                                0.,
+                                0.,
+                                ts.size as f64,
+                                (ts.position / 1000. * ts.size * h_scale) as f64,
+                                ts.rise as f64,
+                            ]),
+                        advances: Vec::new(),
+                    };
+                    for (code, code_len) in font.codes(bytes) {
+                        if let Some(text) = font.unicode(code) {
+                            run.text.push_str(&text);
+                        }
+                        let advance = advance(ts, font.width(code), code, code_len);
+                        ts.position += advance;
+                        run.advances.push(advance / 1000.);
+                    }
+                    self.text.push_str(&run.text);
+                    if !run.advances.is_empty() {
+                        self.runs.push(run);
+                    }
+                    self.last = Some((pen(&matrix, ts), size));
+                }
+                o => o.as_float().ok().iter().for_each(|s| ts.position -= s),
+            }
+        }
+    }
+
+    // Starts a new line when text moves off the previous baseline, or a new
+    // word when it jumps forward along it
+    fn separate(&mut self, at: Coord, size: f32) {
+        let Some((last, last_size)) = self.last else {
+            return;
+        };
+        let size = size.max(last_size).max(f32::EPSILON);
+        let separator = if (at.y - last.y).abs() > size * 0.5 {
+            '\n'
+        } else if (at.x - last.x).abs() > size * 0.15 {
+            ' '
+        } else {
+            return;
+        };
+        match self.text.chars().last() {
+            None | Some('\n') => {}
+            Some(c) if c.is_whitespace() && separator == ' ' => {}
+            _ => self.text.push(separator),
+        }
+    }
+}
+
+// The current text position in user space
+fn pen(matrix: &CTM, ts: &TextState) -> Coord {
+    let x = ts.position / 1000. * ts.size * ts.h_scale / 100.;
+    Coord {
+        x: matrix.a * x + matrix.e,
+        y: matrix.b * x + matrix.f,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Stream, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn to_unicode_overrides_the_encoding() {
+        let mut doc = document();
+        let font = truetype_font(&mut doc);
+        let cmap = doc.add_object(Stream::new(
+            dictionary! {},
+            b"1 beginbfchar <41> <0058> endbfchar".to_vec(),
+        ));
+        let font_dict = doc.get_dictionary_mut(font).unwrap();
+        font_dict.set("ToUnicode", cmap);
+        font_dict.set(
+            "Encoding",
+            dictionary! { "Differences" => vec![65.into(), "A".into(), "eacute".into()] },
+        );
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } } },
+            "BT /F1 10 Tf 10 10 Td (AB) Tj ET",
+        );
+        // B has no entry, so it's the text its glyph name stands for
+        assert_eq!(extract_text(&doc, 1).unwrap(), "Xé");
+    }
+
+    #[test]
+    fn rise_lifts_runs_off_the_baseline() {
+        let doc = text_page("BT /F1 10 Tf 10 20 Td (A) Tj 5 Ts (A) Tj ET");
+        let runs = text_runs(&doc, 1).unwrap();
+        assert_eq!(runs.len(), 2);
+        let origin = |run: &TextRun| run.transform * kurbo::Point::ZERO;
+        // Device y points down, from the top of the 100pt page
+        assert_eq!(origin(&runs[0]), kurbo::Point::new(10., 80.));
+        assert_eq!(origin(&runs[1]), kurbo::Point::new(20., 75.));
+        // A superscript stays on its line
+        assert_eq!(extract_text(&doc, 1).unwrap(), "AA");
+    }
+}
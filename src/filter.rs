@@ -0,0 +1,303 @@
+use std::io::Read;
+
+use eyre::{Result, bail, eyre};
+use lopdf::{Dictionary, Document, Object, Stream};
+
+/// Filters that compress image data and are left for the image decoder.
+pub const IMAGE_FILTERS: &[&[u8]] = &[
+    b"DCTDecode",
+    b"JPXDecode",
+    b"CCITTFaxDecode",
+    b"JBIG2Decode",
+];
+
+/// Applies a stream's `/Filter` chain, stopping at an image codec such as
+/// `DCTDecode` so the caller can decode the image itself.
+pub fn decode_stream(doc: &Document, stream: &Stream) -> Result<Vec<u8>> {
+    decode(doc, &stream.dict, &stream.content)
+}
+
+/// Applies the filters named by `dict`'s /Filter to `data`, with the
+/// parameters in its /DecodeParms. Inline images' abbreviated keys and
+/// filter names are expanded as they're parsed, so only full names are
+/// understood here.
+pub fn decode(doc: &Document, dict: &Dictionary, data: &[u8]) -> Result<Vec<u8>> {
+    let filters = match dict.get(b"Filter").map(|f| doc.dereference(f)) {
+        Ok(Ok((_, Object::Array(filters)))) => filters.clone(),
+        Ok(Ok((_, filter))) => vec![filter.clone()],
+        _ => Vec::new(),
+    };
+    let parms = match dict.get(b"DecodeParms").map(|p| doc.dereference(p)) {
+        Ok(Ok((_, Object::Array(parms)))) => parms.clone(),
+        Ok(Ok((_, parms))) => vec![parms.clone()],
+        _ => Vec::new(),
+    };
+
+    let mut data = data.to_vec();
+    for (i, filter) in filters.iter().enumerate() {
+        let name = filter.as_name()?;
+        if IMAGE_FILTERS.contains(&name) {
+            break;
+        }
+        let parms = parms
+            .get(i)
+            .and_then(|p| doc.dereference(p).ok())
+            .and_then(|(_, p)| p.as_dict().ok());
+        data = match name {
+            b"FlateDecode" => predict(flate(&data), parms)?,
+            b"LZWDecode" => {
+                let early_change = parms
+                    .and_then(|p| p.get(b"EarlyChange").ok())
+                    .and_then(|e| e.as_i64().ok())
+                    .unwrap_or(1);
+                predict(lzw(&data, early_change != 0)?, parms)?
+            }
+            b"ASCII85Decode" => ascii85(&data)?,
+            b"ASCIIHexDecode" => ascii_hex(&data),
+            b"RunLengthDecode" => run_length(&data),
+            other => bail!("Unsupported filter {}", String::from_utf8_lossy(other)),
+        };
+    }
+    Ok(data)
+}
+
+// Keeps whatever decompresses before an error, since truncated streams are common
+fn flate(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let _ = flate2::read::ZlibDecoder::new(data).read_to_end(&mut decoded);
+    if decoded.is_empty() {
+        let _ = flate2::read::DeflateDecoder::new(data).read_to_end(&mut decoded);
+    }
+    decoded
+}
+
+fn lzw(data: &[u8], early_change: bool) -> Result<Vec<u8>> {
+    let mut decoder = if early_change {
+        weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+    } else {
+        weezl::decode::Decoder::new(weezl::BitOrder::Msb, 8)
+    };
+    let mut decoded = Vec::new();
+    let result = decoder.into_vec(&mut decoded).decode(data);
+    match result.status {
+        Ok(_) => Ok(decoded),
+        Err(_) if !decoded.is_empty() => Ok(decoded),
+        Err(e) => Err(eyre!("LZW decoding failed: {e}")),
+    }
+}
+
+// Undoes the TIFF or PNG predictor given in /DecodeParms
+fn predict(data: Vec<u8>, parms: Option<&Dictionary>) -> Result<Vec<u8>> {
+    let Some(parms) = parms else {
+        return Ok(data);
+    };
+    let int = |key: &[u8], default: i64| parms.get(key).and_then(|v| v.as_i64()).unwrap_or(default);
+    let predictor = int(b"Predictor", 1);
+    if predictor < 2 {
+        return Ok(data);
+    }
+    let colors = int(b"Colors", 1).max(1) as usize;
+    let bpc = int(b"BitsPerComponent", 8).max(1) as usize;
+    let columns = int(b"Columns", 1).max(1) as usize;
+    let bpp = (colors * bpc).div_ceil(8);
+    let row = (colors * bpc * columns).div_ceil(8);
+
+    if predictor == 2 {
+        if bpc != 8 {
+            bail!("Unsupported TIFF predictor with {bpc} bits per component");
+        }
+        let mut data = data;
+        for line in data.chunks_mut(row) {
+            for i in bpp..line.len() {
+                line[i] = line[i].wrapping_add(line[i - bpp]);
+            }
+        }
+        return Ok(data);
+    }
+
+    // PNG predictors prefix every row with the filter it uses
+    let mut decoded = Vec::with_capacity(data.len() / (row + 1) * row);
+    let mut previous = vec![0u8; row];
+    for line in data.chunks(row + 1) {
+        let (&filter, line) = line.split_first().unwrap_or((&0, &[]));
+        let mut current = line.to_vec();
+        current.resize(row, 0);
+        for i in 0..row {
+            let left = if i >= bpp { current[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            current[i] = current[i].wrapping_add(match filter {
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => 0,
+            });
+        }
+        decoded.extend_from_slice(&current);
+        previous = current;
+    }
+    Ok(decoded)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let p = left as i16 + up as i16 - up_left as i16;
+    let (pa, pb, pc) = (
+        (p - left as i16).abs(),
+        (p - up as i16).abs(),
+        (p - up_left as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn ascii85(data: &[u8]) -> Result<Vec<u8>> {
+    let data = data.strip_prefix(b"<~").unwrap_or(data);
+    let mut decoded = Vec::with_capacity(data.len() * 4 / 5);
+    let mut group = Vec::with_capacity(5);
+    for &c in data {
+        match c {
+            b'~' => break,
+            b'z' if group.is_empty() => decoded.extend([0; 4]),
+            b'!'..=b'u' => {
+                group.push(c - b'!');
+                if group.len() == 5 {
+                    let value = group.iter().fold(0u64, |v, d| v * 85 + *d as u64);
+                    decoded.extend((value as u32).to_be_bytes());
+                    group.clear();
+                }
+            }
+            c if c.is_ascii_whitespace() => {}
+            c => bail!("Invalid ASCII85 character {:?}", c as char),
+        }
+    }
+    // A final partial group is padded with 'u' and truncated
+    if group.len() > 1 {
+        let n = group.len();
+        group.resize(5, 84);
+        let value = group.iter().fold(0u64, |v, d| v * 85 + *d as u64);
+        decoded.extend(&(value as u32).to_be_bytes()[..n - 1]);
+    }
+    Ok(decoded)
+}
+
+fn ascii_hex(data: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = data
+        .iter()
+        .take_while(|b| **b != b'>')
+        .filter_map(|b| (*b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|d| d[0] << 4 | d.get(1).unwrap_or(&0))
+        .collect()
+}
+
+fn run_length(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while let Some(&length) = data.get(i) {
+        match length {
+            128 => break,
+            0..=127 => {
+                let end = (i + 2 + length as usize).min(data.len());
+                decoded.extend_from_slice(&data[i + 1..end]);
+                i = end;
+            }
+            _ => {
+                if let Some(&byte) = data.get(i + 1) {
+                    decoded.extend(std::iter::repeat_n(byte, 257 - length as usize));
+                }
+                i += 2;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use lopdf::dictionary;
+
+    use super::*;
+
+    fn decoded(dict: Dictionary, data: &[u8]) -> Vec<u8> {
+        decode_stream(&Document::new(), &Stream::new(dict, data.to_vec())).unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn ascii_filters_decode() {
+        let hex = decoded(
+            dictionary! { "Filter" => "ASCIIHexDecode" },
+            b"48 65 6C6c 6>",
+        );
+        assert_eq!(hex, b"Hell`");
+        let a85 = decoded(dictionary! { "Filter" => "ASCII85Decode" }, b"<~87cURDZ~>");
+        assert_eq!(a85, b"Hello");
+        let zeros = decoded(dictionary! { "Filter" => "ASCII85Decode" }, b"z~>");
+        assert_eq!(zeros, [0; 4]);
+    }
+
+    #[test]
+    fn run_length_decodes_runs_and_literals() {
+        let data = decoded(
+            dictionary! { "Filter" => "RunLengthDecode" },
+            &[2, b'a', b'b', b'c', 254, b'x', 128, b'z'],
+        );
+        assert_eq!(data, b"abcxxx");
+    }
+
+    #[test]
+    fn flate_and_lzw_decode() {
+        let flate = decoded(
+            dictionary! { "Filter" => "FlateDecode" },
+            &deflate(b"flate"),
+        );
+        assert_eq!(flate, b"flate");
+        let mut encoder = weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8);
+        let lzw = encoder.encode(b"lzw lzw lzw").unwrap();
+        let lzw = decoded(dictionary! { "Filter" => "LZWDecode" }, &lzw);
+        assert_eq!(lzw, b"lzw lzw lzw");
+    }
+
+    #[test]
+    fn filters_chain_with_their_parameters() {
+        // Two rows of three bytes, the second predicted from the one above
+        let rows = [0, 1, 2, 3, 2, 1, 1, 1];
+        let hex: String = deflate(&rows).iter().map(|b| format!("{b:02x}")).collect();
+        let data = decoded(
+            dictionary! {
+                "Filter" => vec!["ASCIIHexDecode".into(), "FlateDecode".into()],
+                "DecodeParms" => vec![
+                    Object::Null,
+                    dictionary! { "Predictor" => 12, "Columns" => 3 }.into(),
+                ],
+            },
+            hex.as_bytes(),
+        );
+        assert_eq!(data, [1, 2, 3, 2, 3, 4]);
+    }
+
+    #[test]
+    fn image_codecs_are_left_encoded() {
+        let data = decoded(
+            dictionary! { "Filter" => vec!["ASCIIHexDecode".into(), "DCTDecode".into()] },
+            b"FFD8>",
+        );
+        assert_eq!(data, [0xFF, 0xD8]);
+    }
+}
@@ -0,0 +1,635 @@
+use eyre::{Result, bail};
+use lopdf::{Document, Object};
+
+use crate::{FromPDF, get};
+
+#[derive(Debug, Clone)]
+pub enum PdfFunction {
+    Sampled {
+        domain: Vec<f32>,
+        range: Vec<f32>,
+        size: Vec<usize>,
+        encode: Vec<f32>,
+        decode: Vec<f32>,
+        /// Samples scaled to 0..1, with the first input varying fastest.
+        samples: Vec<f32>,
+    },
+    Exponential {
+        domain: Vec<f32>,
+        range: Option<Vec<f32>>,
+        c0: Vec<f32>,
+        c1: Vec<f32>,
+        n: f32,
+    },
+    Stitching {
+        domain: Vec<f32>,
+        range: Option<Vec<f32>>,
+        functions: Vec<PdfFunction>,
+        bounds: Vec<f32>,
+        encode: Vec<f32>,
+    },
+    PostScript {
+        domain: Vec<f32>,
+        range: Vec<f32>,
+        program: Vec<Instruction>,
+    },
+}
+
+impl PdfFunction {
+    pub fn eval(&self, input: &[f32]) -> Vec<f32> {
+        match self {
+            PdfFunction::Sampled {
+                domain,
+                range,
+                size,
+                encode,
+                decode,
+                samples,
+            } => {
+                let outputs = range.len() / 2;
+                // Where each input falls in the sample grid, and how far it is
+                // towards the next sample
+                let position: Vec<(usize, f32)> = size
+                    .iter()
+                    .enumerate()
+                    .map(|(i, size)| {
+                        let x = clip(input.get(i).copied().unwrap_or(0.), domain, i);
+                        let e = interpolate(
+                            x,
+                            domain[2 * i],
+                            domain[2 * i + 1],
+                            encode[2 * i],
+                            encode[2 * i + 1],
+                        );
+                        let e = e.max(0.).min((size - 1) as f32);
+                        let index = (e.floor() as usize).min(size.saturating_sub(2));
+                        (index, e - index as f32)
+                    })
+                    .collect();
+
+                // Interpolate between the samples at every corner of the cell
+                let mut output = vec![0.; outputs];
+                for corner in 0..1usize << size.len() {
+                    let mut weight = 1.;
+                    let mut offset = 0;
+                    let mut stride = 1;
+                    for (i, (index, fraction)) in position.iter().enumerate() {
+                        let upper = (corner >> i) & 1 == 1 && size[i] > 1;
+                        weight *= if upper { *fraction } else { 1. - fraction };
+                        offset += (index + upper as usize) * stride;
+                        stride *= size[i];
+                    }
+                    if weight == 0. {
+                        continue;
+                    }
+                    for (j, out) in output.iter_mut().enumerate() {
+                        *out += weight * samples.get(offset * outputs + j).unwrap_or(&0.);
+                    }
+                }
+
+                output
+                    .iter()
+                    .enumerate()
+                    .map(|(j, s)| {
+                        let y = interpolate(*s, 0., 1., decode[2 * j], decode[2 * j + 1]);
+                        clip(y, range, j)
+                    })
+                    .collect()
+            }
+            PdfFunction::Exponential {
+                domain,
+                range,
+                c0,
+                c1,
+                n,
+            } => {
+                let x = clip(input.first().copied().unwrap_or(0.), domain, 0);
+                let output = c0
+                    .iter()
+                    .zip(c1)
+                    .map(|(c0, c1)| c0 + x.powf(*n) * (c1 - c0));
+                clip_all(output, range.as_deref())
+            }
+            PdfFunction::Stitching {
+                domain,
+                range,
+                functions,
+                bounds,
+                encode,
+            } => {
+                let x = clip(input.first().copied().unwrap_or(0.), domain, 0);
+                let k = bounds.iter().take_while(|b| x >= **b).count();
+                let low = if k == 0 { domain[0] } else { bounds[k - 1] };
+                let high = bounds.get(k).copied().unwrap_or(domain[1]);
+                let x = interpolate(x, low, high, encode[2 * k], encode[2 * k + 1]);
+                clip_all(functions[k].eval(&[x]).into_iter(), range.as_deref())
+            }
+            PdfFunction::PostScript {
+                domain,
+                range,
+                program,
+            } => {
+                let mut stack: Vec<Value> = (0..domain.len() / 2)
+                    .map(|i| {
+                        Value::Number(clip(input.get(i).copied().unwrap_or(0.), domain, i) as f64)
+                    })
+                    .collect();
+                // A program that fails part way leaves whatever it had computed
+                let _ = run(program, &mut stack);
+                let outputs = range.len() / 2;
+                let start = stack.len().saturating_sub(outputs);
+                let output = stack[start..]
+                    .iter()
+                    .map(|v| v.number().unwrap_or(0.) as f32);
+                let mut output: Vec<f32> = clip_all(output, Some(range));
+                output.resize(outputs, 0.);
+                output
+            }
+        }
+    }
+}
+
+impl FromPDF for PdfFunction {
+    fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
+        let (_, root) = doc.dereference(root)?;
+        let dict = match root {
+            Object::Stream(stream) => &stream.dict,
+            other => other.as_dict()?,
+        };
+        let domain: Vec<f32> = get(doc, dict.get(b"Domain")?)?;
+        if domain.len() < 2 {
+            bail!(
+                "Function /Domain needs at least two values, got {:?}",
+                domain
+            );
+        }
+        let range: Option<Vec<f32>> = match dict.get(b"Range") {
+            Ok(range) => Some(get(doc, range)?),
+            Err(_) => None,
+        };
+
+        match dict.get(b"FunctionType")?.as_i64()? {
+            0 => {
+                let Some(range) = range else {
+                    bail!("Sampled function without /Range");
+                };
+                let size: Vec<i64> = get(doc, dict.get(b"Size")?)?;
+                let size: Vec<usize> = size.iter().map(|s| (*s).max(1) as usize).collect();
+                if size.len() * 2 != domain.len() {
+                    bail!("Sampled function /Size doesn't match its /Domain");
+                }
+                let encode: Vec<f32> = match dict.get(b"Encode") {
+                    Ok(encode) => get(doc, encode)?,
+                    Err(_) => size.iter().flat_map(|s| [0., (s - 1) as f32]).collect(),
+                };
+                let decode: Vec<f32> = match dict.get(b"Decode") {
+                    Ok(decode) => get(doc, decode)?,
+                    Err(_) => range.clone(),
+                };
+                if encode.len() < domain.len() || decode.len() < range.len() {
+                    bail!("Sampled function /Encode or /Decode is too short");
+                }
+                let bits = dict.get(b"BitsPerSample")?.as_i64()?;
+                if !matches!(bits, 1 | 2 | 4 | 8 | 12 | 16 | 24 | 32) {
+                    bail!("Unsupported BitsPerSample {bits}");
+                }
+                let data: Vec<u8> = get(doc, root)?;
+                Ok(PdfFunction::Sampled {
+                    samples: unpack_samples(&data, bits as u32),
+                    domain,
+                    range,
+                    size,
+                    encode,
+                    decode,
+                })
+            }
+            2 => {
+                let c0 = match dict.get(b"C0") {
+                    Ok(c0) => get(doc, c0)?,
+                    Err(_) => vec![0.],
+                };
+                let c1 = match dict.get(b"C1") {
+                    Ok(c1) => get(doc, c1)?,
+                    Err(_) => vec![1.],
+                };
+                Ok(PdfFunction::Exponential {
+                    domain,
+                    range,
+                    c0,
+                    c1,
+                    n: get(doc, dict.get(b"N")?)?,
+                })
+            }
+            3 => {
+                let functions: Vec<PdfFunction> = get(doc, dict.get(b"Functions")?)?;
+                let bounds: Vec<f32> = get(doc, dict.get(b"Bounds")?)?;
+                let encode: Vec<f32> = get(doc, dict.get(b"Encode")?)?;
+                if functions.is_empty()
+                    || bounds.len() + 1 != functions.len()
+                    || encode.len() < 2 * functions.len()
+                {
+                    bail!(
+                        "Stitching function has {} functions, {} bounds and {} encode values",
+                        functions.len(),
+                        bounds.len(),
+                        encode.len()
+                    );
+                }
+                Ok(PdfFunction::Stitching {
+                    domain,
+                    range,
+                    functions,
+                    bounds,
+                    encode,
+                })
+            }
+            4 => {
+                let Some(range) = range else {
+                    bail!("PostScript function without /Range");
+                };
+                let code: Vec<u8> = get(doc, root)?;
+                Ok(PdfFunction::PostScript {
+                    domain,
+                    range,
+                    program: parse_program(&code)?,
+                })
+            }
+            other => bail!("Unsupported function type {other}"),
+        }
+    }
+}
+
+// Clips an input to the i'th interval of a domain or range
+fn clip(x: f32, interval: &[f32], i: usize) -> f32 {
+    match interval.get(2 * i..2 * i + 2) {
+        Some(&[low, high]) => x.max(low).min(high),
+        _ => x,
+    }
+}
+
+fn clip_all(output: impl Iterator<Item = f32>, range: Option<&[f32]>) -> Vec<f32> {
+    output
+        .enumerate()
+        .map(|(i, y)| range.map_or(y, |range| clip(y, range, i)))
+        .collect()
+}
+
+fn interpolate(x: f32, x_min: f32, x_max: f32, y_min: f32, y_max: f32) -> f32 {
+    if x_max == x_min {
+        y_min
+    } else {
+        y_min + (x - x_min) * (y_max - y_min) / (x_max - x_min)
+    }
+}
+
+// Reads big-endian samples of any width, scaled to 0..1
+fn unpack_samples(data: &[u8], bits: u32) -> Vec<f32> {
+    let max = ((1u64 << bits) - 1) as f32;
+    let count = data.len() * 8 / bits as usize;
+    (0..count)
+        .map(|i| {
+            let start = i * bits as usize;
+            let mut value = 0u64;
+            for bit in start..start + bits as usize {
+                let byte = data[bit / 8];
+                value = value << 1 | (byte >> (7 - bit % 8) & 1) as u64;
+            }
+            value as f32 / max
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum Instruction {
+    Number(f64),
+    Bool(bool),
+    Operator(String),
+    If(Vec<Instruction>),
+    IfElse(Vec<Instruction>, Vec<Instruction>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn number(self) -> Result<f64> {
+        match self {
+            Value::Number(n) => Ok(n),
+            Value::Bool(_) => bail!("Expected a number"),
+        }
+    }
+}
+
+fn parse_program(code: &[u8]) -> Result<Vec<Instruction>> {
+    let text = String::from_utf8_lossy(code);
+    let spaced = text.replace('{', " { ").replace('}', " } ");
+    let mut tokens = spaced.split_whitespace();
+    match tokens.next() {
+        Some("{") => parse_block(&mut tokens),
+        _ => bail!("PostScript function must start with {{"),
+    }
+}
+
+// Parses instructions up to the closing brace of a block
+fn parse_block<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Vec<Instruction>> {
+    let mut block = Vec::new();
+    let mut procs: Vec<Vec<Instruction>> = Vec::new();
+    while let Some(token) = tokens.next() {
+        let instruction = match token {
+            "}" => return Ok(block),
+            "{" => {
+                procs.push(parse_block(tokens)?);
+                continue;
+            }
+            "if" => match procs.pop() {
+                Some(then) => Instruction::If(then),
+                None => bail!("if without a procedure"),
+            },
+            "ifelse" => match (procs.pop(), procs.pop()) {
+                (Some(otherwise), Some(then)) => Instruction::IfElse(then, otherwise),
+                _ => bail!("ifelse without two procedures"),
+            },
+            "true" => Instruction::Bool(true),
+            "false" => Instruction::Bool(false),
+            token => match token.parse() {
+                Ok(n) => Instruction::Number(n),
+                Err(_) => Instruction::Operator(token.to_string()),
+            },
+        };
+        block.push(instruction);
+    }
+    bail!("Unterminated PostScript procedure")
+}
+
+fn run(program: &[Instruction], stack: &mut Vec<Value>) -> Result<()> {
+    for instruction in program {
+        match instruction {
+            Instruction::Number(n) => stack.push(Value::Number(*n)),
+            Instruction::Bool(b) => stack.push(Value::Bool(*b)),
+            Instruction::If(then) => {
+                if pop_bool(stack)? {
+                    run(then, stack)?;
+                }
+            }
+            Instruction::IfElse(then, otherwise) => {
+                if pop_bool(stack)? {
+                    run(then, stack)?;
+                } else {
+                    run(otherwise, stack)?;
+                }
+            }
+            Instruction::Operator(op) => operator(op, stack)?,
+        }
+    }
+    Ok(())
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value> {
+    match stack.pop() {
+        Some(value) => Ok(value),
+        None => bail!("PostScript stack underflow"),
+    }
+}
+
+fn pop_number(stack: &mut Vec<Value>) -> Result<f64> {
+    pop(stack)?.number()
+}
+
+fn pop_bool(stack: &mut Vec<Value>) -> Result<bool> {
+    match pop(stack)? {
+        Value::Bool(b) => Ok(b),
+        Value::Number(_) => bail!("Expected a boolean"),
+    }
+}
+
+fn operator(op: &str, stack: &mut Vec<Value>) -> Result<()> {
+    use Value::{Bool, Number};
+
+    let unary = |stack: &mut Vec<Value>, f: fn(f64) -> f64| -> Result<()> {
+        let a = pop_number(stack)?;
+        stack.push(Number(f(a)));
+        Ok(())
+    };
+    let binary = |stack: &mut Vec<Value>, f: fn(f64, f64) -> f64| -> Result<()> {
+        let b = pop_number(stack)?;
+        let a = pop_number(stack)?;
+        stack.push(Number(f(a, b)));
+        Ok(())
+    };
+    let compare = |stack: &mut Vec<Value>, f: fn(f64, f64) -> bool| -> Result<()> {
+        let b = pop_number(stack)?;
+        let a = pop_number(stack)?;
+        stack.push(Bool(f(a, b)));
+        Ok(())
+    };
+
+    match op {
+        "abs" => unary(stack, f64::abs)?,
+        "neg" => unary(stack, |a| -a)?,
+        "ceiling" => unary(stack, f64::ceil)?,
+        "floor" => unary(stack, f64::floor)?,
+        "round" => unary(stack, |a| (a + 0.5).floor())?,
+        "truncate" | "cvi" => unary(stack, f64::trunc)?,
+        "cvr" => {}
+        "sqrt" => unary(stack, f64::sqrt)?,
+        "sin" => unary(stack, |a| a.to_radians().sin())?,
+        "cos" => unary(stack, |a| a.to_radians().cos())?,
+        "ln" => unary(stack, f64::ln)?,
+        "log" => unary(stack, f64::log10)?,
+        "add" => binary(stack, |a, b| a + b)?,
+        "sub" => binary(stack, |a, b| a - b)?,
+        "mul" => binary(stack, |a, b| a * b)?,
+        "div" => binary(stack, |a, b| if b == 0. { 0. } else { a / b })?,
+        "idiv" => binary(stack, |a, b| {
+            if b.trunc() == 0. {
+                0.
+            } else {
+                (a.trunc() / b.trunc()).trunc()
+            }
+        })?,
+        "mod" => binary(stack, |a, b| {
+            if b.trunc() == 0. {
+                0.
+            } else {
+                a.trunc() % b.trunc()
+            }
+        })?,
+        "exp" => binary(stack, f64::powf)?,
+        "atan" => binary(stack, |num, den| {
+            num.atan2(den).to_degrees().rem_euclid(360.)
+        })?,
+        "bitshift" => binary(stack, |a, shift| {
+            let (a, shift) = (a as i64, shift as i64);
+            (if shift >= 0 {
+                a << shift.min(63)
+            } else {
+                a >> (-shift).min(63)
+            }) as f64
+        })?,
+        "eq" | "ne" => {
+            let b = pop(stack)?;
+            let a = pop(stack)?;
+            let equal = match (a, b) {
+                (Number(a), Number(b)) => a == b,
+                (Bool(a), Bool(b)) => a == b,
+                _ => false,
+            };
+            stack.push(Bool(equal == (op == "eq")));
+        }
+        "gt" => compare(stack, |a, b| a > b)?,
+        "ge" => compare(stack, |a, b| a >= b)?,
+        "lt" => compare(stack, |a, b| a < b)?,
+        "le" => compare(stack, |a, b| a <= b)?,
+        "and" | "or" | "xor" => {
+            let b = pop(stack)?;
+            let a = pop(stack)?;
+            stack.push(match (a, b) {
+                (Bool(a), Bool(b)) => Bool(match op {
+                    "and" => a & b,
+                    "or" => a | b,
+                    _ => a ^ b,
+                }),
+                (Number(a), Number(b)) => {
+                    let (a, b) = (a as i64, b as i64);
+                    Number(match op {
+                        "and" => a & b,
+                        "or" => a | b,
+                        _ => a ^ b,
+                    } as f64)
+                }
+                _ => bail!("Mismatched operands to {op}"),
+            });
+        }
+        "not" => {
+            let value = match pop(stack)? {
+                Bool(b) => Bool(!b),
+                Number(n) => Number(!(n as i64) as f64),
+            };
+            stack.push(value);
+        }
+        "pop" => {
+            pop(stack)?;
+        }
+        "dup" => {
+            let a = pop(stack)?;
+            stack.extend([a, a]);
+        }
+        "exch" => {
+            let b = pop(stack)?;
+            let a = pop(stack)?;
+            stack.extend([b, a]);
+        }
+        "copy" => {
+            let n = pop_number(stack)? as usize;
+            if n > stack.len() {
+                bail!("PostScript stack underflow");
+            }
+            stack.extend_from_within(stack.len() - n..);
+        }
+        "index" => {
+            let n = pop_number(stack)? as usize;
+            let Some(value) = stack.len().checked_sub(n + 1).map(|i| stack[i]) else {
+                bail!("PostScript stack underflow");
+            };
+            stack.push(value);
+        }
+        "roll" => {
+            let j = pop_number(stack)? as i64;
+            let n = pop_number(stack)? as usize;
+            if n > stack.len() {
+                bail!("PostScript stack underflow");
+            }
+            if n > 0 {
+                let start = stack.len() - n;
+                let shift = j.rem_euclid(n as i64) as usize;
+                stack[start..].rotate_right(shift);
+            }
+        }
+        other => bail!("Unsupported PostScript operator {other}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Dictionary, Stream, dictionary};
+
+    use super::*;
+
+    fn function(object: impl Into<Object>) -> PdfFunction {
+        PdfFunction::from_pdf(&Document::new(), &object.into()).unwrap()
+    }
+
+    fn exponential(c0: f32, c1: f32, n: f32) -> Dictionary {
+        dictionary! {
+            "FunctionType" => 2,
+            "Domain" => vec![0.into(), 1.into()],
+            "C0" => vec![c0.into()],
+            "C1" => vec![c1.into()],
+            "N" => n,
+        }
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn sampled_functions_interpolate_between_samples() {
+        // Two outputs sampled at three points
+        let f = function(Stream::new(
+            dictionary! {
+                "FunctionType" => 0,
+                "Domain" => vec![0.into(), 1.into()],
+                "Range" => vec![0.into(), 1.into(), 0.into(), 10.into()],
+                "Size" => vec![3.into()],
+                "BitsPerSample" => 8,
+            },
+            vec![0, 0, 255, 255, 0, 0],
+        ));
+        assert!(close(&f.eval(&[0.]), &[0., 0.]));
+        assert!(close(&f.eval(&[0.25]), &[0.5, 5.]));
+        assert!(close(&f.eval(&[0.5]), &[1., 10.]));
+        assert!(close(&f.eval(&[2.]), &[0., 0.]));
+    }
+
+    #[test]
+    fn exponential_functions_raise_to_n() {
+        let f = function(exponential(0.2, 1., 2.));
+        assert!(close(&f.eval(&[0.5]), &[0.4]));
+        // Inputs are clipped to the domain
+        assert!(close(&f.eval(&[-1.]), &[0.2]));
+    }
+
+    #[test]
+    fn stitching_functions_pick_a_function_by_bounds() {
+        let f = function(dictionary! {
+            "FunctionType" => 3,
+            "Domain" => vec![0.into(), 1.into()],
+            "Functions" => vec![exponential(0., 1., 1.).into(), exponential(1., 0., 1.).into()],
+            "Bounds" => vec![0.5.into()],
+            "Encode" => vec![0.into(), 1.into(), 0.into(), 1.into()],
+        });
+        assert!(close(&f.eval(&[0.25]), &[0.5]));
+        assert!(close(&f.eval(&[0.5]), &[1.]));
+        assert!(close(&f.eval(&[0.75]), &[0.5]));
+    }
+
+    #[test]
+    fn postscript_functions_run_their_programs() {
+        let f = function(Stream::new(
+            dictionary! {
+                "FunctionType" => 4,
+                "Domain" => vec![0.into(), 1.into(), 0.into(), 1.into()],
+                "Range" => vec![0.into(), 1.into(), 0.into(), 1.into()],
+            },
+            b"{ 2 copy gt { exch } if pop dup 0.5 lt { 2 mul } { 1 exch sub } ifelse 0.3 }"
+                .to_vec(),
+        ));
+        assert!(close(&f.eval(&[0.2, 0.3]), &[0.4, 0.3]));
+        assert!(close(&f.eval(&[0.9, 0.8]), &[0.2, 0.3]));
+    }
+}
@@ -1,14 +1,43 @@
-use std::{collections::HashMap, fmt::Debug, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use eyre::{Result, bail, eyre};
+use eyre::{Result, WrapErr, bail, eyre};
 
+pub mod bounds;
+pub mod canvas;
+pub mod color;
+pub mod content;
+pub mod cpu;
+pub mod error;
+pub mod extract;
+pub mod filter;
+pub mod functions;
 pub mod offscreen;
+pub mod optional;
+pub mod pattern;
+pub mod shading;
+pub mod svg;
+#[cfg(test)]
+mod testing;
 pub mod text;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod xobject;
 
-use kurbo::BezPath;
-use lopdf::{Dictionary, Document, Object, ObjectId, content::Content};
-use peniko::{Color, Fill};
+pub use canvas::Canvas;
+use color::ColorSpace;
+pub use error::RasterError;
+use kurbo::{Affine, BezPath, Cap, Join, Rect, Shape, Stroke};
+use lopdf::{Dictionary, Document, Object, ObjectId, content::Operation};
+use optional::{Hidden, OptionalContent};
+use peniko::{BlendMode, Color, Compose, Fill, Mix};
 pub use text::font::Font;
+use text::{TextOperation, font::FontProgram, type3::Type3Font};
 use vello::Scene;
 
 fn get<A: FromPDF>(doc: &Document, root: &Object) -> Result<A> {
@@ -21,10 +50,11 @@ pub trait FromPDF: Sized {
 
 impl FromPDF for Vec<u8> {
     fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
-        Ok(doc
-            .get_object(root.as_reference()?)?
-            .as_stream()?
-            .decompressed_content()?)
+        match doc.dereference(root)?.1 {
+            Object::String(bytes, _) => Ok(bytes.clone()),
+            Object::Stream(stream) => filter::decode_stream(doc, stream),
+            other => bail!("Expected stream or string, got {:?}", other),
+        }
     }
 }
 
@@ -42,7 +72,7 @@ impl FromPDF for String {
 
 impl<A: FromPDF> FromPDF for Vec<A> {
     fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
-        match root {
+        match doc.dereference(root)?.1 {
             Object::Array(objs) => objs.iter().map(|o| A::from_pdf(doc, o)).collect(),
             _ => Err(eyre!("expected Array")),
         }
@@ -75,14 +105,72 @@ pub struct TextMatrix {
     pub f: i64,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct TextState {
     pub position: f32,
     pub size: f32,
     pub matrix: CTM,
+    pub line_matrix: CTM,
+    pub leading: f32,
+    pub char_spacing: f32,
+    pub word_spacing: f32,
+    pub h_scale: f32,
+    pub rise: f32,
+    pub render_mode: u8,
+    pub clip_path: BezPath,
     pub font: Option<Rc<Font>>,
 }
 
+impl Default for TextState {
+    fn default() -> Self {
+        Self {
+            position: 0.,
+            size: 0.,
+            matrix: CTM::default(),
+            line_matrix: CTM::default(),
+            leading: 0.,
+            char_spacing: 0.,
+            word_spacing: 0.,
+            h_scale: 100.,
+            rise: 0.,
+            render_mode: 0,
+            clip_path: BezPath::new(),
+            font: None,
+        }
+    }
+}
+
+impl TextState {
+    pub fn set_matrix(&mut self, matrix: CTM) {
+        self.matrix = matrix.clone();
+        self.line_matrix = matrix;
+        self.position = 0.;
+    }
+
+    /// Changes the font size without moving the text position, which is kept
+    /// in thousandths of an em.
+    pub fn set_size(&mut self, size: f32) {
+        self.position = if size == 0. {
+            0.
+        } else {
+            self.position * self.size / size
+        };
+        self.size = size;
+    }
+
+    pub fn next_line(&mut self, tx: f32, ty: f32) {
+        let translation = CTM {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 1.0,
+            e: tx,
+            f: ty,
+        };
+        self.set_matrix(concat(&self.line_matrix, &translation));
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy)]
 pub struct Coord {
     pub x: f32,
@@ -105,7 +193,7 @@ pub fn concat(m1: &CTM, m2: &CTM) -> CTM {
         b: m1.b * m2.a + m1.d * m2.b,
         c: m1.a * m2.c + m1.c * m2.d,
         d: m1.b * m2.c + m1.d * m2.d,
-        e: m1.a * m2.e + m1.b * m2.f + m1.e,
+        e: m1.a * m2.e + m1.c * m2.f + m1.e,
         f: m1.b * m2.e + m1.d * m2.f + m1.f,
     }
 }
@@ -133,6 +221,29 @@ impl Default for CTM {
     }
 }
 
+impl FromPDF for CTM {
+    fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
+        match get::<Vec<f32>>(doc, root)?[..] {
+            [a, b, c, d, e, f] => Ok(CTM { a, b, c, d, e, f }),
+            ref other => bail!("Expected [a b c d e f] matrix, got {:?}", other),
+        }
+    }
+}
+
+impl From<&CTM> for Affine {
+    fn from(ctm: &CTM) -> Self {
+        let CTM { a, b, c, d, e, f } = *ctm;
+        Affine::new([a, b, c, d, e, f].map(|v| v as f64))
+    }
+}
+
+impl From<Affine> for CTM {
+    fn from(affine: Affine) -> Self {
+        let [a, b, c, d, e, f] = affine.as_coeffs().map(|v| v as f32);
+        CTM { a, b, c, d, e, f }
+    }
+}
+
 impl Debug for CTM {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
@@ -150,129 +261,798 @@ pub struct GraphicsState {
     pub ctm: CTM,
     pub stroke_color: Color,
     pub non_stroke_color: Color,
+    pub current_stroke_space: ColorSpace,
+    pub current_fill_space: ColorSpace,
     pub path: BezPath,
-    pub text_state: Option<TextState>,
+    pub text_state: TextState,
     pub line_width: f32,
+    pub dash_pattern: Vec<f64>,
+    pub dash_phase: f64,
+    pub line_cap: Cap,
+    pub line_join: Join,
+    pub miter_limit: f64,
     pub current_point: Coord,
+    /// Where the current subpath began, which `h` returns the current point to.
+    pub subpath_start: Coord,
+    pub clip_pending: bool,
+    pub clip_layers: usize,
+    /// Set while drawing a luminosity soft mask, where paint only counts
+    /// for its luminance.
+    pub luminosity_mask: bool,
+    pub blend_mode: Mix,
+    /// The layer pushed to blend with `blend_mode`, if it isn't normal.
+    pub blend_layer: Option<usize>,
+    /// The pattern fills paint with, chosen by `scn` in the Pattern color space.
+    pub fill_pattern: Option<Object>,
+    /// How far, in device pixels, curves we approximate may stray from the
+    /// real ones, set by `i`.
+    pub flatness: f64,
+    /// Whether strokes and fills overprint, set by an ExtGState's `OP` and `op`.
+    pub stroke_overprint: bool,
+    pub fill_overprint: bool,
+    /// Set by `OPM 1`, where a CMYK component of 0 leaves that ink alone.
+    pub nonzero_overprint: bool,
+    /// Whether the stroke and fill colors are CMYK with a component of 0.
+    pub stroke_zero_ink: bool,
+    pub fill_zero_ink: bool,
 }
 
+const DEFAULT_FLATNESS: f64 = 0.1;
+
 impl Default for GraphicsState {
     fn default() -> Self {
         Self {
             ctm: Default::default(),
             stroke_color: Color::BLACK,
             non_stroke_color: Color::BLACK,
+            current_stroke_space: ColorSpace::default(),
+            current_fill_space: ColorSpace::default(),
             path: BezPath::new(),
-            text_state: None,
+            text_state: TextState::default(),
             line_width: 1.,
+            dash_pattern: Vec::new(),
+            dash_phase: 0.,
+            line_cap: Cap::Butt,
+            line_join: Join::Miter,
+            miter_limit: 10.,
             current_point: Coord::default(),
+            subpath_start: Coord::default(),
+            clip_pending: false,
+            clip_layers: 0,
+            luminosity_mask: false,
+            blend_mode: Mix::Normal,
+            blend_layer: None,
+            fill_pattern: None,
+            flatness: DEFAULT_FLATNESS,
+            stroke_overprint: false,
+            fill_overprint: false,
+            nonzero_overprint: false,
+            stroke_zero_ink: false,
+            fill_zero_ink: false,
+        }
+    }
+}
+
+impl GraphicsState {
+    /// Sets the tolerance curves are approximated to, where 0 asks for the
+    /// default and the largest allowed is 100.
+    pub fn set_flatness(&mut self, flatness: f32) {
+        self.flatness = if flatness > 0. {
+            flatness.min(100.) as f64
+        } else {
+            DEFAULT_FLATNESS
+        };
+    }
+
+    /// Whether a stroke or fill leaves some of the inks beneath it showing,
+    /// which only CMYK colors with a component of 0 do, and only under
+    /// `OPM 1`; any other process color paints every ink.
+    pub fn overprints(&self, stroke: bool) -> bool {
+        self.nonzero_overprint
+            && match stroke {
+                true => self.stroke_overprint && self.stroke_zero_ink,
+                false => self.fill_overprint && self.fill_zero_ink,
+            }
+    }
+
+    /// The color actually painted for `color`, which inside a luminosity mask
+    /// is black with the color's luminance as its alpha.
+    pub fn paint(&self, color: Color) -> Color {
+        if self.luminosity_mask {
+            let [r, g, b, a] = color.components;
+            Color::new([0., 0., 0., a * luminance(r, g, b)])
+        } else {
+            color
         }
     }
 }
 
+pub(crate) fn luminance(r: f32, g: f32, b: f32) -> f32 {
+    0.3 * r + 0.59 * g + 0.11 * b
+}
+
+/// A soft mask set by an ExtGState, applied to everything drawn while it's in effect.
+#[derive(Debug, Clone)]
+pub struct SoftMask {
+    pub group: Object,
+    /// The CTM when the mask was set, which places the group.
+    pub ctm: CTM,
+    pub luminosity: bool,
+    pub backdrop: Color,
+}
+
 #[derive(Debug)]
 pub struct State {
     pub gs: GraphicsState,
     pub stack: Vec<GraphicsState>,
+    /// The deepest `q` have nested, counting any that were ignored.
+    pub max_depth: usize,
+    /// How many `q` past the maximum depth are still open; they were
+    /// ignored, and so are the `Q` matching them.
+    pub ignored_saves: usize,
+    /// Soft masks waiting to be applied, with the layer holding what they mask.
+    pub masks: Vec<(usize, SoftMask)>,
+    /// The CTM the content stream started with, which patterns are placed in.
+    pub base_ctm: CTM,
+    /// Each open marked content sequence, and whether it's optional content
+    /// that's hidden.
+    pub marked_content: Vec<bool>,
 }
 
 impl Default for State {
     fn default() -> Self {
+        State::new(GraphicsState::default())
+    }
+}
+
+impl State {
+    /// The state a content stream starts in, with its own stack so unbalanced
+    /// q/Q can't leak out.
+    pub fn new(gs: GraphicsState) -> Self {
         Self {
-            gs: Default::default(),
+            base_ctm: gs.ctm.clone(),
+            gs,
             stack: Vec::new(),
+            max_depth: 0,
+            ignored_saves: 0,
+            masks: Vec::new(),
+            marked_content: Vec::new(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RenderSettings {
+    /// Smooths edges with `anti_aliasing`; without it edges are drawn with
+    /// vello's cheaper area coverage.
     pub anti_alias: bool,
+    pub anti_aliasing: AntiAliasing,
+    /// Renders at this resolution, overriding any scale the caller passes.
+    pub dpi: Option<f32>,
+    /// The page color; `None` leaves the background transparent.
+    pub background: Option<Color>,
+    /// Runs vello's pipeline on the CPU rather than in compute shaders.
+    pub use_cpu: bool,
+    /// Draws the appearance streams of annotations, such as link borders and
+    /// form fields, over the page.
+    pub render_annotations: bool,
+    /// Logs operators that fail, e.g. on operands of the wrong type, and
+    /// carries on with the next rather than giving up on the page.
+    pub skip_bad_operators: bool,
+    /// How deeply `q` may nest. Any deeper are ignored, along with the `Q`
+    /// matching them, which keeps documents that never restore the graphics
+    /// state from using ever more memory.
+    pub max_stack_depth: usize,
+    /// Font files to stand in for fonts that aren't embedded, by /BaseFont
+    /// or standard 14 name, tried before the system's in
+    /// [`text::substitute::FONT_DIRS`]. [`FontCache::new`] reads these, so
+    /// the cache passed to [`draw_doc`] should be built from the same
+    /// settings.
+    pub font_substitutions: HashMap<String, PathBuf>,
 }
 
 impl Default for RenderSettings {
     fn default() -> Self {
-        Self { anti_alias: true }
+        Self {
+            anti_alias: true,
+            anti_aliasing: AntiAliasing::default(),
+            dpi: None,
+            background: Some(Color::WHITE),
+            use_cpu: false,
+            render_annotations: false,
+            skip_bad_operators: false,
+            max_stack_depth: 256,
+            font_substitutions: HashMap::new(),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The scale to render at: from `dpi` if set, otherwise `scale`.
+    pub fn scale(&self, scale: f32) -> f32 {
+        self.dpi.map_or(scale, scale_for_dpi)
+    }
+
+    /// The antialiasing vello renders with.
+    pub fn aa_config(&self) -> vello::AaConfig {
+        if !self.anti_alias {
+            return vello::AaConfig::Area;
+        }
+        match self.anti_aliasing {
+            AntiAliasing::Area => vello::AaConfig::Area,
+            AntiAliasing::Msaa8 => vello::AaConfig::Msaa8,
+            AntiAliasing::Msaa16 => vello::AaConfig::Msaa16,
+        }
+    }
+}
+
+/// How vello antialiases edges: from analytic area coverage, which is
+/// fastest, or with 8 or 16 samples per pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AntiAliasing {
+    Area,
+    Msaa8,
+    #[default]
+    Msaa16,
+}
+
+/// Converts a resolution to a scale relative to PDF points, which are 1/72 inch.
+pub fn scale_for_dpi(dpi: f32) -> f32 {
+    dpi / 72.0
+}
+
+/// Loads a document, decrypting it with `password`. lopdf already decrypts
+/// documents whose user password is empty, so this only matters for the
+/// ones that need a real password.
+pub fn load_encrypted(bytes: &[u8], password: &str) -> Result<Document, RasterError> {
+    let mut doc = Document::load_mem(bytes)?;
+    if doc.is_encrypted() {
+        if doc.authenticate_user_password(password).is_err() {
+            if password.is_empty() {
+                return Err(RasterError::PasswordRequired);
+            }
+            // lopdf only derives the decryption key from the user password
+            if doc.authenticate_owner_password(password).is_ok() {
+                return Err(RasterError::OwnerPassword);
+            }
+            return Err(RasterError::IncorrectPassword);
+        }
+        doc.decrypt(password)
+            .map_err(|e| eyre!("Failed to decrypt the document: {e}"))?;
+    }
+    Ok(doc)
+}
+
+/// Reads and parses the PDF at `path`.
+pub fn open(path: impl AsRef<Path>) -> Result<Document, RasterError> {
+    open_encrypted(path, "")
+}
+
+/// Reads and parses the PDF at `path`, decrypting it with `password` like
+/// [`load_encrypted`].
+pub fn open_encrypted(path: impl AsRef<Path>, password: &str) -> Result<Document, RasterError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)
+        .wrap_err_with(|| eyre!("Failed to read PDF file: {}", path.display()))?;
+    load_encrypted(&bytes, password)
+        .map_err(|e| e.wrap_err(format!("Failed to parse PDF document: {}", path.display())))
+}
+
+/// Looks up a 1-based page number, or reports that it's out of range
+/// along with how many pages there are.
+pub fn validate_page(doc: &Document, page: u32) -> Result<ObjectId, RasterError> {
+    let pages = doc.get_pages();
+    pages
+        .get(&page)
+        .copied()
+        .ok_or_else(|| RasterError::PageNotFound {
+            page,
+            count: pages.len(),
+        })
+}
+
+pub fn page_count(doc: &Document) -> usize {
+    doc.get_pages().len()
+}
+
+/// The width and height of a 1-based page, in points.
+pub fn page_size(doc: &Document, page: u32) -> Result<(f32, f32), RasterError> {
+    Ok(dimensions(
+        doc,
+        doc.get_dictionary(validate_page(doc, page)?)?,
+    )?)
+}
+
+// Looks up a page attribute, falling back to the page tree nodes it inherits from
+fn inherited<'a>(doc: &'a Document, page: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut node = page;
+    for _ in 0..MAX_FORM_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return doc.dereference(value).ok().map(|(_, value)| value);
+        }
+        node = doc.get_dict_in_dict(node, b"Parent").ok()?;
+    }
+    None
+}
+
+/// The page's content streams, decoded and joined together.
+pub fn page_content(doc: &Document, page_id: ObjectId) -> Result<Vec<u8>> {
+    let page = doc.get_dictionary(page_id)?;
+    let streams = match page.get(b"Contents").map(|c| doc.dereference(c)) {
+        Ok(Ok((_, Object::Array(streams)))) => streams.clone(),
+        Ok(Ok((_, stream))) => vec![stream.clone()],
+        _ => Vec::new(),
+    };
+    let mut content = Vec::new();
+    for stream in &streams {
+        let stream = doc.dereference(stream)?.1.as_stream()?;
+        content.extend(filter::decode_stream(doc, stream)?);
+        content.push(b'\n');
+    }
+    Ok(content)
+}
+
+/// The page's /Resources, which may be inherited from the page tree.
+pub fn page_resources<'a>(doc: &'a Document, page: &'a Dictionary) -> Option<&'a Dictionary> {
+    inherited(doc, page, b"Resources").and_then(|r| r.as_dict().ok())
+}
+
+/// The page's /Rotate, normalized to one of 0, 90, 180 or 270 degrees.
+pub fn rotation(doc: &Document, page: &Dictionary) -> i64 {
+    let rotate = inherited(doc, page, b"Rotate")
+        .and_then(|r| r.as_i64().ok())
+        .unwrap_or(0);
+    (rotate / 90).rem_euclid(4) * 90
+}
+
+fn rect(object: &Object) -> Result<[f32; 4]> {
+    match &object.as_array()?[..] {
+        [x0, y0, x1, y1] => {
+            let (x0, y0, x1, y1) = (
+                x0.as_float()?,
+                y0.as_float()?,
+                x1.as_float()?,
+                y1.as_float()?,
+            );
+            Ok([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+        }
+        other => bail!("Expected [x0 y0 x1 y1], but {:?}", other),
     }
 }
 
-pub fn dimensions(page: &Dictionary) -> Result<(f32, f32)> {
-    match &*page.get(b"MediaBox")?.as_array()?.clone() {
-        [_, _, w, h] => Ok((w.as_float()?, h.as_float()?)),
-        other => bail!("Expected [0 0 w h], but {:?}", other),
+/// The visible region of the page in user space as `[x0, y0, x1, y1]`: its
+/// /CropBox clipped to the /MediaBox, or the /MediaBox when there's no crop.
+pub fn page_box(doc: &Document, page: &Dictionary) -> Result<[f32; 4]> {
+    let media_box = rect(inherited(doc, page, b"MediaBox").ok_or_else(|| eyre!("No MediaBox"))?)?;
+    match inherited(doc, page, b"CropBox").map(rect) {
+        Some(Ok([x0, y0, x1, y1])) => {
+            let [mx0, my0, mx1, my1] = media_box;
+            let clipped = [x0.max(mx0), y0.max(my0), x1.min(mx1), y1.min(my1)];
+            if clipped[0] < clipped[2] && clipped[1] < clipped[3] {
+                Ok(clipped)
+            } else {
+                Ok(media_box)
+            }
+        }
+        _ => Ok(media_box),
     }
 }
 
+/// How many points a unit of the page's user space is, from the /UserUnit
+/// large pages use to get around the limit on page size.
+pub fn user_unit(page: &Dictionary) -> f32 {
+    page.get(b"UserUnit")
+        .and_then(Object::as_float)
+        .ok()
+        .filter(|unit| *unit > 0.)
+        .unwrap_or(1.)
+}
+
+/// The size of the page as displayed in points, with width and height
+/// swapped for pages rotated by 90 or 270 degrees.
+pub fn dimensions(doc: &Document, page: &Dictionary) -> Result<(f32, f32)> {
+    let [x0, y0, x1, y1] = page_box(doc, page)?;
+    let unit = user_unit(page);
+    let (w, h) = ((x1 - x0) * unit, (y1 - y0) * unit);
+    Ok(match rotation(doc, page) {
+        90 | 270 => (h, w),
+        _ => (w, h),
+    })
+}
+
+// The initial CTM, which moves the page box's origin to the bottom left of
+// the displayed page, scales user units to points and applies its rotation
+fn page_ctm(doc: &Document, page: &Dictionary) -> Result<CTM> {
+    let [x0, y0, ..] = page_box(doc, page)?;
+    let origin = CTM {
+        e: -x0,
+        f: -y0,
+        ..CTM::default()
+    };
+    let unit = user_unit(page);
+    let scale = CTM {
+        a: unit,
+        d: unit,
+        ..CTM::default()
+    };
+    let rotate = rotation_ctm(rotation(doc, page), dimensions(doc, page)?);
+    Ok(concat(&rotate, &concat(&scale, &origin)))
+}
+
+// Maps unrotated user space onto a displayed page of size (w, h), whose
+// origin stays at the bottom left
+fn rotation_ctm(rotation: i64, (w, h): (f32, f32)) -> CTM {
+    let (a, b, c, d, e, f) = match rotation {
+        90 => (0., -1., 1., 0., 0., h),
+        180 => (-1., 0., 0., -1., w, h),
+        270 => (0., 1., -1., 0., w, 0.),
+        _ => return CTM::default(),
+    };
+    CTM { a, b, c, d, e, f }
+}
+
 pub struct DeviceScale {
+    width: u32,
     height: u32,
     scale: f32,
 }
 
-fn to_color(r: &Object, g: &Object, b: &Object) -> Result<Color> {
-    Ok(Color::new([
-        r.as_float()?,
-        g.as_float()?,
-        b.as_float()?,
-        1.0,
-    ]))
+impl DeviceScale {
+    pub fn affine(&self, ctm: &CTM) -> Affine {
+        let scale = self.scale as f64;
+        Affine::new([scale, 0., 0., -scale, 0., self.height as f64]) * Affine::from(ctm)
+    }
+
+    /// The whole output area in device space.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0., 0., self.width as f64, self.height as f64)
+    }
+}
+
+fn device_space(operator: &str) -> ColorSpace {
+    match operator {
+        "g" | "G" => ColorSpace::DeviceGray,
+        "rg" | "RG" => ColorSpace::DeviceRGB,
+        _ => ColorSpace::DeviceCMYK,
+    }
+}
+
+pub struct Resources<'a> {
+    pub dict: &'a Dictionary,
+    pub fonts: HashMap<Vec<u8>, Rc<Font>>,
+    pub ext_gstate: HashMap<Vec<u8>, Dictionary>,
+}
+
+impl<'a> Resources<'a> {
+    pub fn new(doc: &Document, dict: &'a Dictionary, fonts: &FontCache) -> Self {
+        let entries = |key: &[u8]| -> Vec<(Vec<u8>, &Object)> {
+            doc.get_dict_in_dict(dict, key)
+                .map(|d| {
+                    d.iter()
+                        .filter_map(|(name, obj)| {
+                            Some((name.clone(), doc.dereference(obj).ok()?.1))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let ext_gstate = entries(b"ExtGState")
+            .into_iter()
+            .filter_map(|(name, obj)| Some((name, obj.as_dict().ok()?.clone())))
+            .collect();
+
+        let fonts = doc
+            .get_dict_in_dict(dict, b"Font")
+            .map(|d| {
+                d.iter()
+                    .filter_map(|(name, obj)| match fonts.load(doc, obj) {
+                        Ok(font) => Some((name.clone(), font)),
+                        Err(e) => {
+                            log::warn!("Skipping font {}: {e}", String::from_utf8_lossy(name));
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Resources {
+            dict,
+            fonts,
+            ext_gstate,
+        }
+    }
+}
+
+/// Fonts loaded from a document, kept by the reference to their dictionary
+/// so that pages sharing a font share the parsed program and the glyph
+/// outlines already built from it. A cache only holds fonts from the one
+/// document, and the one [`RenderSettings`], it's used with.
+pub struct FontCache {
+    // Fonts that failed keep the reason, to report it again
+    fonts: RefCell<HashMap<ObjectId, Result<Rc<Font>, String>>>,
+    substitutions: HashMap<String, PathBuf>,
+}
+
+impl FontCache {
+    /// A cache loading fonts with the settings'
+    /// [`font_substitutions`](RenderSettings::font_substitutions).
+    pub fn new(settings: &RenderSettings) -> Self {
+        FontCache {
+            fonts: RefCell::default(),
+            substitutions: settings.font_substitutions.clone(),
+        }
+    }
+
+    fn load(&self, doc: &Document, font: &Object) -> Result<Rc<Font>, RasterError> {
+        let load = |font| match Font::load(doc, font, &self.substitutions) {
+            Ok(font) => Ok(Rc::new(font)),
+            Err(e) => match RasterError::from(e) {
+                RasterError::UnsupportedFont(reason) => Err(reason),
+                e => Err(e.to_string()),
+            },
+        };
+        // Fonts written directly in a resource dictionary can't be shared
+        let Ok(id) = font.as_reference() else {
+            return load(font).map_err(RasterError::UnsupportedFont);
+        };
+        let cached = self.fonts.borrow().get(&id).cloned();
+        let loaded = cached.unwrap_or_else(|| {
+            let loaded = doc.get_object(id).map_err(|e| e.to_string()).and_then(load);
+            self.fonts.borrow_mut().insert(id, loaded.clone());
+            loaded
+        });
+        loaded.map_err(RasterError::UnsupportedFont)
+    }
+
+    /// How many glyph outlines have been asked for across every font, and
+    /// how many of those had to be built.
+    pub fn glyph_stats(&self) -> (usize, usize) {
+        self.fonts
+            .borrow()
+            .values()
+            .flatten()
+            .map(|font| font.glyph_stats())
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Context<'a> {
+    doc: &'a Document,
+    fonts: &'a FontCache,
+    optional: &'a OptionalContent,
+    scale: &'a DeviceScale,
+    settings: &'a RenderSettings,
+    depth: usize,
+    /// Set inside Type3 glyphs declared with `d1`, which are shapes painted in
+    /// the text's color, so color operators are ignored.
+    uncolored: bool,
 }
 
+const MAX_FORM_DEPTH: usize = 32;
+
+/// Draws a 1-based page at `width` x `height` pixels onto any [`Canvas`],
+/// such as a vello `Scene`, leaving the background to the caller.
 pub fn draw_doc(
     doc: &Document,
-    scene: &mut Scene,
+    scene: &mut dyn Canvas,
     width: u32,
     height: u32,
     page: u32,
     settings: &RenderSettings,
-) -> Result<()> {
-    let page_id = doc
-        .get_pages()
-        .get(&page)
-        .ok_or_else(|| eyre!("No such page"))?
-        .clone();
+    fonts: &FontCache,
+) -> Result<(), RasterError> {
+    let page_id = validate_page(doc, page)?;
     let page_dict = doc.get_dictionary(page_id)?;
-    let size: (f32, f32) = dimensions(page_dict)?;
+    let size: (f32, f32) = dimensions(doc, page_dict)?;
     let scale = DeviceScale {
+        width,
         height,
         scale: width as f32 / size.0,
     };
 
-    let fonts = doc.get_page_fonts(page_id)?;
     let default_dict = Dictionary::default();
-    let resource_dict = doc
-        .get_dict_in_dict(page_dict, b"Resources")
-        .unwrap_or(&default_dict);
+    let resource_dict = page_resources(doc, page_dict).unwrap_or(&default_dict);
+    let resources = Resources::new(doc, resource_dict, fonts);
 
-    let ext_gstate_map: HashMap<Vec<u8>, Dictionary> = match resource_dict.get(b"ExtGState") {
-        Ok(obj) => match obj.as_dict() {
-            Ok(ext_gstate_dict) => ext_gstate_dict
-                .iter()
-                .filter_map(|(name, obj_ref)| {
-                    obj_ref
-                        .as_reference()
-                        .ok()
-                        .and_then(|id| doc.get_dictionary(id).ok())
-                        .map(|dict| (name.clone(), dict.clone()))
-                })
-                .collect(),
-            Err(_) => HashMap::new(),
+    let raw = page_content(doc, page_id)?;
+    let content = content::decode_content(&raw)
+        .map_err(|e| RasterError::MalformedContent(format!("{e:#}")))?;
+
+    let optional = OptionalContent::new(doc);
+    let cx = Context {
+        doc,
+        fonts,
+        optional: &optional,
+        scale: &scale,
+        settings,
+        depth: 0,
+        uncolored: false,
+    };
+    let ctm = page_ctm(doc, page_dict)?;
+    draw_content(
+        cx,
+        scene,
+        &mut State::new(GraphicsState {
+            ctm: ctm.clone(),
+            ..Default::default()
+        }),
+        &resources,
+        &content.operations,
+    )?;
+
+    if settings.render_annotations {
+        draw_annotations(cx, scene, page_dict, &ctm, &resources);
+    }
+    Ok(())
+}
+
+// Draws the normal appearance of each visible annotation; one that can't be
+// drawn is skipped rather than failing the page
+fn draw_annotations(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    page: &Dictionary,
+    ctm: &CTM,
+    resources: &Resources,
+) {
+    let doc = cx.doc;
+    let Ok(annots) = page
+        .get(b"Annots")
+        .and_then(|annots| doc.dereference(annots))
+        .and_then(|(_, annots)| annots.as_array())
+    else {
+        return;
+    };
+    for annot in annots {
+        let result = doc
+            .dereference(annot)
+            .and_then(|(_, annot)| annot.as_dict())
+            .map_err(Into::into)
+            .and_then(|annot| draw_annotation(cx, scene, annot, ctm, resources));
+        if let Err(e) = result {
+            log::warn!("Skipping annotation: {e}");
+        }
+    }
+}
+
+fn draw_annotation(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    annot: &Dictionary,
+    ctm: &CTM,
+    resources: &Resources,
+) -> Result<()> {
+    let doc = cx.doc;
+    // The Hidden and NoView flags
+    let flags = annot.get(b"F").and_then(|f| f.as_i64()).unwrap_or(0);
+    if flags & (1 << 1 | 1 << 5) != 0 {
+        return Ok(());
+    }
+    let Ok(normal) = doc
+        .get_dict_in_dict(annot, b"AP")
+        .and_then(|appearances| appearances.get(b"N"))
+    else {
+        return Ok(());
+    };
+    // Appearances with several states, like checkboxes, pick one with /AS
+    let appearance = match doc.dereference(normal)?.1 {
+        Object::Dictionary(states) => match annot.get(b"AS").and_then(|state| state.as_name()) {
+            Ok(state) => states.get(state)?,
+            Err(_) => return Ok(()),
         },
-        Err(_) => HashMap::new(),
+        _ => normal,
     };
+    let dict = &doc.dereference(appearance)?.1.as_stream()?.dict;
 
-    let font_map: HashMap<Vec<u8>, Rc<Font>> = fonts
-        .iter()
-        .flat_map(|(font_id, font_obj)| {
-            Font::from_pdf(doc, &Object::Dictionary((*font_obj).clone()))
-                .ok()
-                .map(|font| (font_id.clone(), Rc::new(font)))
-        })
-        .collect();
+    // The appearance's box, once transformed by its matrix, is stretched
+    // to fill the annotation's rectangle
+    let [x0, y0, x1, y1] = rect(doc.dereference(annot.get(b"Rect")?)?.1)?;
+    let [bx0, by0, bx1, by1] = rect(doc.dereference(dict.get(b"BBox")?)?.1)?;
+    let matrix = match dict.get(b"Matrix") {
+        Ok(matrix) => get(doc, matrix)?,
+        Err(_) => CTM::default(),
+    };
+    let bounds = Affine::from(&matrix)
+        .transform_rect_bbox(Rect::new(bx0 as f64, by0 as f64, bx1 as f64, by1 as f64));
+    if bounds.width() == 0. || bounds.height() == 0. {
+        return Ok(());
+    }
+    let (a, d) = (
+        (x1 - x0) / bounds.width() as f32,
+        (y1 - y0) / bounds.height() as f32,
+    );
+    let fit = CTM {
+        a,
+        d,
+        e: x0 - bounds.x0 as f32 * a,
+        f: y0 - bounds.y0 as f32 * d,
+        ..CTM::default()
+    };
+    let gs = GraphicsState {
+        ctm: concat(ctm, &fit),
+        ..Default::default()
+    };
+    draw_form(cx, scene, gs, resources, appearance)
+}
+
+/// Builds the scene for a page at `width` x `height` pixels, background
+/// included, without touching the GPU; callers with their own vello renderer
+/// can draw or composite it as they like.
+pub fn build_scene(
+    doc: &Document,
+    page: u32,
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+) -> Result<Scene, RasterError> {
+    let mut scene = Scene::new();
+    let fonts = FontCache::new(settings);
+    paint_page(doc, &mut scene, page, width, height, settings, &fonts)?;
+    Ok(scene)
+}
+
+// Draws a page over its background
+fn paint_page(
+    doc: &Document,
+    canvas: &mut dyn Canvas,
+    page: u32,
+    width: u32,
+    height: u32,
+    settings: &RenderSettings,
+    fonts: &FontCache,
+) -> Result<(), RasterError> {
+    if let Some(background) = settings.background {
+        canvas.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            background.into(),
+            &Rect::new(0.0, 0.0, width as f64, height as f64).to_path(0.1),
+        );
+    }
+    draw_doc(doc, canvas, width, height, page, settings, fonts)
+}
+
+fn draw_content(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    operations: &[Operation],
+) -> Result<()> {
+    for op in operations {
+        let result = match state.marked_content.contains(&true) {
+            true => draw_operation(cx, &mut Hidden(scene), state, resources, op),
+            false => draw_operation(cx, scene, state, resources, op),
+        };
+        if let Err(e) = result {
+            if !cx.settings.skip_bad_operators {
+                return Err(e);
+            }
+            log::warn!("Skipping {} after an error: {e:#}", op.operator);
+        }
+    }
+    if !state.stack.is_empty() {
+        log::warn!(
+            "Content ended with {} unmatched q, having nested {} deep",
+            state.stack.len() + state.ignored_saves,
+            state.max_depth
+        );
+    }
 
-    let raw = doc.get_page_content(page_id)?;
-    let content = Content::decode(&raw)?;
+    pop_layers(cx, scene, state, resources, 0)
+}
 
-    let mut state = State::default();
+fn draw_operation(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    op: &Operation,
+) -> Result<()> {
+    let Context { doc, scale, .. } = cx;
 
     let transform = |state: &State, x: &Object, y: &Object| -> Result<Coord> {
         Ok(transform_from(
@@ -281,280 +1061,2052 @@ pub fn draw_doc(
                 y: y.as_float()?,
             },
             &state.gs.ctm,
-            &scale,
+            scale,
         ))
     };
 
-    for op in content.operations {
-        let o = op.operator.as_str();
-        // eprintln!("op: {:?} {:?}", o, &op.operands[..]);
-        match (o, &op.operands[..]) {
-            ("BT", []) => {
-                state.gs.text_state = Some(TextState::default());
-            }
-            ("Tm", [a, b, c, d, e, f]) => {
-                if let Some(ts) = &mut state.gs.text_state {
-                    let tm_params = CTM {
-                        a: a.as_float()?,
-                        b: b.as_float()?,
-                        c: c.as_float()?,
-                        d: d.as_float()?,
-                        e: e.as_float()?,
-                        f: f.as_float()?,
-                    };
-                    ts.matrix = concat(&state.gs.ctm, &tm_params);
-                }
-            }
-            ("Tf", [Object::Name(n), size]) => {
-                if let Some(font) = font_map.get(n) {
-                    if let Some(ts) = &mut state.gs.text_state {
-                        ts.font = Some(font.clone());
-                        ts.size = size.as_float()?;
-                    }
-                }
+    let o = op.operator.as_str();
+    log::trace!("op: {:?} {:?}", o, &op.operands[..]);
+    match text::text_operation(&mut state.gs.text_state, resources, op)? {
+        TextOperation::Show(glyphs) => {
+            show_text(cx, scene, state, resources, glyphs);
+            return Ok(());
+        }
+        TextOperation::State => return Ok(()),
+        TextOperation::Other => {}
+    }
+    match (o, &op.operands[..]) {
+        ("ET", []) => {
+            let clip = std::mem::take(&mut state.gs.text_state.clip_path);
+            if !clip.is_empty() {
+                scene.push_layer(Mix::Clip.into(), 1.0, Affine::IDENTITY, &clip);
+                state.gs.clip_layers += 1;
             }
+        }
+        ("cm", [a, b, c, d, e, f]) => {
+            let ctm = CTM {
+                a: a.as_float()?,
+                b: b.as_float()?,
+                c: c.as_float()?,
+                d: d.as_float()?,
+                e: e.as_float()?,
+                f: f.as_float()?,
+            };
 
-            ("TJ", [text]) => {
-                text::draw_text(&scale, scene, &mut state.gs, text.as_array()?, &settings)?;
-            }
-            ("Tj", [text]) => {
-                text::draw_text(&scale, scene, &mut state.gs, &[text.clone()], &settings);
-            }
-            ("Td", [tx, ty]) => {
-                if let Some(ts) = &mut state.gs.text_state {
-                    let tx_val = tx.as_float()?;
-                    let ty_val = ty.as_float()?;
-                    let translation = CTM {
-                        a: 1.0,
-                        b: 0.0,
-                        c: 0.0,
-                        d: 1.0,
-                        e: tx_val,
-                        f: ty_val,
-                    };
-                    ts.matrix = concat(&ts.matrix, &translation);
-                }
-            }
-            ("ET", []) => {
-                state.gs.text_state = None;
-            }
-            ("cm", [a, b, c, d, e, f]) => {
-                let ctm = CTM {
-                    a: a.as_float()?,
-                    b: b.as_float()?,
-                    c: c.as_float()?,
-                    d: d.as_float()?,
-                    e: e.as_float()?,
-                    f: f.as_float()?,
-                };
-
-                state.gs.ctm = concat(&state.gs.ctm, &ctm);
-            }
+            state.gs.ctm = concat(&state.gs.ctm, &ctm);
+        }
 
-            ("q", []) => {
-                state.stack.push(state.gs.clone());
-            }
-            ("Q", []) => {
-                state.gs = state.stack.pop().ok_or_else(|| {
-                    eyre!("Popped empty graphics stack: unbalanced q/Q operators")
-                })?;
-            }
-            ("scn", [r, g, b]) => {
-                let next = to_color(r, g, b)?;
-                state.gs.non_stroke_color = Color::new([
-                    next.components[0],
-                    next.components[1],
-                    next.components[2],
-                    state.gs.non_stroke_color.components[3],
-                ]);
-            }
-            ("SCN", [r, g, b]) => {
-                let next = to_color(r, g, b)?;
-                state.gs.stroke_color = Color::new([
-                    next.components[0],
-                    next.components[1],
-                    next.components[2],
-                    state.gs.stroke_color.components[3],
-                ]);
-            }
-            ("rg", [r, g, b]) => {
-                let next = to_color(r, g, b)?;
-                state.gs.non_stroke_color = Color::new([
-                    next.components[0],
-                    next.components[1],
-                    next.components[2],
-                    state.gs.non_stroke_color.components[3],
-                ]);
+        ("q", []) => {
+            if state.stack.len() >= cx.settings.max_stack_depth {
+                if state.ignored_saves == 0 {
+                    log::warn!("Ignoring q nested more than {} deep", state.stack.len());
+                }
+                state.ignored_saves += 1;
+                state.max_depth = state.max_depth.max(state.stack.len() + state.ignored_saves);
+                return Ok(());
             }
-            ("RG", [r, g, b]) => {
-                let next = to_color(r, g, b)?;
-                state.gs.stroke_color = Color::new([
-                    next.components[0],
-                    next.components[1],
-                    next.components[2],
-                    state.gs.stroke_color.components[3],
-                ]);
+            state.stack.push(state.gs.clone());
+            state.max_depth = state.max_depth.max(state.stack.len());
+        }
+        ("Q", []) => {
+            if state.ignored_saves > 0 {
+                state.ignored_saves -= 1;
+                return Ok(());
             }
-            ("g", [gray]) => {
-                let g = gray.as_float()?;
-                state.gs.non_stroke_color =
-                    Color::new([g, g, g, state.gs.non_stroke_color.components[3]]);
+            // Unbalanced q/Q is common enough that viewers just ignore the extra Q
+            let Some(restored) = state.stack.pop() else {
+                log::warn!("Ignoring Q with an empty graphics stack");
+                return Ok(());
+            };
+            pop_layers(cx, scene, state, resources, restored.clip_layers)?;
+            state.gs = restored;
+        }
+        ("g" | "rg" | "k" | "cs" | "sc" | "scn" | "G" | "RG" | "K" | "CS" | "SC" | "SCN", _)
+            if cx.uncolored => {}
+        ("scn", [components @ .., Object::Name(name)]) => {
+            let gs = &mut state.gs;
+            gs.fill_pattern = doc
+                .get_dict_in_dict(resources.dict, b"Pattern")
+                .and_then(|patterns| patterns.get(name))
+                .ok()
+                .cloned();
+            // Uncolored patterns take their color from the operands
+            if !components.is_empty() {
+                gs.non_stroke_color = gs
+                    .current_fill_space
+                    .to_color(components, gs.non_stroke_color.components[3])?;
+                gs.fill_zero_ink = color::zero_ink(components);
             }
-            ("G", [gray]) => {
-                let g = gray.as_float()?;
-                state.gs.stroke_color = Color::new([g, g, g, state.gs.stroke_color.components[3]]);
+        }
+        ("SCN", [.., Object::Name(_)]) => {
+            // Pattern strokes are not yet supported
+        }
+        // sc and SC work like scn and SCN but never name a pattern
+        ("sc" | "scn", operands) => {
+            let gs = &mut state.gs;
+            gs.fill_pattern = None;
+            gs.non_stroke_color = gs
+                .current_fill_space
+                .to_color(operands, gs.non_stroke_color.components[3])?;
+            gs.fill_zero_ink = color::zero_ink(operands);
+        }
+        ("SC" | "SCN", operands) => {
+            let gs = &mut state.gs;
+            gs.stroke_color = gs
+                .current_stroke_space
+                .to_color(operands, gs.stroke_color.components[3])?;
+            gs.stroke_zero_ink = color::zero_ink(operands);
+        }
+        ("g" | "rg" | "k", operands) => {
+            let gs = &mut state.gs;
+            gs.fill_pattern = None;
+            gs.current_fill_space = device_space(o);
+            gs.non_stroke_color = gs
+                .current_fill_space
+                .to_color(operands, gs.non_stroke_color.components[3])?;
+            gs.fill_zero_ink = color::zero_ink(operands);
+        }
+        ("G" | "RG" | "K", operands) => {
+            let gs = &mut state.gs;
+            gs.current_stroke_space = device_space(o);
+            gs.stroke_color = gs
+                .current_stroke_space
+                .to_color(operands, gs.stroke_color.components[3])?;
+            gs.stroke_zero_ink = color::zero_ink(operands);
+        }
+        ("cs", [Object::Name(name)]) => {
+            let gs = &mut state.gs;
+            gs.fill_pattern = None;
+            gs.current_fill_space = color::resolve(doc, resources.dict, name).unwrap_or_else(|e| {
+                log::warn!("Unsupported color space: {e}");
+                ColorSpace::default()
+            });
+            gs.non_stroke_color = gs
+                .current_fill_space
+                .initial_color(gs.non_stroke_color.components[3]);
+            // CMYK starts out black, which is 0 in every other ink
+            gs.fill_zero_ink = matches!(gs.current_fill_space, ColorSpace::DeviceCMYK);
+        }
+        ("CS", [Object::Name(name)]) => {
+            let gs = &mut state.gs;
+            gs.current_stroke_space =
+                color::resolve(doc, resources.dict, name).unwrap_or_else(|e| {
+                    log::warn!("Unsupported color space: {e}");
+                    ColorSpace::default()
+                });
+            gs.stroke_color = gs
+                .current_stroke_space
+                .initial_color(gs.stroke_color.components[3]);
+            gs.stroke_zero_ink = matches!(gs.current_stroke_space, ColorSpace::DeviceCMYK);
+        }
+        ("ri", [Object::Name(_name)]) => {}
+        ("Do", [Object::Name(name)]) => {
+            let xobject = doc
+                .get_dict_in_dict(resources.dict, b"XObject")?
+                .get(name)?;
+            let dict = &doc.dereference(xobject)?.1.as_stream()?.dict;
+            if let Ok(oc) = dict.get(b"OC")
+                && !cx.optional.visible(doc, oc)
+            {
+                return Ok(());
             }
-            ("cs", [Object::Name(_name)]) => {}
-            ("CS", [Object::Name(_name)]) => {}
-            ("ri", [Object::Name(_name)]) => {}
-            ("Do", [Object::Name(_name)]) => {
-                // XObject painting not yet supported (images, forms, etc.)
+            match dict.get(b"Subtype")?.as_name()? {
+                b"Image" => {
+                    let fill = state.gs.paint(state.gs.non_stroke_color);
+                    match xobject::decode_image(doc, xobject, fill) {
+                        Ok(image) => draw_image(scene, &image, &state.gs, scale),
+                        Err(e) => log::warn!("Skipping image: {e:#}"),
+                    }
+                }
+                b"Form" => draw_form(cx, scene, state.gs.clone(), resources, xobject)?,
+                other => {
+                    log::warn!("Unsupported XObject: {:?}", String::from_utf8_lossy(other));
+                }
             }
-            ("BDC", [_, _]) => {}
-            ("EMC", []) => {}
-            ("m", [x, y]) => {
-                let xy = transform(&state, &x, y)?;
-                state.gs.path.move_to((xy.x as f64, xy.y as f64));
-                state.gs.current_point = xy;
+        }
+        ("sh", [Object::Name(name)]) => {
+            let shading = doc
+                .get_dict_in_dict(resources.dict, b"Shading")?
+                .get(name)?;
+            let transform = scale.affine(&state.gs.ctm);
+            if transform.determinant().abs() <= f64::EPSILON {
+                return Ok(());
             }
-            ("l", [x, y]) => {
-                let xy = transform(&state, &x, y)?;
-                state.gs.path.line_to((xy.x as f64, xy.y as f64));
-                state.gs.current_point = xy;
+            let bounds = transform.inverse().transform_rect_bbox(scale.bounds());
+            let gs = &state.gs;
+            let alpha = gs.non_stroke_color.components[3];
+            let paint = |color: Color| gs.paint(color.with_alpha(alpha));
+            if let Err(e) = get::<shading::Shading>(doc, shading)
+                .and_then(|s| s.draw(scene, transform, bounds, gs.flatness, paint))
+            {
+                log::warn!("Unsupported shading: {e}");
             }
-            ("v", [x2, y2, x3, y3]) => {
-                let xy1 = state.gs.current_point;
-                let xy2 = transform(&state, &x2, &y2)?;
-                let xy3 = transform(&state, &x3, &y3)?;
-                state.gs.path.curve_to(
-                    (xy1.x as f64, xy1.y as f64),
-                    (xy2.x as f64, xy2.y as f64),
-                    (xy3.x as f64, xy3.y as f64),
-                );
-                state.gs.current_point = xy3;
+        }
+        ("BI", [Object::Stream(image)]) => {
+            // Inline images may name a color space from the resources
+            let mut image = image.clone();
+            if let Ok(Object::Name(name)) = image.dict.get(b"ColorSpace")
+                && let Ok(space) = doc
+                    .get_dict_in_dict(resources.dict, b"ColorSpace")
+                    .and_then(|spaces| spaces.get(name))
+            {
+                image.dict.set("ColorSpace", space.clone());
             }
-            ("y", [x2, y2, x3, y3]) => {
-                let xy1 = state.gs.current_point;
-                let xy2 = transform(&state, &x2, &y2)?;
-                let xy3 = transform(&state, &x3, &y3)?;
-                state.gs.path.curve_to(
-                    (xy1.x as f64, xy1.y as f64),
-                    (xy2.x as f64, xy2.y as f64),
-                    (xy3.x as f64, xy3.y as f64),
-                );
-                state.gs.current_point = xy3;
+            let fill = state.gs.paint(state.gs.non_stroke_color);
+            match xobject::decode_image(doc, &Object::Stream(image), fill) {
+                Ok(image) => draw_image(scene, &image, &state.gs, scale),
+                Err(e) => log::warn!("Skipping inline image: {e:#}"),
             }
-            ("c", [x1, y1, x2, y2, x3, y3]) => {
-                let xy1 = transform(&state, &x1, &y1)?;
-                let xy2 = transform(&state, &x2, &y2)?;
-                let xy3 = transform(&state, &x3, &y3)?;
-                state.gs.path.curve_to(
-                    (xy1.x as f64, xy1.y as f64),
-                    (xy2.x as f64, xy2.y as f64),
-                    (xy3.x as f64, xy3.y as f64),
-                );
-                state.gs.current_point = xy3;
+        }
+        ("BMC", [_]) => state.marked_content.push(false),
+        ("BDC", [Object::Name(tag), properties]) => {
+            let properties = match properties {
+                Object::Name(name) => doc
+                    .get_dict_in_dict(resources.dict, b"Properties")
+                    .and_then(|all| all.get(name))
+                    .ok(),
+                properties => Some(properties),
+            };
+            let hidden = tag == b"OC" && properties.is_some_and(|oc| !cx.optional.visible(doc, oc));
+            state.marked_content.push(hidden);
+        }
+        ("EMC", []) => {
+            state.marked_content.pop();
+        }
+        ("m", [x, y]) => {
+            let xy = transform(state, x, y)?;
+            state.gs.path.move_to((xy.x as f64, xy.y as f64));
+            state.gs.current_point = xy;
+            state.gs.subpath_start = xy;
+        }
+        ("l", [x, y]) => {
+            let xy = transform(state, x, y)?;
+            state.gs.path.line_to((xy.x as f64, xy.y as f64));
+            state.gs.current_point = xy;
+        }
+        ("v", [x2, y2, x3, y3]) => {
+            let xy1 = state.gs.current_point;
+            let xy2 = transform(state, x2, y2)?;
+            let xy3 = transform(state, x3, y3)?;
+            state.gs.path.curve_to(
+                (xy1.x as f64, xy1.y as f64),
+                (xy2.x as f64, xy2.y as f64),
+                (xy3.x as f64, xy3.y as f64),
+            );
+            state.gs.current_point = xy3;
+        }
+        ("y", [x1, y1, x3, y3]) => {
+            let xy1 = transform(state, x1, y1)?;
+            let xy3 = transform(state, x3, y3)?;
+            state.gs.path.curve_to(
+                (xy1.x as f64, xy1.y as f64),
+                (xy3.x as f64, xy3.y as f64),
+                (xy3.x as f64, xy3.y as f64),
+            );
+            state.gs.current_point = xy3;
+        }
+        ("c", [x1, y1, x2, y2, x3, y3]) => {
+            let xy1 = transform(state, x1, y1)?;
+            let xy2 = transform(state, x2, y2)?;
+            let xy3 = transform(state, x3, y3)?;
+            state.gs.path.curve_to(
+                (xy1.x as f64, xy1.y as f64),
+                (xy2.x as f64, xy2.y as f64),
+                (xy3.x as f64, xy3.y as f64),
+            );
+            state.gs.current_point = xy3;
+        }
+        ("re", [x, y, w, h]) => {
+            let (x, y, w, h) = (x.as_float()?, y.as_float()?, w.as_float()?, h.as_float()?);
+            // Transform every corner, since the CTM may rotate or shear the rectangle
+            let corners = [(x, y), (x + w, y), (x + w, y + h), (x, y + h)]
+                .map(|(x, y)| transform_from(&Coord { x, y }, &state.gs.ctm, scale));
+            let path = &mut state.gs.path;
+            path.move_to((corners[0].x as f64, corners[0].y as f64));
+            for corner in &corners[1..] {
+                path.line_to((corner.x as f64, corner.y as f64));
             }
-            ("re", [xo, yo, wo, ho]) => {
-                let x = xo.as_float()?;
-                let y = yo.as_float()?;
-                let w = wo.as_float()?;
-                let h = ho.as_float()?;
-                let xy0 = transform_from(&Coord { x, y }, &state.gs.ctm, &scale);
-                let xy1 = transform_from(&Coord { x: x + w, y: y + h }, &state.gs.ctm, &scale);
-                let wh = Coord {
-                    x: xy1.x - xy0.x,
-                    y: xy1.y - xy0.y,
-                };
-                use kurbo::{Rect, Shape};
-                let rect = Rect::new(
-                    xy0.x as f64,
-                    xy0.y as f64,
-                    (xy0.x + wh.x) as f64,
-                    (xy0.y + wh.y) as f64,
-                );
-                state.gs.path.extend(rect.path_elements(0.1));
+            path.close_path();
+            // re is a closed subpath, so it leaves the current point where it started
+            state.gs.current_point = corners[0];
+            state.gs.subpath_start = corners[0];
+        }
+        ("h", []) => {
+            state.gs.path.close_path();
+            state.gs.current_point = state.gs.subpath_start;
+        }
+        ("i", [flatness]) => {
+            state.gs.set_flatness(flatness.as_float()?);
+        }
+        ("W" | "W*", []) => {
+            state.gs.clip_pending = true;
+        }
+        ("n", []) => {
+            end_path(scene, &mut state.gs);
+        }
+        ("w", [lw]) => {
+            state.gs.line_width = lw.as_float()?;
+        }
+        ("J", [cap]) => {
+            state.gs.line_cap = match cap.as_i64()? {
+                1 => Cap::Round,
+                2 => Cap::Square,
+                _ => Cap::Butt,
+            };
+        }
+        ("j", [join]) => {
+            state.gs.line_join = match join.as_i64()? {
+                1 => Join::Round,
+                2 => Join::Bevel,
+                _ => Join::Miter,
+            };
+        }
+        ("M", [limit]) => {
+            state.gs.miter_limit = limit.as_float()? as f64;
+        }
+        ("d", [Object::Array(pattern), phase]) => {
+            state.gs.dash_pattern = pattern
+                .iter()
+                .map(|d| Ok(d.as_float()? as f64))
+                .collect::<Result<_>>()?;
+            // A pattern of all zeros would never advance, so treat it as solid
+            if state.gs.dash_pattern.iter().all(|d| *d <= 0.) {
+                state.gs.dash_pattern.clear();
             }
-            ("h", []) => {
+            state.gs.dash_phase = phase.as_float()? as f64;
+        }
+        ("f" | "F" | "f*" | "S" | "s" | "B" | "B*" | "b" | "b*", []) => {
+            if matches!(o, "s" | "b" | "b*") {
                 state.gs.path.close_path();
             }
-            ("W", []) => {}
-            ("W*", []) => {}
-            ("n", []) => {
-                state.gs.path = BezPath::new();
-            }
-            ("f" | "f*", []) => {
-                let fill_rule = if o == "f" {
-                    Fill::NonZero
-                } else {
-                    Fill::EvenOdd
-                };
-                use kurbo::Affine;
-                scene.fill(
-                    fill_rule,
-                    Affine::IDENTITY,
-                    state.gs.non_stroke_color,
-                    None,
-                    &state.gs.path,
-                );
-                state.gs.path = BezPath::new();
-            }
-            ("w", [lw]) => {
-                state.gs.line_width = lw.as_float()?;
-            }
-            ("S", []) => {
-                use kurbo::Affine;
-                use peniko::kurbo::Stroke;
-                let stroke = Stroke::new(state.gs.line_width as f64 * scale.scale as f64);
-                scene.stroke(
-                    &stroke,
-                    Affine::IDENTITY,
-                    state.gs.stroke_color,
-                    None,
-                    &state.gs.path,
-                );
-                state.gs.path = BezPath::new();
+            let rule = match o {
+                "f" | "F" | "B" | "b" => Some(Fill::NonZero),
+                "f*" | "B*" | "b*" => Some(Fill::EvenOdd),
+                _ => None,
+            };
+            match (rule, &state.gs.fill_pattern) {
+                (Some(_), Some(pattern)) => {
+                    if let Err(e) = pattern::fill(cx, scene, state, resources, pattern) {
+                        log::warn!("Unsupported pattern: {e}");
+                    }
+                }
+                (Some(rule), None) => fill_path(scene, &state.gs, rule, scale),
+                (None, _) => {}
             }
-            ("B", []) => {
-                use kurbo::Affine;
-                use peniko::kurbo::Stroke;
-                scene.fill(
-                    Fill::NonZero,
-                    Affine::IDENTITY,
-                    state.gs.non_stroke_color,
-                    None,
-                    &state.gs.path,
-                );
-                let stroke = Stroke::new(state.gs.line_width as f64 * scale.scale as f64);
-                scene.stroke(
-                    &stroke,
-                    Affine::IDENTITY,
-                    state.gs.stroke_color,
-                    None,
-                    &state.gs.path,
-                );
-                state.gs.path = BezPath::new();
+            if !matches!(o, "f" | "F" | "f*") {
+                stroke_path(scene, &state.gs, scale);
             }
-            ("gs", [Object::Name(name)]) => {
-                if let Some(gstate_dict) = ext_gstate_map.get(name) {
-                    if let Some(ca) = gstate_dict.get(b"ca").and_then(|ca| ca.as_float()).ok() {
-                        let c = state.gs.non_stroke_color;
-                        state.gs.non_stroke_color =
-                            Color::new([c.components[0], c.components[1], c.components[2], ca]);
-                    }
-                    if let Some(ca) = gstate_dict.get(b"CA").and_then(|ca| ca.as_float()).ok() {
-                        let c = state.gs.stroke_color;
-                        state.gs.stroke_color =
-                            Color::new([c.components[0], c.components[1], c.components[2], ca]);
-                    }
+            end_path(scene, &mut state.gs);
+        }
+        ("gs", [Object::Name(name)]) => {
+            if let Some(gstate_dict) = resources.ext_gstate.get(name) {
+                if let Ok(mask) = gstate_dict.get(b"SMask") {
+                    set_soft_mask(cx, scene, state, resources, mask)?;
+                }
+                if let Ok(flatness) = gstate_dict.get(b"FL").and_then(Object::as_float) {
+                    state.gs.set_flatness(flatness);
+                }
+                if let Ok(op) = gstate_dict.get(b"OP").and_then(Object::as_bool) {
+                    state.gs.stroke_overprint = op;
+                    // OP stands in for op when there isn't one
+                    state.gs.fill_overprint = op;
+                }
+                if let Ok(op) = gstate_dict.get(b"op").and_then(Object::as_bool) {
+                    state.gs.fill_overprint = op;
+                }
+                if let Ok(mode) = gstate_dict.get(b"OPM").and_then(Object::as_i64) {
+                    state.gs.nonzero_overprint = mode == 1;
+                }
+                if let Ok(mode) = gstate_dict.get(b"BM") {
+                    set_blend_mode(cx, scene, state, resources, blend_mode(doc, mode))?;
+                }
+                if let Ok(ca) = gstate_dict.get(b"ca").and_then(|ca| ca.as_float()) {
+                    let c = state.gs.non_stroke_color;
+                    state.gs.non_stroke_color =
+                        Color::new([c.components[0], c.components[1], c.components[2], ca]);
+                }
+                if let Ok(ca) = gstate_dict.get(b"CA").and_then(|ca| ca.as_float()) {
+                    let c = state.gs.stroke_color;
+                    state.gs.stroke_color =
+                        Color::new([c.components[0], c.components[1], c.components[2], ca]);
                 }
             }
+        }
 
-            (_o, _a) => {
-                eprintln!("MISSING: {:?} {:?}", _o, _a);
-            }
+        // Read by draw_char_proc before the glyph runs
+        ("d0", [_, _]) | ("d1", [_, _, _, _, _, _]) => {}
+
+        (o, a) => {
+            log::debug!("Unhandled operator: {:?} {:?}", o, a);
+        }
+    }
+    Ok(())
+}
+
+// Pops layers down to `to`, applying any soft masks on the way
+fn pop_layers(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    to: usize,
+) -> Result<()> {
+    while state.gs.clip_layers > to {
+        state.gs.clip_layers -= 1;
+        if state
+            .masks
+            .last()
+            .is_some_and(|(layer, _)| *layer == state.gs.clip_layers)
+        {
+            let (_, mask) = state.masks.pop().unwrap();
+            draw_soft_mask(cx, scene, resources, &mask)?;
+        }
+        scene.pop_layer();
+    }
+    Ok(())
+}
+
+// Draws a form XObject; `gs` is the state it starts from
+fn draw_form(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    gs: GraphicsState,
+    resources: &Resources,
+    xobject: &Object,
+) -> Result<()> {
+    let doc = cx.doc;
+    if cx.depth >= MAX_FORM_DEPTH {
+        bail!("Form XObjects nested more than {MAX_FORM_DEPTH} deep");
+    }
+    let dict = &doc.dereference(xobject)?.1.as_stream()?.dict;
+    let raw: Vec<u8> = get(doc, xobject)?;
+    let content = content::decode_content(&raw)?;
+    let form_resources;
+    let form_resources = match doc.get_dict_in_dict(dict, b"Resources") {
+        Ok(form_dict) => {
+            form_resources = Resources::new(doc, form_dict, cx.fonts);
+            &form_resources
         }
+        Err(_) => resources,
+    };
+    let matrix = match dict.get(b"Matrix") {
+        Ok(matrix) => get(doc, matrix)?,
+        Err(_) => CTM::default(),
+    };
+
+    let mut form_state = State::new(GraphicsState {
+        ctm: concat(&gs.ctm, &matrix),
+        path: BezPath::new(),
+        clip_layers: 0,
+        blend_layer: None,
+        ..gs
+    });
+
+    let bbox = match &get::<Vec<f32>>(doc, dict.get(b"BBox")?)?[..] {
+        [x0, y0, x1, y1] => Rect::new(*x0 as f64, *y0 as f64, *x1 as f64, *y1 as f64),
+        other => bail!("Expected [x0 y0 x1 y1] BBox, got {:?}", other),
+    };
+    scene.push_layer(
+        Mix::Clip.into(),
+        1.0,
+        cx.scale.affine(&form_state.gs.ctm),
+        &bbox.to_path(0.1),
+    );
+    let result = draw_content(
+        Context {
+            depth: cx.depth + 1,
+            ..cx
+        },
+        scene,
+        &mut form_state,
+        form_resources,
+        &content.operations,
+    );
+    scene.pop_layer();
+    result
+}
+
+fn show_text(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    glyphs: &[Object],
+) {
+    let font = state.gs.text_state.font.clone();
+    let glyph_resources = match font.as_deref().map(|f| &f.font) {
+        Some(FontProgram::Type3(Type3Font {
+            resources: Some(dict),
+            ..
+        })) => Some(Resources::new(cx.doc, dict, cx.fonts)),
+        _ => None,
+    };
+    let glyph_resources = glyph_resources.as_ref().unwrap_or(resources);
+    // Text that's only stroked overprints like a stroke, and otherwise like a fill
+    let stroked = matches!(state.gs.text_state.render_mode, 1 | 5);
+    let overprint = state.gs.overprints(stroked);
+    let result = overprinted(scene, overprint, cx.scale, |scene| {
+        text::draw_text(
+            cx.scale,
+            scene,
+            &mut state.gs,
+            glyphs,
+            cx.settings,
+            &mut |scene, gs, proc| draw_char_proc(cx, scene, gs.clone(), glyph_resources, proc),
+        )
+    });
+    // Text whose font can't be shown is skipped, like shadings and patterns
+    // that can't be drawn, rather than blanking the rest of the page
+    if let Err(e) = result {
+        log::warn!("Skipping text: {e:#}");
+    }
+}
+
+// Runs a Type3 glyph procedure, with `gs` mapping glyph space to the device,
+// returning the horizontal advance its d0 or d1 sets in glyph space
+fn draw_char_proc(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    gs: GraphicsState,
+    resources: &Resources,
+    proc: &Object,
+) -> Result<Option<f32>> {
+    if cx.depth >= MAX_FORM_DEPTH {
+        bail!("Type3 glyphs nested more than {MAX_FORM_DEPTH} deep");
     }
+    let raw: Vec<u8> = get(cx.doc, proc)?;
+    let content = content::decode_content(&raw)?;
+    let (advance, uncolored) = match content.operations.first() {
+        Some(Operation { operator, operands }) if operator == "d0" || operator == "d1" => (
+            operands.first().and_then(|wx| wx.as_float().ok()),
+            operator == "d1",
+        ),
+        _ => (None, false),
+    };
+    let mut glyph_state = State::new(GraphicsState {
+        path: BezPath::new(),
+        clip_layers: 0,
+        blend_layer: None,
+        ..gs
+    });
+    draw_content(
+        Context {
+            depth: cx.depth + 1,
+            uncolored: cx.uncolored || uncolored,
+            ..cx
+        },
+        scene,
+        &mut glyph_state,
+        resources,
+        &content.operations,
+    )?;
+    Ok(advance)
+}
+
+// Starts masking what's drawn next, until the graphics state is restored
+fn set_soft_mask(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    mask: &Object,
+) -> Result<()> {
+    let doc = cx.doc;
+    // A new mask replaces the current one, which can only be finished early
+    // if nothing has been layered on top of it since
+    let top = state.masks.last().map(|(layer, _)| *layer);
+    if let Some(layer) = top.filter(|layer| layer + 1 == state.gs.clip_layers) {
+        pop_layers(cx, scene, state, resources, layer)?;
+    }
+    let mask = match doc.dereference(mask)?.1 {
+        Object::Name(name) if name == b"None" => return Ok(()),
+        mask => mask.as_dict()?,
+    };
+
+    let luminosity = mask.get(b"S")?.as_name()? == b"Luminosity";
+    let group = mask.get(b"G")?.clone();
+    let backdrop = match mask.get(b"BC") {
+        Ok(backdrop) if luminosity => {
+            let components: Vec<Object> = doc.dereference(backdrop)?.1.as_array()?.clone();
+            ColorSpace::default().to_color(&components, 1.)?
+        }
+        _ => Color::BLACK,
+    };
+
+    scene.push_layer(
+        Mix::Normal.into(),
+        1.0,
+        Affine::IDENTITY,
+        &cx.scale.bounds().to_path(0.1),
+    );
+    state.masks.push((
+        state.gs.clip_layers,
+        SoftMask {
+            group,
+            ctm: state.gs.ctm.clone(),
+            luminosity,
+            backdrop,
+        },
+    ));
+    state.gs.clip_layers += 1;
+    Ok(())
+}
+
+// Reads a /BM entry, which may be an array of modes to pick the first known one from
+fn blend_mode(doc: &Document, mode: &Object) -> Mix {
+    let names = match doc.dereference(mode) {
+        Ok((_, Object::Array(modes))) => modes.clone(),
+        Ok((_, mode)) => vec![mode.clone()],
+        Err(_) => Vec::new(),
+    };
+    names
+        .iter()
+        .filter_map(|name| {
+            Some(match name.as_name().ok()? {
+                b"Normal" | b"Compatible" => Mix::Normal,
+                b"Multiply" => Mix::Multiply,
+                b"Screen" => Mix::Screen,
+                b"Overlay" => Mix::Overlay,
+                b"Darken" => Mix::Darken,
+                b"Lighten" => Mix::Lighten,
+                b"ColorDodge" => Mix::ColorDodge,
+                b"ColorBurn" => Mix::ColorBurn,
+                b"HardLight" => Mix::HardLight,
+                b"SoftLight" => Mix::SoftLight,
+                b"Difference" => Mix::Difference,
+                b"Exclusion" => Mix::Exclusion,
+                b"Hue" => Mix::Hue,
+                b"Saturation" => Mix::Saturation,
+                b"Color" => Mix::Color,
+                b"Luminosity" => Mix::Luminosity,
+                _ => return None,
+            })
+        })
+        .next()
+        .unwrap_or(Mix::Normal)
+}
 
+// Blends what's drawn next onto the page with `mode`, until the graphics
+// state is restored. The drawing is blended as one group rather than
+// object by object.
+fn set_blend_mode(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &mut State,
+    resources: &Resources,
+    mode: Mix,
+) -> Result<()> {
+    if mode == state.gs.blend_mode {
+        return Ok(());
+    }
+    if let Some(layer) = state
+        .gs
+        .blend_layer
+        .filter(|layer| layer + 1 == state.gs.clip_layers)
+    {
+        pop_layers(cx, scene, state, resources, layer)?;
+        state.gs.blend_layer = None;
+    }
+    state.gs.blend_mode = mode;
+    if mode != Mix::Normal {
+        scene.push_layer(
+            mode.into(),
+            1.0,
+            Affine::IDENTITY,
+            &cx.scale.bounds().to_path(0.1),
+        );
+        state.gs.blend_layer = Some(state.gs.clip_layers);
+        state.gs.clip_layers += 1;
+    }
     Ok(())
 }
+
+// Keeps only as much of the layer underneath as the mask covers
+fn draw_soft_mask(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    resources: &Resources,
+    mask: &SoftMask,
+) -> Result<()> {
+    let bounds = cx.scale.bounds().to_path(0.1);
+    scene.push_layer(
+        BlendMode::new(Mix::Normal, Compose::DestIn),
+        1.0,
+        Affine::IDENTITY,
+        &bounds,
+    );
+    let gs = GraphicsState {
+        ctm: mask.ctm.clone(),
+        luminosity_mask: mask.luminosity,
+        ..Default::default()
+    };
+    if mask.luminosity {
+        scene.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            gs.paint(mask.backdrop).into(),
+            &bounds,
+        );
+    }
+    let result = draw_form(cx, scene, gs, resources, &mask.group);
+    scene.pop_layer();
+    result
+}
+
+fn draw_image(
+    scene: &mut dyn Canvas,
+    image: &peniko::Image,
+    gs: &GraphicsState,
+    scale: &DeviceScale,
+) {
+    // Image space maps the unit square with its origin at the top left
+    let unit = Affine::new([
+        1. / image.width as f64,
+        0.,
+        0.,
+        -1. / image.height as f64,
+        0.,
+        1.,
+    ]);
+    scene.draw_image(image, scale.affine(&gs.ctm) * unit);
+}
+
+// Overprinting is approximated by multiplying paint onto what's beneath,
+// which for CMYK is exact where the two use different inks but darker than
+// it should be where they share one. There are no separations to keep
+// apart, so spot colors and images always knock out.
+fn overprinted<R>(
+    scene: &mut dyn Canvas,
+    overprint: bool,
+    scale: &DeviceScale,
+    paint: impl FnOnce(&mut dyn Canvas) -> R,
+) -> R {
+    if !overprint {
+        return paint(scene);
+    }
+    scene.push_layer(
+        Mix::Multiply.into(),
+        1.0,
+        Affine::IDENTITY,
+        &scale.bounds().to_path(0.1),
+    );
+    let result = paint(scene);
+    scene.pop_layer();
+    result
+}
+
+fn fill_path(scene: &mut dyn Canvas, gs: &GraphicsState, rule: Fill, scale: &DeviceScale) {
+    overprinted(scene, gs.overprints(false), scale, |scene| {
+        scene.fill(
+            rule,
+            Affine::IDENTITY,
+            gs.paint(gs.non_stroke_color).into(),
+            &gs.path,
+        )
+    });
+}
+
+fn stroke_path(scene: &mut dyn Canvas, gs: &GraphicsState, scale: &DeviceScale) {
+    overprinted(scene, gs.overprints(true), scale, |scene| {
+        stroke_in(scene, gs, scale)
+    });
+}
+
+fn stroke_in(scene: &mut dyn Canvas, gs: &GraphicsState, scale: &DeviceScale) {
+    let to_device = scale.affine(&gs.ctm);
+    let stroke = Stroke::new(gs.line_width as f64)
+        .with_caps(gs.line_cap)
+        .with_join(gs.line_join)
+        .with_miter_limit(gs.miter_limit)
+        .with_dashes(gs.dash_phase, gs.dash_pattern.iter().copied());
+
+    let determinant = to_device.determinant().abs();
+    // A width of 0 asks for the thinnest line that can be drawn
+    let hairline = gs.line_width <= 0.;
+    if determinant > f64::EPSILON && !hairline {
+        // Paths are kept in device space, so map back to user space and stroke
+        // there, letting the CTM scale and skew the pen
+        let path = to_device.inverse() * &gs.path;
+        scene.stroke(&stroke, to_device, gs.paint(gs.stroke_color).into(), &path);
+    } else {
+        // Hairlines are a device pixel wide however the CTM shapes the pen,
+        // and a degenerate CTM can't be inverted, so both get a uniform pen
+        let device = match determinant > f64::EPSILON {
+            true => determinant.sqrt(),
+            false => scale.scale as f64,
+        };
+        let stroke = Stroke {
+            width: if hairline { 1. } else { stroke.width * device },
+            dash_pattern: stroke.dash_pattern.iter().map(|d| d * device).collect(),
+            dash_offset: stroke.dash_offset * device,
+            ..stroke
+        };
+        scene.stroke(
+            &stroke,
+            Affine::IDENTITY,
+            gs.paint(gs.stroke_color).into(),
+            &gs.path,
+        );
+    }
+}
+
+fn end_path(scene: &mut dyn Canvas, gs: &mut GraphicsState) {
+    if gs.clip_pending {
+        // vello clip layers always use the nonzero rule, so W* is approximated
+        scene.push_layer(Mix::Clip.into(), 1.0, Affine::IDENTITY, &gs.path);
+        gs.clip_layers += 1;
+        gs.clip_pending = false;
+    }
+    gs.path = BezPath::new();
+}
+
+#[cfg(test)]
+mod tests {
+    use kurbo::PathEl;
+    use lopdf::{Stream, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    // Runs `content` on a blank 100 x 100 point page, returning the state
+    // it leaves behind
+    fn interpret(content: &str) -> State {
+        interpret_with(content, &RenderSettings::default())
+    }
+
+    fn interpret_with(content: &str, settings: &RenderSettings) -> State {
+        let doc = page(Dictionary::new(), "");
+        let dict = Dictionary::new();
+        let fonts = FontCache::new(settings);
+        let optional = OptionalContent::default();
+        let cx = Context {
+            doc: &doc,
+            fonts: &fonts,
+            optional: &optional,
+            scale: &DeviceScale {
+                width: 100,
+                height: 100,
+                scale: 1.,
+            },
+            settings,
+            depth: 0,
+            uncolored: false,
+        };
+        let resources = Resources::new(&doc, &dict, &fonts);
+        let mut state = State::default();
+        for op in content::decode_content(content.as_bytes())
+            .unwrap()
+            .operations
+        {
+            draw_operation(cx, &mut Scene::new(), &mut state, &resources, &op).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn td_moves_from_the_start_of_the_line() {
+        let doc = text_page("BT /F1 10 Tf 10 70 Td (A) Tj 30 -20 Td (A) Tj ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 75), BLACK);
+        // Not after the first glyph, but 30 points right of where its line started
+        assert_eq!(pixel(&image, 25, 55), WHITE);
+        assert_eq!(pixel(&image, 45, 55), BLACK);
+    }
+
+    #[test]
+    fn td_with_capital_d_also_sets_the_leading() {
+        let doc = text_page("BT /F1 10 Tf 10 70 Td (A) Tj 20 -20 TD (A) Tj T* (A) Tj ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 35, 55), BLACK);
+        assert_eq!(pixel(&image, 35, 35), BLACK);
+        assert_eq!(pixel(&image, 35, 25), WHITE);
+    }
+
+    #[test]
+    fn t_star_moves_down_by_the_leading() {
+        let doc = text_page("BT /F1 10 Tf 15 TL 10 70 Td (A) Tj T* (A) Tj ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 60), BLACK);
+        assert_eq!(pixel(&image, 15, 52), WHITE);
+    }
+
+    #[test]
+    fn tj_shows_a_string() {
+        let doc = text_page("BT /F1 10 Tf 10 10 Td (AA) Tj ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 15), BLACK);
+        assert_eq!(pixel(&image, 25, 15), BLACK);
+        assert_eq!(pixel(&image, 35, 15), WHITE);
+    }
+
+    #[test]
+    fn text_in_a_font_that_cant_be_loaded_doesnt_blank_the_page() {
+        let doc = page(
+            dictionary! {
+                "Font" => dictionary! {
+                    "F1" => dictionary! { "Type" => "Font", "Subtype" => "Unknown" },
+                },
+            },
+            "0 0 1 rg 10 10 20 20 re f BT /F1 10 Tf 10 50 Td (A) Tj ET 50 10 20 20 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 15), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 55, 15), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn quote_moves_to_the_next_line_first() {
+        let doc = text_page("BT /F1 10 Tf 15 TL 10 70 Td (A) Tj (A) ' ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 75), BLACK);
+        assert_eq!(pixel(&image, 15, 60), BLACK);
+        assert_eq!(pixel(&image, 25, 60), WHITE);
+    }
+
+    #[test]
+    fn double_quote_sets_the_spacing_too() {
+        let doc = text_page("BT /F1 10 Tf 15 TL 10 70 Td 0 10 (AA) \" ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 15, 60), BLACK);
+        assert_eq!(pixel(&image, 25, 60), WHITE);
+        assert_eq!(pixel(&image, 35, 60), BLACK);
+    }
+
+    #[test]
+    fn rg_sets_the_fill_and_rg_with_capitals_the_stroke() {
+        let doc = page(
+            Dictionary::new(),
+            "1 0 0 rg 0 0 1 RG 10 w 10 10 30 30 re f 60 20 m 90 20 l S",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 75, 20), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn g_sets_gray_fills_and_strokes() {
+        let doc = page(
+            Dictionary::new(),
+            "0.5 g 0.2 G 10 w 10 10 30 30 re f 60 20 m 90 20 l S",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), [128, 128, 128, 255]);
+        assert_eq!(pixel(&image, 75, 20), [51, 51, 51, 255]);
+    }
+
+    #[test]
+    fn k_sets_cmyk_fills_and_strokes() {
+        let doc = page(
+            Dictionary::new(),
+            "0 1 1 0 k 0 0 0 1 K 10 w 10 10 30 30 re f 60 20 m 90 20 l S",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 75, 20), BLACK);
+    }
+
+    #[test]
+    fn cs_picks_the_space_sc_reads_operands_in() {
+        let doc = page(
+            dictionary! { "ColorSpace" => dictionary! { "CS0" => vec![
+                Object::Name(b"CalRGB".to_vec()),
+                dictionary! { "WhitePoint" => vec![0.95.into(), 1.into(), 1.09.into()] }.into(),
+            ] } },
+            "/CS0 cs 0 1 0 scn /DeviceGray CS 0.2 SC 10 w \
+             10 10 30 30 re f 60 20 m 90 20 l S",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 75, 20), [51, 51, 51, 255]);
+    }
+
+    #[test]
+    fn do_draws_an_image_over_the_unit_square() {
+        let mut doc = document();
+        let image = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 2,
+                "Height" => 2,
+                "ColorSpace" => "DeviceRGB",
+                "BitsPerComponent" => 8,
+            },
+            vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 0],
+        ));
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "XObject" => dictionary! { "Im0" => image } } },
+            "q 40 0 0 40 10 10 cm /Im0 Do Q",
+        );
+        let image = render(&doc);
+        // The first row of samples is the top of the image
+        assert_eq!(pixel(&image, 15, 45), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 45, 45), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 15, 15), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 45, 15), [255, 255, 0, 255]);
+        assert_eq!(pixel(&image, 55, 15), WHITE);
+    }
+
+    #[test]
+    fn do_draws_a_form_clipped_to_its_bbox() {
+        let mut doc = document();
+        let form = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), 20.into(), 20.into()],
+                "Matrix" => vec![1.into(), 0.into(), 0.into(), 1.into(), 30.into(), 30.into()],
+            },
+            b"1 0 0 rg 0 0 40 40 re f".to_vec(),
+        ));
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "XObject" => dictionary! { "Fm0" => form } } },
+            "/Fm0 Do 10 10 10 10 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 35, 35), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 55, 55), WHITE);
+        // The form's color doesn't leak out
+        assert_eq!(pixel(&image, 15, 15), BLACK);
+    }
+
+    #[test]
+    fn w_clips_until_the_state_is_restored() {
+        let doc = page(
+            Dictionary::new(),
+            "q 10 10 30 30 re W n 0 0 100 100 re f Q 60 60 20 20 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 50, 25), WHITE);
+        assert_eq!(pixel(&image, 70, 70), BLACK);
+    }
+
+    #[test]
+    fn n_ends_the_path_without_painting_it() {
+        let doc = page(
+            Dictionary::new(),
+            "10 10 30 30 re n 60 60 m 60 80 l 80 80 l f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), WHITE);
+        // The next path starts afresh
+        assert_eq!(pixel(&image, 65, 75), BLACK);
+        assert_eq!(pixel(&image, 35, 65), WHITE);
+    }
+
+    #[test]
+    fn y_repeats_the_end_point_as_the_second_control_point() {
+        let state = interpret("10 10 m 20 80 90 10 y");
+        assert_eq!(
+            state.gs.path.elements(),
+            [
+                PathEl::MoveTo((10., 90.).into()),
+                PathEl::CurveTo((20., 20.).into(), (90., 90.).into(), (90., 90.).into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn b_closes_fills_and_strokes() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let doc = page(
+            Dictionary::new(),
+            "1 0 0 rg 0 0 1 RG 4 w 10 10 m 50 10 l 50 50 l b \
+             60 10 m 90 10 l 90 40 l B",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 40, 20), red);
+        // b strokes the closing edge, while B leaves it open
+        assert_eq!(pixel(&image, 30, 30), blue);
+        assert_eq!(pixel(&image, 78, 26), red);
+    }
+
+    #[test]
+    fn b_star_and_capital_b_star_fill_by_the_even_odd_rule() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let nested = "10 10 80 80 re 30 30 40 40 re";
+        for (op, stroked) in [("b*", true), ("B*", true), ("f*", false), ("b", true)] {
+            let doc = page(
+                Dictionary::new(),
+                &format!("1 0 0 rg 0 0 1 RG 2 w {nested} {op}"),
+            );
+            let image = render(&doc);
+            assert_eq!(pixel(&image, 20, 50), red, "{op}");
+            let center = if op == "b" { red } else { WHITE };
+            assert_eq!(pixel(&image, 50, 50), center, "{op}");
+            let edge = if stroked { blue } else { red };
+            assert_eq!(pixel(&image, 10, 50), edge, "{op}");
+        }
+    }
+
+    #[test]
+    fn d_dashes_strokes() {
+        let doc = page(
+            Dictionary::new(),
+            "4 w [10 10] 0 d 0 70 m 100 70 l S [10 10] 5 d 0 50 m 100 50 l S \
+             [0 0] 0 d 0 30 m 100 30 l S",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 5, 70), BLACK);
+        assert_eq!(pixel(&image, 15, 70), WHITE);
+        assert_eq!(pixel(&image, 25, 70), BLACK);
+        // The phase starts that far into the pattern
+        assert_eq!(pixel(&image, 2, 50), BLACK);
+        assert_eq!(pixel(&image, 7, 50), WHITE);
+        // A pattern of zeros would never advance, so it's solid
+        assert_eq!(pixel(&image, 15, 30), BLACK);
+    }
+
+    #[test]
+    fn j_and_capital_j_set_joins_and_caps() {
+        let render_stroke = |style: &str| {
+            render(&page(
+                Dictionary::new(),
+                &format!("10 w {style} 20 80 m 80 80 l S 20 20 m 50 50 l 80 20 l S"),
+            ))
+        };
+        let butt = render_stroke("0 J 0 j");
+        let square = render_stroke("2 J 2 j");
+        assert_eq!(pixel(&butt, 17, 80), WHITE);
+        assert_eq!(pixel(&square, 17, 80), BLACK);
+        // A miter reaches past the corner that a bevel cuts off
+        assert_eq!(pixel(&butt, 50, 55), BLACK);
+        assert_eq!(pixel(&square, 50, 55), WHITE);
+    }
+
+    #[test]
+    fn tr_sets_text_render_modes() {
+        // Text over a red page, followed by a blue square when it's clipping
+        let render_text = |mode: u8, font: fn(&mut Document) -> ObjectId| {
+            let clip = if mode >= 4 {
+                "0 0 1 rg 0 0 100 100 re f"
+            } else {
+                ""
+            };
+            render(&font_page(
+                font,
+                &format!(
+                    "1 0 0 rg 0 0 100 100 re f 0 g 4 w \
+                     BT /F1 40 Tf {mode} Tr 20 30 Td (A) Tj ET {clip}"
+                ),
+            ))
+        };
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let fill = render_text(0, truetype_font);
+        assert_eq!(pixel(&fill, 40, 50), BLACK);
+        let stroke = render_text(1, truetype_font);
+        assert_eq!(pixel(&stroke, 40, 50), red);
+        assert_eq!(pixel(&stroke, 20, 50), BLACK);
+        let invisible = render_text(3, truetype_font);
+        assert_eq!(pixel(&invisible, 20, 50), red);
+        assert_eq!(pixel(&invisible, 40, 50), red);
+        // The glyphs clip what follows the text object
+        let clip = render_text(7, truetype_font);
+        assert_eq!(pixel(&clip, 40, 50), blue);
+        assert_eq!(pixel(&clip, 80, 50), red);
+        // Type3 glyphs are painted however their procedures paint them,
+        // unless they're invisible, and don't clip
+        assert_eq!(pixel(&render_text(1, square_font), 40, 50), BLACK);
+        assert_eq!(pixel(&render_text(3, square_font), 40, 50), red);
+        assert_eq!(pixel(&render_text(7, square_font), 80, 50), blue);
+    }
+
+    #[test]
+    fn tc_spaces_characters() {
+        let image = render(&text_page("BT /F1 10 Tf 10 Tc 10 10 Td (AA) Tj ET"));
+        assert_eq!(pixel(&image, 15, 15), BLACK);
+        assert_eq!(pixel(&image, 25, 15), WHITE);
+        assert_eq!(pixel(&image, 35, 15), BLACK);
+    }
+
+    #[test]
+    fn tw_spaces_words() {
+        let image = render(&text_page(
+            "BT /F1 10 Tf 10 Tw 10 10 Td (A A) Tj 0 20 Td (AA) Tj ET",
+        ));
+        // The space is half an em, widened by the word spacing
+        assert_eq!(pixel(&image, 32, 15), WHITE);
+        assert_eq!(pixel(&image, 40, 15), BLACK);
+        // Other characters aren't
+        assert_eq!(pixel(&image, 25, 35), BLACK);
+    }
+
+    #[test]
+    fn tz_scales_text_horizontally() {
+        let image = render(&text_page("BT /F1 20 Tf 50 Tz 10 10 Td (AA) Tj ET"));
+        // Both glyphs and advances are half as wide
+        assert_eq!(pixel(&image, 17, 20), BLACK);
+        assert_eq!(pixel(&image, 28, 20), BLACK);
+        assert_eq!(pixel(&image, 32, 20), WHITE);
+        assert_eq!(pixel(&image, 15, 28), BLACK);
+    }
+
+    #[test]
+    fn ts_raises_text() {
+        let image = render(&text_page(
+            "BT /F1 10 Tf 10 10 Td 5 Ts (A) Tj -5 Ts (A) Tj ET",
+        ));
+        assert_eq!(pixel(&image, 15, 12), WHITE);
+        assert_eq!(pixel(&image, 15, 22), BLACK);
+        assert_eq!(pixel(&image, 25, 7), BLACK);
+        assert_eq!(pixel(&image, 25, 17), WHITE);
+    }
+
+    #[test]
+    fn composite_fonts_take_widths_from_w() {
+        // CIDs 0 and 1 share a width from a range, and CID 2 is given one
+        let font = |doc: &mut Document| {
+            let w = vec![
+                0.into(),
+                1.into(),
+                500.into(),
+                2.into(),
+                vec![1500.into()].into(),
+            ];
+            cid_font(doc, dictionary! { "W" => w })
+        };
+        let image = render(&font_page(
+            font,
+            "BT /F1 20 Tf 10 10 Td <000200010001> Tj ET",
+        ));
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        assert_eq!(pixel(&image, 25, 20), WHITE);
+        assert_eq!(pixel(&image, 45, 20), BLACK);
+        assert_eq!(pixel(&image, 62, 20), BLACK);
+        assert_eq!(pixel(&image, 72, 20), WHITE);
+    }
+
+    #[test]
+    fn composite_fonts_default_to_dw() {
+        let font = |doc: &mut Document| {
+            let w = vec![2.into(), vec![1000.into()].into()];
+            cid_font(doc, dictionary! { "W" => w, "DW" => 250 })
+        };
+        let image = render(&font_page(
+            font,
+            "BT /F1 20 Tf 10 10 Td <00010001> Tj 0 40 Td <00020001> Tj ET",
+        ));
+        assert_eq!(pixel(&image, 32, 20), BLACK);
+        assert_eq!(pixel(&image, 37, 20), WHITE);
+        // CIDs /W lists keep their own widths
+        assert_eq!(pixel(&image, 25, 60), WHITE);
+        assert_eq!(pixel(&image, 35, 60), BLACK);
+    }
+
+    #[test]
+    fn simple_truetype_fonts_draw_their_glyphs() {
+        let image = render(&font_page(
+            truetype_font,
+            "BT /F1 20 Tf 10 10 Td (BA) Tj ET",
+        ));
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        // B is half an em wide, and advances by its width of 600
+        assert_eq!(pixel(&image, 21, 20), WHITE);
+        assert_eq!(pixel(&image, 25, 20), BLACK);
+        assert_eq!(pixel(&image, 41, 20), BLACK);
+        assert_eq!(pixel(&image, 43, 20), WHITE);
+        assert_eq!(pixel(&image, 25, 31), WHITE);
+    }
+
+    #[test]
+    fn type1_fonts_draw_their_glyphs() {
+        let image = render(&font_page(type1_font, "BT /F1 20 Tf 10 10 Td (AA) Tj ET"));
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        assert_eq!(pixel(&image, 45, 20), BLACK);
+        assert_eq!(pixel(&image, 55, 20), WHITE);
+        assert_eq!(pixel(&image, 15, 35), WHITE);
+    }
+
+    #[test]
+    fn cff_fonts_draw_their_glyphs() {
+        let image = render(&font_page(cff_font, "BT /F1 20 Tf 10 10 Td (AA) Tj ET"));
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        assert_eq!(pixel(&image, 45, 20), BLACK);
+        assert_eq!(pixel(&image, 55, 20), WHITE);
+        assert_eq!(pixel(&image, 15, 35), WHITE);
+    }
+
+    #[test]
+    fn cid_to_gid_maps_pick_glyphs() {
+        // CID 5 is the half em glyph and CID 6 the square
+        let font = |doc: &mut Document| {
+            let map = [0, 0, 0, 0, 0, 2, 1].map(u16::to_be_bytes).concat();
+            let map = doc.add_object(Stream::new(dictionary! {}, map));
+            cid_font(doc, dictionary! { "CIDToGIDMap" => map })
+        };
+        let image = render(&font_page(font, "BT /F1 20 Tf 10 10 Td <00050006> Tj ET"));
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        assert_eq!(pixel(&image, 25, 20), WHITE);
+        assert_eq!(pixel(&image, 35, 20), BLACK);
+    }
+
+    #[test]
+    fn pages_are_rendered_by_number() {
+        let mut doc = document();
+        add_page(&mut doc, Dictionary::new(), "");
+        add_page(&mut doc, Dictionary::new(), "0 0 100 100 re f");
+        assert_eq!(pixel(&render(&doc), 50, 50), WHITE);
+        let settings = RenderSettings::default();
+        let second = cpu::render_page_cpu(&doc, 2, 1., &settings).unwrap();
+        assert_eq!(pixel(&second, 50, 50), BLACK);
+        let missing = cpu::render_page_cpu(&doc, 3, 1., &settings).unwrap_err();
+        assert!(matches!(
+            missing,
+            RasterError::PageNotFound { page: 3, count: 2 }
+        ));
+    }
+
+    #[test]
+    fn malformed_content_is_reported_as_such() {
+        let doc = page(Dictionary::new(), "0 0 50 50 re f BI /W 1 /H 1");
+        let error = cpu::render_page_cpu(&doc, 1, 1., &RenderSettings::default()).unwrap_err();
+        assert!(matches!(error, RasterError::MalformedContent(_)), "{error}");
+    }
+
+    #[test]
+    fn fonts_that_fail_to_load_are_unsupported() {
+        let mut doc = document();
+        let mmtype9 = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "MMType9",
+            "BaseFont" => "Nonesuch",
+        });
+        let broken = doc.add_object(dictionary! { "Type" => "Font" });
+        let fonts = FontCache::new(&RenderSettings::default());
+        for _ in 0..2 {
+            // The second time round the failures come from the cache
+            let error = fonts.load(&doc, &mmtype9.into()).err().unwrap();
+            assert!(matches!(&error, RasterError::UnsupportedFont(name) if name == "MMType9"));
+            let error = fonts.load(&doc, &broken.into()).err().unwrap();
+            assert!(matches!(error, RasterError::UnsupportedFont(_)));
+        }
+    }
+
+    #[test]
+    fn page_count_counts_pages() {
+        let mut doc = document();
+        assert_eq!(page_count(&doc), 0);
+        for _ in 0..3 {
+            add_page(&mut doc, Dictionary::new(), "");
+        }
+        assert_eq!(page_count(&doc), 3);
+    }
+
+    #[test]
+    fn rotate_turns_pages_clockwise() {
+        let mut doc = document();
+        let media_box = vec![0.into(), 0.into(), 100.into(), 50.into()];
+        add_page(
+            &mut doc,
+            dictionary! { "MediaBox" => media_box, "Rotate" => -270 },
+            "0 0 10 10 re f",
+        );
+        assert_eq!(page_size(&doc, 1).unwrap(), (50., 100.));
+        let image = render(&doc);
+        assert_eq!((image.width(), image.height()), (50, 100));
+        // The bottom left corner is turned to the top left
+        assert_eq!(pixel(&image, 5, 95), BLACK);
+        assert_eq!(pixel(&image, 5, 5), WHITE);
+    }
+
+    #[test]
+    fn crop_box_is_the_visible_page() {
+        let mut doc = document();
+        let crop_box = vec![50.into(), 50.into(), 100.into(), 150.into()];
+        add_page(
+            &mut doc,
+            dictionary! { "CropBox" => crop_box },
+            "50 50 10 10 re f",
+        );
+        // It's clipped to the media box
+        assert_eq!(page_size(&doc, 1).unwrap(), (50., 50.));
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 5, 5), BLACK);
+        assert_eq!(pixel(&image, 15, 15), WHITE);
+    }
+
+    #[test]
+    fn pages_inherit_media_box_and_resources() {
+        let mut doc = document();
+        let font = square_font(&mut doc);
+        let page = add_page(
+            &mut doc,
+            Dictionary::new(),
+            "BT /F1 10 Tf 10 10 Td (A) Tj ET",
+        );
+        doc.get_dictionary_mut(page).unwrap().remove(b"MediaBox");
+        let pages = doc
+            .catalog()
+            .unwrap()
+            .get(b"Pages")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let tree = doc.get_dictionary_mut(pages).unwrap();
+        tree.set("MediaBox", vec![0.into(), 0.into(), 40.into(), 30.into()]);
+        tree.set(
+            "Resources",
+            dictionary! { "Font" => dictionary! { "F1" => font } },
+        );
+        let image = render(&doc);
+        assert_eq!((image.width(), image.height()), (40, 30));
+        assert_eq!(pixel(&image, 15, 15), BLACK);
+    }
+
+    #[test]
+    fn dpi_overrides_the_scale() {
+        let settings = RenderSettings {
+            dpi: Some(144.),
+            ..Default::default()
+        };
+        assert_eq!(settings.scale(3.), 2.);
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let image = cpu::render_page_cpu(&doc, 1, 3., &settings).unwrap();
+        assert_eq!((image.width(), image.height()), (200, 200));
+        assert_eq!(pixel(&image, 90, 90), BLACK);
+        assert_eq!(pixel(&image, 110, 110), WHITE);
+    }
+
+    #[test]
+    fn background_fills_the_page() {
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let red = RenderSettings {
+            background: Some(Color::from_rgb8(255, 0, 0)),
+            ..Default::default()
+        };
+        let image = render_with(&doc, &red);
+        assert_eq!(pixel(&image, 75, 75), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        let transparent = RenderSettings {
+            background: None,
+            ..Default::default()
+        };
+        assert_eq!(pixel(&render_with(&doc, &transparent), 75, 75), [0; 4]);
+    }
+
+    #[test]
+    fn re_follows_a_rotated_ctm() {
+        let doc = page(
+            Dictionary::new(),
+            "0.7071 0.7071 -0.7071 0.7071 50 20 cm 0 0 40 40 re f",
+        );
+        let image = render(&doc);
+        // A diamond standing on its corner at (50, 20)
+        assert_eq!(pixel(&image, 50, 48), BLACK);
+        assert_eq!(pixel(&image, 30, 48), BLACK);
+        assert_eq!(pixel(&image, 30, 30), WHITE);
+        assert_eq!(pixel(&image, 70, 70), WHITE);
+    }
+
+    #[test]
+    fn strokes_are_scaled_by_the_ctm() {
+        let doc = page(
+            Dictionary::new(),
+            "2 0 0 1 0 0 cm 10 w 10 70 m 40 70 l S 20 10 m 20 40 l S",
+        );
+        let image = render(&doc);
+        // Horizontal lines keep their width, while vertical ones double
+        assert_eq!(pixel(&image, 50, 73), BLACK);
+        assert_eq!(pixel(&image, 50, 77), WHITE);
+        assert_eq!(pixel(&image, 32, 25), BLACK);
+        assert_eq!(pixel(&image, 48, 25), BLACK);
+        assert_eq!(pixel(&image, 52, 25), WHITE);
+    }
+
+    #[test]
+    fn glyphs_follow_the_text_matrix() {
+        let image = render(&font_page(
+            truetype_font,
+            "BT /F1 40 Tf 0.7071 0.7071 -0.7071 0.7071 50 20 Tm (A) Tj ET",
+        ));
+        // A diamond standing on its corner at (50, 20)
+        assert_eq!(pixel(&image, 50, 48), BLACK);
+        assert_eq!(pixel(&image, 30, 48), BLACK);
+        assert_eq!(pixel(&image, 30, 30), WHITE);
+        assert_eq!(pixel(&image, 70, 70), WHITE);
+    }
+
+    // A gray shading of `shading_type` running from `c0` to `c1` between its
+    // `coords`, and extended past both ends if `extend`
+    fn gray(image: &image::RgbaImage, x: u32, y: u32) -> u8 {
+        pixel(image, x, y)[0]
+    }
+
+    #[test]
+    fn sh_paints_axial_shadings() {
+        let render_shading = |extend| {
+            let shading = gray_shading(2, &[20., 0., 80., 0.], extend, 0., 1.);
+            let resources = dictionary! { "Shading" => dictionary! { "Sh1" => shading } };
+            render(&page(resources, "/Sh1 sh"))
+        };
+        let image = render_shading(false);
+        assert!(gray(&image, 25, 50) < 30);
+        assert!((120..136).contains(&gray(&image, 50, 50)));
+        assert!(gray(&image, 75, 50) > 225);
+        // Without extending it, the shading stops at its ends
+        assert_eq!(pixel(&image, 10, 50), WHITE);
+        assert_eq!(pixel(&render_shading(true), 10, 50), BLACK);
+    }
+
+    #[test]
+    fn sh_paints_radial_shadings() {
+        let render_shading = |extend| {
+            let shading = gray_shading(3, &[50., 50., 0., 50., 50., 40.], extend, 1., 0.);
+            let resources = dictionary! { "Shading" => dictionary! { "Sh1" => shading } };
+            render(&page(resources, "/Sh1 sh"))
+        };
+        let image = render_shading(false);
+        assert!(gray(&image, 50, 52) > 225);
+        assert!((88..104).contains(&gray(&image, 50, 75)));
+        assert!(gray(&image, 50, 88) < 30);
+        assert_eq!(pixel(&image, 95, 95), WHITE);
+        assert_eq!(pixel(&render_shading(true), 95, 95), BLACK);
+    }
+
+    #[test]
+    fn bm_blends_with_the_backdrop() {
+        let render_blend = |mode: &str| {
+            let resources = dictionary! {
+                "ExtGState" => dictionary! { "GS1" => dictionary! { "BM" => mode } },
+            };
+            render(&page(
+                resources,
+                "1 0 0 rg 0 0 60 100 re f /GS1 gs 0 1 0 rg 40 0 60 100 re f",
+            ))
+        };
+        let multiply = render_blend("Multiply");
+        assert_eq!(pixel(&multiply, 50, 50), BLACK);
+        assert_eq!(pixel(&multiply, 80, 50), [0, 255, 0, 255]);
+        let screen = render_blend("Screen");
+        assert_eq!(pixel(&screen, 50, 50), [255, 255, 0, 255]);
+        assert_eq!(pixel(&screen, 20, 50), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn inline_images_are_drawn() {
+        let doc = page(
+            Dictionary::new(),
+            "q 50 0 0 50 25 25 cm BI /W 2 /H 1 /CS /RGB /BPC 8 /F /AHx ID ff00000000ff> EI Q \
+             0 g 0 0 10 10 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 35, 50), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 65, 50), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 50, 80), WHITE);
+        // The content stream carries on after the image
+        assert_eq!(pixel(&image, 5, 5), BLACK);
+    }
+
+    #[test]
+    fn d0_and_d1_set_type3_glyph_metrics() {
+        let mut doc = document();
+        let font = square_font(&mut doc);
+        // B sets its own color, and A's width is overridden by its d1
+        let b = doc.add_object(Stream::new(
+            dictionary! {},
+            b"1000 0 d0 0 0 1 rg 0 0 500 1000 re f".to_vec(),
+        ));
+        let font_dict = doc.get_dictionary_mut(font).unwrap();
+        let Ok(Object::Dictionary(procs)) = font_dict.get_mut(b"CharProcs") else {
+            panic!("the font has glyph procedures");
+        };
+        procs.set("B", b);
+        font_dict.set("LastChar", 66);
+        let Ok(Object::Array(widths)) = font_dict.get_mut(b"Widths") else {
+            panic!("the font has widths");
+        };
+        widths[33] = 3000.into();
+        widths.push(1000.into());
+        let Ok(Object::Dictionary(encoding)) = font_dict.get_mut(b"Encoding") else {
+            panic!("the font has an encoding");
+        };
+        encoding.set("Differences", vec![65.into(), "A".into(), "B".into()]);
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } } },
+            "BT /F1 10 Tf 1 0 0 rg 10 10 Td (AAB) Tj ET",
+        );
+        let image = render(&doc);
+        let red = [255, 0, 0, 255];
+        assert_eq!(pixel(&image, 15, 15), red);
+        assert_eq!(pixel(&image, 25, 15), red);
+        assert_eq!(pixel(&image, 32, 15), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 37, 15), WHITE);
+    }
+
+    #[test]
+    fn unbalanced_q_and_capital_q_are_tolerated() {
+        let doc = page(
+            Dictionary::new(),
+            "q 1 0 0 rg Q Q Q 0 0 50 50 re f q 0 0 1 rg",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 75, 75), WHITE);
+    }
+
+    #[test]
+    fn build_scene_encodes_the_page() {
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let paths = |settings: &RenderSettings| {
+            build_scene(&doc, 1, 100, 100, settings)
+                .unwrap()
+                .encoding()
+                .n_paths
+        };
+        // The page's fill, over the background
+        assert_eq!(paths(&RenderSettings::default()), 2);
+        let transparent = RenderSettings {
+            background: None,
+            ..Default::default()
+        };
+        assert_eq!(paths(&transparent), 1);
+        assert!(build_scene(&doc, 2, 100, 100, &transparent).is_err());
+    }
+
+    #[test]
+    fn annotations_are_drawn_into_their_rect() {
+        let mut doc = document();
+        let square = doc.add_object(Stream::new(
+            dictionary! { "BBox" => vec![0.into(), 0.into(), 1.into(), 1.into()] },
+            b"0 0 1 1 re f".to_vec(),
+        ));
+        let annot = |doc: &mut Document, rect: [i64; 4], extra: Dictionary| {
+            let mut annot = dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Widget",
+                "Rect" => rect.map(Object::from).to_vec(),
+            };
+            annot.extend(&extra);
+            doc.add_object(annot)
+        };
+        let annots = vec![
+            annot(
+                &mut doc,
+                [10, 10, 30, 30],
+                dictionary! { "AP" => dictionary! { "N" => square } },
+            )
+            .into(),
+            // Hidden
+            annot(
+                &mut doc,
+                [60, 10, 80, 30],
+                dictionary! { "F" => 2, "AP" => dictionary! { "N" => square } },
+            )
+            .into(),
+            // A checkbox, drawn in its /AS state
+            annot(
+                &mut doc,
+                [10, 60, 30, 80],
+                dictionary! {
+                    "AS" => "On",
+                    "AP" => dictionary! { "N" => dictionary! { "On" => square } },
+                },
+            )
+            .into(),
+            // No appearance for its state
+            annot(
+                &mut doc,
+                [60, 60, 80, 80],
+                dictionary! {
+                    "AS" => "Off",
+                    "AP" => dictionary! { "N" => dictionary! { "On" => square } },
+                },
+            )
+            .into(),
+        ];
+        add_page(&mut doc, dictionary! { "Annots" => annots }, "");
+        let settings = RenderSettings {
+            render_annotations: true,
+            ..Default::default()
+        };
+        let image = render_with(&doc, &settings);
+        assert_eq!(pixel(&image, 20, 20), BLACK);
+        assert_eq!(pixel(&image, 35, 20), WHITE);
+        assert_eq!(pixel(&image, 70, 20), WHITE);
+        assert_eq!(pixel(&image, 20, 70), BLACK);
+        assert_eq!(pixel(&image, 70, 70), WHITE);
+        // They're only drawn when asked for
+        assert_eq!(pixel(&render(&doc), 20, 20), WHITE);
+    }
+
+    #[test]
+    fn anti_aliasing_follows_the_settings() {
+        let settings = |anti_alias, anti_aliasing| RenderSettings {
+            anti_alias,
+            anti_aliasing,
+            ..Default::default()
+        };
+        let msaa = settings(true, AntiAliasing::Msaa16);
+        assert!(matches!(msaa.aa_config(), vello::AaConfig::Msaa16));
+        let off = settings(false, AntiAliasing::Msaa16);
+        assert!(matches!(off.aa_config(), vello::AaConfig::Area));
+
+        // An edge halfway across a pixel
+        let doc = page(Dictionary::new(), "0 0 10.5 100 re f");
+        let edge = |settings| {
+            pixel(
+                &cpu::render_page_cpu(&doc, 1, 1., &settings).unwrap(),
+                10,
+                50,
+            )
+        };
+        let smooth = edge(settings(true, AntiAliasing::Area));
+        assert!(smooth[0] > 64 && smooth[0] < 192, "{smooth:?}");
+        assert!([BLACK, WHITE].contains(&edge(off)));
+    }
+
+    #[test]
+    fn encrypted_documents_need_their_user_password() {
+        let mut doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let id = Object::string_literal("0123456789abcdef");
+        doc.trailer.set("ID", vec![id.clone(), id]);
+        let state = lopdf::EncryptionState::try_from(lopdf::EncryptionVersion::V2 {
+            document: &doc,
+            owner_password: "owner",
+            user_password: "user",
+            key_length: 128,
+            permissions: lopdf::Permissions::default(),
+        })
+        .unwrap();
+        doc.encrypt(&state).unwrap();
+        let mut bytes = Vec::new();
+        doc.save_to(&mut bytes).unwrap();
+
+        let decrypted = load_encrypted(&bytes, "user").unwrap();
+        assert_eq!(pixel(&render(&decrypted), 25, 25), BLACK);
+        assert!(matches!(
+            load_encrypted(&bytes, ""),
+            Err(RasterError::PasswordRequired)
+        ));
+        assert!(matches!(
+            load_encrypted(&bytes, "wrong"),
+            Err(RasterError::IncorrectPassword)
+        ));
+        assert!(matches!(
+            load_encrypted(&bytes, "owner"),
+            Err(RasterError::OwnerPassword)
+        ));
+        // Unencrypted documents load whatever the password
+        let mut plain = Vec::new();
+        page(Dictionary::new(), "").save_to(&mut plain).unwrap();
+        assert!(load_encrypted(&plain, "anything").is_ok());
+    }
+
+    #[test]
+    fn sc_and_capital_sc_set_colors_in_the_current_space() {
+        let state = interpret("/DeviceRGB cs 1 0 0 sc /DeviceCMYK CS 0 1 0 0 SC");
+        assert_eq!(state.gs.non_stroke_color, Color::new([1., 0., 0., 1.]));
+        assert_eq!(state.gs.stroke_color, Color::new([1., 0., 1., 1.]));
+
+        let image = render(&page(
+            Dictionary::new(),
+            "/DeviceRGB cs 0 0 1 sc /DeviceRGB CS 0 1 0 SC 10 w 0 0 50 50 re B",
+        ));
+        assert_eq!(pixel(&image, 25, 25), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 50, 25), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn h_returns_to_the_start_of_the_subpath() {
+        let state = interpret("10 20 m 50 20 l 50 60 l h");
+        let Coord { x, y } = state.gs.current_point;
+        // In device space, from the top of the page
+        assert_eq!((x, y), (10., 80.));
+        // And re's subpath starts at its corner
+        let state = interpret("10 20 m 30 30 40 40 re 50 50 l h");
+        let Coord { x, y } = state.gs.current_point;
+        assert_eq!((x, y), (30., 70.));
+    }
+
+    #[test]
+    fn v_starts_its_curve_at_the_current_point() {
+        let state = interpret("10 10 m 20 80 90 10 v 30 30 20 20 re 50 90 90 90 v");
+        assert_eq!(
+            state.gs.path.elements(),
+            [
+                PathEl::MoveTo((10., 90.).into()),
+                PathEl::CurveTo((10., 90.).into(), (20., 20.).into(), (90., 90.).into()),
+                PathEl::MoveTo((30., 70.).into()),
+                PathEl::LineTo((50., 70.).into()),
+                PathEl::LineTo((50., 50.).into()),
+                PathEl::LineTo((30., 50.).into()),
+                PathEl::ClosePath,
+                // From the rectangle's origin
+                PathEl::CurveTo((30., 70.).into(), (50., 10.).into(), (90., 10.).into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn tj_adjustments_add_to_character_and_word_spacing() {
+        // A is 10pt wide and the space 5pt, each followed by 2pt of
+        // character spacing, and the space by 3pt of word spacing
+        let doc = text_page("BT /F1 10 Tf 2 Tc 3 Tw 10 10 Td [(A) -500 ( A)] TJ ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 19, 15), BLACK);
+        assert_eq!(pixel(&image, 36, 15), WHITE);
+        assert_eq!(pixel(&image, 37, 15), BLACK);
+        assert_eq!(pixel(&image, 46, 15), BLACK);
+        assert_eq!(pixel(&image, 47, 15), WHITE);
+    }
+
+    #[test]
+    fn tf_keeps_the_text_position() {
+        let doc = text_page("BT /F1 10 Tf 10 10 Td (A) Tj /F1 20 Tf (A) Tj ET");
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 39, 29), BLACK);
+        assert_eq!(pixel(&image, 41, 15), WHITE);
+        assert_eq!(pixel(&image, 15, 25), WHITE);
+    }
+
+    #[test]
+    fn i_sets_the_flatness_and_ri_is_accepted() {
+        assert_eq!(interpret("").gs.flatness, DEFAULT_FLATNESS);
+        assert_eq!(interpret("5 i").gs.flatness, 5.);
+        assert_eq!(interpret("5 i 0 i").gs.flatness, DEFAULT_FLATNESS);
+        assert_eq!(interpret("500 i").gs.flatness, 100.);
+        assert_eq!(interpret("5 i q 1 i Q").gs.flatness, 5.);
+        // Rendering intents only matter to color management, which there isn't
+        interpret("/Perceptual ri");
+
+        let doc = page(
+            dictionary! { "ExtGState" => dictionary! {
+                "GS1" => dictionary! { "FL" => 20, "RI" => "Saturation" },
+            } },
+            "/GS1 gs /RelativeColorimetric ri 0 0 50 50 re f",
+        );
+        assert_eq!(pixel(&render(&doc), 25, 25), BLACK);
+    }
+
+    #[test]
+    fn documents_are_opened_from_a_path() {
+        let path = std::env::temp_dir().join(format!("rasterizer-open-{}.pdf", std::process::id()));
+        page(Dictionary::new(), "0 0 50 50 re f")
+            .save(&path)
+            .unwrap();
+        let doc = open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pixel(&render(&doc.unwrap()), 25, 25), BLACK);
+
+        let missing = open(&path).unwrap_err();
+        assert!(format!("{missing}").contains(&path.display().to_string()));
+    }
+
+    #[test]
+    fn overprinting_keeps_the_inks_a_cmyk_color_leaves_out() {
+        let render_with_gs = |gs: Dictionary, paint: &str| {
+            let doc = page(
+                dictionary! { "ExtGState" => dictionary! { "GS1" => gs } },
+                &format!("1 0 0 0 k 0 0 100 100 re f /GS1 gs {paint} 0 0 50 100 re f"),
+            );
+            pixel(&render(&doc), 25, 50)
+        };
+        let (blue, magenta) = ([0, 0, 255, 255], [255, 0, 255, 255]);
+        let overprint = dictionary! { "OP" => true, "OPM" => 1 };
+        assert_eq!(render_with_gs(overprint.clone(), "0 1 0 0 k"), blue);
+        // op overrides OP for fills
+        let fill_off = dictionary! { "OP" => true, "op" => false, "OPM" => 1 };
+        assert_eq!(render_with_gs(fill_off, "0 1 0 0 k"), magenta);
+        // Without OPM 1, every ink is painted
+        assert_eq!(
+            render_with_gs(dictionary! { "OP" => true }, "0 1 0 0 k"),
+            magenta
+        );
+        // And colors that aren't CMYK paint every ink too
+        assert_eq!(render_with_gs(overprint, "1 0 1 rg"), magenta);
+    }
+
+    #[test]
+    fn zero_width_lines_are_a_pixel_wide() {
+        let settings = RenderSettings {
+            anti_alias: false,
+            ..Default::default()
+        };
+        let rows_painted = |content: &str| {
+            let image =
+                cpu::render_page_cpu(&page(Dictionary::new(), content), 1, 3., &settings).unwrap();
+            (0..image.height())
+                .filter(|y| image.get_pixel(150, *y).0 != WHITE)
+                .count()
+        };
+        assert_eq!(rows_painted("0 w 10 50.5 m 90 50.5 l S"), 1);
+        // However the CTM stretches the pen
+        assert_eq!(rows_painted("1 0 0 10 0 0 cm 0 w 10 5.05 m 90 5.05 l S"), 1);
+        assert_eq!(rows_painted("1 w 10 50.5 m 90 50.5 l S"), 3);
+    }
+
+    #[test]
+    fn bad_operators_fail_the_page_unless_skipped() {
+        let doc = page(
+            Dictionary::new(),
+            "0 0 20 20 re f 40 /oops 20 20 re f 60 60 20 20 re f",
+        );
+        let settings = RenderSettings::default();
+        assert!(cpu::render_page_cpu(&doc, 1, 1., &settings).is_err());
+
+        let skip = RenderSettings {
+            skip_bad_operators: true,
+            ..settings
+        };
+        let image = render_with(&doc, &skip);
+        assert_eq!(pixel(&image, 10, 10), BLACK);
+        assert_eq!(pixel(&image, 50, 30), WHITE);
+        assert_eq!(pixel(&image, 70, 70), BLACK);
+    }
+
+    #[test]
+    fn q_past_the_maximum_depth_is_ignored_with_its_q() {
+        let settings = RenderSettings {
+            max_stack_depth: 10,
+            ..Default::default()
+        };
+        let content = format!(
+            "{} 1 0 0 rg {} 0 0 50 50 re f Q 50 50 50 50 re f",
+            "q ".repeat(1000),
+            "Q ".repeat(990)
+        );
+        let image = render_with(&page(Dictionary::new(), &content), &settings);
+        // The first 990 Q match the ignored q, so only the next restores
+        assert_eq!(pixel(&image, 25, 25), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 75, 75), BLACK);
+
+        let content = format!("q 0 0 1 rg {} Q 0 0 50 50 re f", "q ".repeat(1000));
+        let image = render_with(&page(Dictionary::new(), &content), &settings);
+        assert_eq!(pixel(&image, 25, 25), [0, 0, 255, 255]);
+
+        let state = interpret_with(&content, &settings);
+        assert_eq!(state.stack.len(), 10);
+        assert_eq!(state.ignored_saves, 990);
+        assert_eq!(state.max_depth, 1001);
+    }
+
+    #[test]
+    fn pages_drawn_with_one_cache_share_fonts_and_outlines() {
+        let mut doc = document();
+        let font = truetype_font(&mut doc);
+        for _ in 0..2 {
+            add_page(
+                &mut doc,
+                dictionary! { "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } } },
+                "BT /F1 20 Tf 10 10 Td (ABA) Tj ET",
+            );
+        }
+        let settings = RenderSettings::default();
+        let fonts = FontCache::new(&settings);
+        for page in [1, 2] {
+            draw_doc(&doc, &mut Scene::new(), 100, 100, page, &settings, &fonts).unwrap();
+        }
+        assert_eq!(fonts.fonts.borrow().len(), 1);
+        // Six glyphs are drawn, but only A and B are outlined
+        assert_eq!(fonts.glyph_stats(), (6, 2));
+    }
+
+    #[test]
+    fn fonts_that_arent_embedded_use_the_substitutions() {
+        let path =
+            std::env::temp_dir().join(format!("rasterizer-squares-{}.ttf", std::process::id()));
+        std::fs::write(&path, truetype_program()).unwrap();
+        let doc = font_page(
+            |doc| {
+                doc.add_object(dictionary! {
+                    "Type" => "Font",
+                    "Subtype" => "TrueType",
+                    "BaseFont" => "Nonesuch",
+                    "FirstChar" => 65,
+                    "LastChar" => 66,
+                    "Widths" => vec![1000.into(), 600.into()],
+                })
+            },
+            "BT /F1 20 Tf 10 10 Td (BA) Tj ET",
+        );
+        let settings = RenderSettings {
+            font_substitutions: HashMap::from([("Nonesuch".to_string(), path.clone())]),
+            ..Default::default()
+        };
+        let image = render_with(&doc, &settings);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pixel(&image, 15, 20), BLACK);
+        assert_eq!(pixel(&image, 21, 20), WHITE);
+        assert_eq!(pixel(&image, 25, 20), BLACK);
+    }
+
+    #[test]
+    fn user_units_scale_the_page() {
+        let mut doc = document();
+        add_page(
+            &mut doc,
+            dictionary! {
+                "MediaBox" => vec![0.into(), 0.into(), 50.into(), 50.into()],
+                "UserUnit" => 2,
+            },
+            "0 0 25 25 re f",
+        );
+        let page = doc.get_dictionary(validate_page(&doc, 1).unwrap()).unwrap();
+        assert_eq!(dimensions(&doc, page).unwrap(), (100., 100.));
+        let image = render(&doc);
+        assert_eq!(image.dimensions(), (100, 100));
+        assert_eq!(pixel(&image, 45, 45), BLACK);
+        assert_eq!(pixel(&image, 55, 55), WHITE);
+    }
+
+    #[test]
+    fn images_that_cant_be_decoded_are_skipped() {
+        let mut doc = document();
+        let image = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 8,
+                "Height" => 8,
+                "ImageMask" => true,
+                "Filter" => "CCITTFaxDecode",
+            },
+            vec![0; 8],
+        ));
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "XObject" => dictionary! { "Im1" => image } } },
+            "0 0 50 50 re f q 100 0 0 100 0 0 cm /Im1 Do Q \
+             BI /W 8 /H 8 /IM true /F /CCF ID 00000000 EI 50 50 50 50 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 75, 25), WHITE);
+        assert_eq!(pixel(&image, 75, 75), BLACK);
+    }
+
+    #[test]
+    fn indexed_colors_are_painted_from_the_palette() {
+        let indexed = |base: Object, lookup: Object| {
+            Object::Array(vec!["Indexed".into(), base, 1.into(), lookup])
+        };
+        let rgb = indexed(
+            "DeviceRGB".into(),
+            Object::string_literal(vec![255, 0, 0, 0, 0, 255]),
+        );
+        // A CMYK profile, with the palette in a stream
+        let profile = Stream::new(dictionary! { "N" => 4 }, Vec::new());
+        let cmyk = indexed(
+            Object::Array(vec!["ICCBased".into(), Object::Stream(profile)]),
+            Object::Stream(Stream::new(
+                Dictionary::new(),
+                vec![0, 0, 0, 255, 0, 255, 255, 0],
+            )),
+        );
+        let doc = page(
+            dictionary! { "ColorSpace" => dictionary! { "CS0" => rgb, "CS1" => cmyk } },
+            "/CS0 cs 1 scn 0 0 50 100 re f /CS1 cs 1 scn 50 0 50 100 re f",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 50), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 75, 50), [255, 0, 0, 255]);
+    }
+}
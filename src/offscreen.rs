@@ -2,160 +2,650 @@ use crate::*;
 use eyre::{Result, eyre};
 use image::{ImageBuffer, RgbaImage};
 use lopdf::Document;
-use vello::util::RenderContext;
+use peniko::Color;
+use std::{
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
 use vello::{Renderer, RendererOptions, Scene};
 
-pub async fn pdf_to_rgba_image(
-    doc: &Document,
-    page: u32,
-    scale: f32,
-    render_settings: &RenderSettings,
-) -> Result<RgbaImage> {
-    let page_id = doc
-        .get_pages()
-        .get(&page)
-        .ok_or_else(|| eyre!("Page {} not found in PDF", page))?
-        .clone();
-
-    let page_dict = doc.get_dictionary(page_id)?;
-    let size = dimensions(page_dict)?;
-
-    let width = (size.0 as f32 * scale) as u32;
-    let height = (size.1 as f32 * scale) as u32;
-
-    let mut render_cx = RenderContext::new();
-
-    let device_id = render_cx
-        .device(None)
-        .await
-        .ok_or_else(|| eyre!("No compatible device found"))?;
-
-    let mut renderer = Renderer::new(
-        &render_cx.devices[device_id].device,
-        RendererOptions {
-            use_cpu: false,
-            antialiasing_support: vello::AaSupport::all(),
-            num_init_threads: None,
-            pipeline_cache: None,
-        },
-    )
-    .unwrap();
-
-    let device = &render_cx.devices[0].device;
-    let queue = &render_cx.devices[0].queue;
-
-    let texture_desc = wgpu::TextureDescriptor {
-        size: wgpu::Extent3d {
+/// Renders pages without a window, keeping the GPU device, renderer and
+/// target texture around so that rendering many pages only pays for setup once.
+pub struct OffscreenRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    renderer: Renderer,
+    target: Option<Target>,
+    scene: Scene,
+    pub scale: f32,
+    pub settings: RenderSettings,
+}
+
+struct Target {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    bytes_per_row: u32,
+}
+
+impl Target {
+    fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::STORAGE_BINDING,
+            label: Some("Render Texture"),
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let u32_size = std::mem::size_of::<u32>() as u32;
+        let bytes_per_row = (u32_size * width).div_ceil(256) * 256;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            label: Some("Output Buffer"),
+            mapped_at_creation: false,
+        });
+
+        Target {
+            texture,
+            view,
+            buffer,
             width,
             height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8Unorm,
-        usage: wgpu::TextureUsages::COPY_SRC
-            | wgpu::TextureUsages::RENDER_ATTACHMENT
-            | wgpu::TextureUsages::STORAGE_BINDING,
-        label: Some("Render Texture"),
-        view_formats: &[],
-    };
-    let texture = device.create_texture(&texture_desc);
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-    let u32_size = std::mem::size_of::<u32>() as u32;
-    let unpadded_bytes_per_row = u32_size * width;
-    let bytes_per_row = ((unpadded_bytes_per_row + 255) / 256) * 256;
-    let output_buffer_size = (bytes_per_row * height) as wgpu::BufferAddress;
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        size: output_buffer_size,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-        label: Some("Output Buffer"),
-        mapped_at_creation: false,
-    });
-
-    let mut scene = Scene::new();
-
-    use kurbo::{Affine, Rect};
-    use peniko::Color;
-    scene.fill(
-        peniko::Fill::NonZero,
-        Affine::IDENTITY,
-        Color::WHITE,
-        None,
-        &Rect::new(0.0, 0.0, width as f64, height as f64),
-    );
-
-    draw_doc(doc, &mut scene, width, height, page, &render_settings)?;
-
-    let render_params = vello::RenderParams {
-        base_color: peniko::Color::BLACK,
-        width,
-        height,
-        antialiasing_method: vello::AaConfig::Msaa16,
-    };
+            bytes_per_row,
+        }
+    }
+}
+
+impl OffscreenRenderer {
+    /// Sets up rendering on the default GPU adapter, or on a software adapter
+    /// with vello's CPU pipeline when there's no GPU or `settings.use_cpu` is set.
+    pub async fn new(scale: f32, mut settings: RenderSettings) -> Result<Self, RasterError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+
+        let adapter = match instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+        {
+            Some(adapter) if !settings.use_cpu => adapter,
+            adapter => {
+                settings.use_cpu = true;
+                let fallback = instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        force_fallback_adapter: true,
+                        ..Default::default()
+                    })
+                    .await;
+                fallback
+                    .or(adapter)
+                    .ok_or_else(|| eyre!("No compatible device found"))?
+            }
+        };
 
-    renderer
-        .render_to_texture(device, queue, &scene, &texture_view, &render_params)
-        .map_err(|e| eyre!("Render error: {:?}", e))?;
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Copy Encoder"),
-    });
-
-    encoder.copy_texture_to_buffer(
-        wgpu::TexelCopyTextureInfo {
-            aspect: wgpu::TextureAspect::All,
-            texture: &texture,
-            mip_level: 0,
-            origin: wgpu::Origin3d::ZERO,
-        },
-        wgpu::TexelCopyBufferInfo {
-            buffer: &output_buffer,
-            layout: wgpu::TexelCopyBufferLayout {
-                offset: 0,
-                bytes_per_row: Some(bytes_per_row),
-                rows_per_image: Some(height),
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .map_err(|e| eyre!("Failed to request a device: {e}"))?;
+
+        let renderer = Renderer::new(
+            &device,
+            RendererOptions {
+                use_cpu: settings.use_cpu,
+                antialiasing_support: vello::AaSupport::all(),
+                num_init_threads: None,
+                pipeline_cache: None,
             },
-        },
-        wgpu::Extent3d {
+        )
+        .map_err(|e| eyre!("Failed to create renderer: {:?}", e))?;
+
+        Ok(OffscreenRenderer {
+            device,
+            queue,
+            renderer,
+            target: None,
+            scene: Scene::new(),
+            scale,
+            settings,
+        })
+    }
+
+    /// Renders a page into the target texture, which is only reallocated
+    /// when the page size differs from the previous one.
+    pub fn render_pdf(&mut self, doc: &Document, page: u32) -> Result<(), RasterError> {
+        self.render_with_fonts(doc, page, &FontCache::new(&self.settings))
+    }
+
+    fn render_with_fonts(
+        &mut self,
+        doc: &Document,
+        page: u32,
+        fonts: &FontCache,
+    ) -> Result<(), RasterError> {
+        let page_dict = doc.get_dictionary(validate_page(doc, page)?)?;
+        let size = dimensions(doc, page_dict)?;
+
+        let scale = self.settings.scale(self.scale);
+        let width = (size.0 * scale) as u32;
+        let height = (size.1 * scale) as u32;
+
+        let device = &self.device;
+        let queue = &self.queue;
+
+        // wgpu panics on textures it can't allocate, so check first
+        let max = device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 || width > max || height > max {
+            return Err(RasterError::Other(eyre!(
+                "Can't render page {page} at {width}x{height} pixels; the limit is {max}x{max}"
+            )));
+        }
+        let target = match self.target.take() {
+            Some(target) if target.width == width && target.height == height => target,
+            _ => Target::new(device, width, height),
+        };
+        let target = self.target.insert(target);
+
+        self.scene.reset();
+        paint_page(
+            doc,
+            &mut self.scene,
+            page,
             width,
             height,
-            depth_or_array_layers: 1,
-        },
-    );
+            &self.settings,
+            fonts,
+        )?;
 
-    queue.submit(Some(encoder.finish()));
+        let render_params = vello::RenderParams {
+            base_color: self.settings.background.unwrap_or(Color::TRANSPARENT),
+            width,
+            height,
+            antialiasing_method: self.settings.aa_config(),
+        };
+
+        self.renderer
+            .render_to_texture(device, queue, &self.scene, &target.view, &render_params)
+            .map_err(|e| eyre!("Render error: {:?}", e))?;
+
+        Ok(())
+    }
+
+    /// Renders every page in order, reusing the GPU resources, target
+    /// texture, scene and fonts from one page to the next.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_all<'a>(
+        &'a mut self,
+        doc: &'a Document,
+    ) -> impl Iterator<Item = Result<RgbaImage, RasterError>> + 'a {
+        let fonts = FontCache::new(&self.settings);
+        (1..=page_count(doc) as u32).map(move |page| {
+            self.render_with_fonts(doc, page, &fonts)?;
+            self.to_rgba_image()
+        })
+    }
+
+    /// Reads the most recently rendered page back from the GPU, blocking
+    /// until it's done.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn to_rgba_image(&self) -> Result<RgbaImage, RasterError> {
+        pollster::block_on(self.read_rgba_image())
+    }
+
+    /// Reads the most recently rendered page back from the GPU. On the web
+    /// this waits for the browser to map the buffer rather than blocking.
+    pub async fn read_rgba_image(&self) -> Result<RgbaImage, RasterError> {
+        let (width, height, data) = self.read_rgba_bytes().await?;
+        Ok(ImageBuffer::from_raw(width, height, data)
+            .ok_or_else(|| eyre!("Failed to create image buffer"))?)
+    }
+
+    /// Like [`read_rgba_image`](Self::read_rgba_image), but returns the
+    /// width, height and tightly packed RGBA rows.
+    pub async fn read_rgba_bytes(&self) -> Result<(u32, u32, Vec<u8>), RasterError> {
+        let target = self
+            .target
+            .as_ref()
+            .ok_or_else(|| eyre!("No page has been rendered"))?;
+        let Target {
+            width,
+            height,
+            bytes_per_row,
+            ..
+        } = *target;
+
+        let device = &self.device;
+        let queue = &self.queue;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Copy Encoder"),
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &target.buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        queue.submit(Some(encoder.finish()));
 
-    let buffer_slice = output_buffer.slice(..);
+        let buffer_slice = target.buffer.slice(..);
 
-    let (tx, rx) = std::sync::mpsc::channel();
-    buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-        tx.send(result).unwrap();
-    });
+        let mapped = Mapped::default();
+        let done = mapped.clone();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| done.finish(result));
 
-    device.poll(wgpu::Maintain::Wait);
-    rx.recv().unwrap()?;
+        // Natively nothing happens until the device is polled, while the
+        // browser maps the buffer on its own
+        #[cfg(not(target_arch = "wasm32"))]
+        device.poll(wgpu::Maintain::Wait);
+        mapped
+            .await
+            .map_err(|e| eyre!("Failed to read the page back: {e}"))?;
 
-    let data = buffer_slice.get_mapped_range();
+        let data = buffer_slice.get_mapped_range();
 
-    let image_data = if bytes_per_row != unpadded_bytes_per_row {
-        let mut unpadded_data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
-        for row in 0..height {
-            let row_start = (row * bytes_per_row) as usize;
-            let row_end = row_start + unpadded_bytes_per_row as usize;
-            unpadded_data.extend_from_slice(&data[row_start..row_end]);
+        let unpadded_bytes_per_row = std::mem::size_of::<u32>() as u32 * width;
+        let image_data = if bytes_per_row != unpadded_bytes_per_row {
+            let mut unpadded_data = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+            for row in 0..height {
+                let row_start = (row * bytes_per_row) as usize;
+                let row_end = row_start + unpadded_bytes_per_row as usize;
+                unpadded_data.extend_from_slice(&data[row_start..row_end]);
+            }
+            unpadded_data
+        } else {
+            data.to_vec()
+        };
+
+        drop(data);
+        target.buffer.unmap();
+
+        Ok((width, height, image_data))
+    }
+}
+
+// Resolves once wgpu calls back from `map_async`
+#[derive(Clone, Default)]
+struct Mapped(Arc<Mutex<MapState>>);
+
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+impl Mapped {
+    fn finish(&self, result: Result<(), wgpu::BufferAsyncError>) {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
         }
-        unpadded_data
-    } else {
-        data.to_vec()
+    }
+}
+
+impl Future for Mapped {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Renders a 1-based page. `render_settings` governs everything about the
+/// render, including the background and antialiasing on the GPU.
+pub async fn pdf_to_rgba_image(
+    doc: &Document,
+    page: u32,
+    scale: f32,
+    render_settings: &RenderSettings,
+) -> Result<RgbaImage, RasterError> {
+    let (width, height, data) = pdf_to_rgba_bytes(doc, page, scale, render_settings).await?;
+    Ok(ImageBuffer::from_raw(width, height, data)
+        .ok_or_else(|| eyre!("Failed to create image buffer"))?)
+}
+
+/// Renders a 1-based page like [`pdf_to_rgba_image`], returning its width,
+/// height and tightly packed RGBA rows for callers that don't use `image`.
+pub async fn pdf_to_rgba_bytes(
+    doc: &Document,
+    page: u32,
+    scale: f32,
+    render_settings: &RenderSettings,
+) -> Result<(u32, u32, Vec<u8>), RasterError> {
+    let mut renderer = OffscreenRenderer::new(scale, render_settings.clone()).await?;
+    renderer.render_pdf(doc, page)?;
+    renderer.read_rgba_bytes().await
+}
+
+/// Renders a range of 1-based pages stacked top to bottom, `gap` pixels
+/// apart, into one image as wide as the widest page. Narrower pages are
+/// centered, and the space around them is the background color.
+pub async fn pdf_to_rgba_image_range(
+    doc: &Document,
+    pages: Range<u32>,
+    scale: f32,
+    gap: u32,
+    render_settings: &RenderSettings,
+) -> Result<RgbaImage, RasterError> {
+    let mut renderer = OffscreenRenderer::new(scale, render_settings.clone()).await?;
+    let fonts = FontCache::new(render_settings);
+    let mut images = Vec::new();
+    for page in pages {
+        renderer.render_with_fonts(doc, page, &fonts)?;
+        images.push(renderer.read_rgba_image().await?);
+    }
+
+    let width = images.iter().map(|image| image.width()).max().unwrap_or(0);
+    let height = images.iter().map(|image| image.height()).sum::<u32>()
+        + gap * (images.len() as u32).saturating_sub(1);
+    let background = render_settings
+        .background
+        .unwrap_or(Color::TRANSPARENT)
+        .to_rgba8()
+        .to_u8_array();
+    let mut stacked = RgbaImage::from_pixel(width, height, image::Rgba(background));
+    let mut y = 0;
+    for image in &images {
+        let x = (width - image.width()) / 2;
+        image::imageops::replace(&mut stacked, image, x as i64, y as i64);
+        y += image.height() + gap;
+    }
+    Ok(stacked)
+}
+
+/// Renders a 1-based page as large as fits within `max_width` x
+/// `max_height` pixels without changing its aspect ratio, e.g. for
+/// thumbnails. The image is exactly that size, with the page centered on
+/// the background color; `render_settings.dpi` is ignored.
+pub async fn pdf_to_rgba_image_fit(
+    doc: &Document,
+    page: u32,
+    max_width: u32,
+    max_height: u32,
+    render_settings: &RenderSettings,
+) -> Result<RgbaImage, RasterError> {
+    let size = dimensions(doc, doc.get_dictionary(validate_page(doc, page)?)?)?;
+    let scale = (max_width as f32 / size.0).min(max_height as f32 / size.1);
+    let settings = RenderSettings {
+        dpi: None,
+        ..render_settings.clone()
     };
+    let image = pdf_to_rgba_image(doc, page, scale, &settings).await?;
 
-    let buffer: RgbaImage = ImageBuffer::from_raw(width, height, image_data)
-        .ok_or_else(|| eyre!("Failed to create image buffer"))?;
+    let background = settings
+        .background
+        .unwrap_or(Color::TRANSPARENT)
+        .to_rgba8()
+        .to_u8_array();
+    let mut fitted = RgbaImage::from_pixel(max_width, max_height, image::Rgba(background));
+    let x = max_width.saturating_sub(image.width()) / 2;
+    let y = max_height.saturating_sub(image.height()) / 2;
+    image::imageops::replace(&mut fitted, &image, x as i64, y as i64);
+    Ok(fitted)
+}
+
+/// Renders a page synchronously by driving [`pdf_to_rgba_image`] on its own
+/// `pollster` executor, so it must not be called from within an async runtime.
+#[cfg(feature = "blocking")]
+pub fn render_page_blocking(
+    doc: &Document,
+    page: u32,
+    scale: f32,
+    settings: &RenderSettings,
+) -> Result<RgbaImage, RasterError> {
+    pollster::block_on(pdf_to_rgba_image(doc, page, scale, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Dictionary, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    // Vello's CPU pipeline on whichever adapter there is, since tests may run
+    // without a GPU, over white and without antialiasing
+    fn settings() -> RenderSettings {
+        RenderSettings {
+            use_cpu: true,
+            anti_alias: false,
+            background: Some(Color::WHITE),
+            ..Default::default()
+        }
+    }
+
+    // A renderer with `settings`, or `None` when there's no adapter at all
+    fn renderer(scale: f32) -> Option<OffscreenRenderer> {
+        let renderer = pollster::block_on(OffscreenRenderer::new(scale, settings()));
+        renderer.map_err(|e| eprintln!("Skipping: {e}")).ok()
+    }
+
+    #[test]
+    fn renderers_are_reused_across_page_sizes() {
+        let Some(mut renderer) = renderer(1.) else {
+            return;
+        };
+        let mut doc = document();
+        add_page(&mut doc, Dictionary::new(), "0 0 50 50 re f");
+        let media_box = vec![0.into(), 0.into(), 50.into(), 20.into()];
+        add_page(&mut doc, dictionary! { "MediaBox" => media_box }, "");
+        renderer.render_pdf(&doc, 1).unwrap();
+        let first = renderer.to_rgba_image().unwrap();
+        assert_eq!((first.width(), first.height()), (100, 100));
+        assert_eq!(pixel(&first, 25, 25), BLACK);
+        assert_eq!(pixel(&first, 75, 75), WHITE);
+        renderer.render_pdf(&doc, 2).unwrap();
+        let second = renderer.to_rgba_image().unwrap();
+        assert_eq!((second.width(), second.height()), (50, 20));
+        assert_eq!(pixel(&second, 25, 10), WHITE);
+    }
+
+    #[test]
+    #[cfg(feature = "blocking")]
+    fn render_page_blocking_renders_without_a_runtime() {
+        if renderer(1.).is_none() {
+            return;
+        }
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let image = render_page_blocking(&doc, 1, 2., &settings()).unwrap();
+        assert_eq!((image.width(), image.height()), (200, 200));
+        assert_eq!(pixel(&image, 90, 90), BLACK);
+        assert_eq!(pixel(&image, 110, 110), WHITE);
+    }
+
+    #[test]
+    fn use_cpu_renders_with_vellos_cpu_pipeline() {
+        let Some(renderer) = renderer(1.) else {
+            return;
+        };
+        assert!(renderer.settings.use_cpu);
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let image = pollster::block_on(pdf_to_rgba_image(&doc, 1, 1., &settings())).unwrap();
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 75, 75), WHITE);
+    }
+
+    #[test]
+    fn soft_masks_hide_what_they_cover() {
+        let Some(mut renderer) = renderer(1.) else {
+            return;
+        };
+        renderer
+            .render_pdf(&soft_mask_page("0 0 100 100 re f"), 1)
+            .unwrap();
+        let image = renderer.to_rgba_image().unwrap();
+        assert_eq!(pixel(&image, 25, 50), BLACK);
+        assert_eq!(pixel(&image, 75, 50), WHITE);
+    }
+
+    #[test]
+    fn page_ranges_are_stacked() {
+        if renderer(1.).is_none() {
+            return;
+        }
+        let mut doc = document();
+        add_page(&mut doc, Dictionary::new(), "");
+        let media_box = vec![0.into(), 0.into(), 50.into(), 20.into()];
+        add_page(
+            &mut doc,
+            dictionary! { "MediaBox" => media_box },
+            "0 0 50 20 re f",
+        );
+        let image =
+            pollster::block_on(pdf_to_rgba_image_range(&doc, 1..3, 1., 10, &settings())).unwrap();
+        assert_eq!((image.width(), image.height()), (100, 130));
+        // The narrower second page is centered below the gap
+        assert_eq!(image.get_pixel(50, 105).0, WHITE);
+        assert_eq!(image.get_pixel(50, 120).0, BLACK);
+        assert_eq!(image.get_pixel(20, 120).0, WHITE);
+        assert_eq!(image.get_pixel(30, 120).0, BLACK);
+    }
+
+    #[test]
+    fn pdf_to_rgba_image_uses_the_settings_background() {
+        if renderer(1.).is_none() {
+            return;
+        }
+        let doc = page(Dictionary::new(), "0 0 50 50 re f");
+        let render = |background| {
+            let settings = RenderSettings {
+                background,
+                ..settings()
+            };
+            pollster::block_on(pdf_to_rgba_image(&doc, 1, 1., &settings)).unwrap()
+        };
+        let red = render(Some(Color::from_rgba8(255, 0, 0, 255)));
+        assert_eq!(pixel(&red, 75, 75), [255, 0, 0, 255]);
+        assert_eq!(pixel(&red, 25, 25), BLACK);
+        let transparent = render(None);
+        assert_eq!(pixel(&transparent, 75, 75)[3], 0);
+        assert_eq!(pixel(&transparent, 25, 25), BLACK);
+    }
 
-    drop(data);
-    output_buffer.unmap();
+    #[test]
+    fn unrenderable_pages_are_errors() {
+        let Some(mut renderer) = renderer(1.) else {
+            return;
+        };
+        assert!(renderer.render_pdf(&document(), 1).is_err());
+        let mut doc = document();
+        let media_box = vec![0.into(), 0.into(), 0.into(), 100.into()];
+        add_page(&mut doc, dictionary! { "MediaBox" => media_box }, "");
+        let media_box = vec![0.into(), 0.into(), 100_000.into(), 100.into()];
+        add_page(&mut doc, dictionary! { "MediaBox" => media_box }, "");
+        assert!(renderer.render_pdf(&doc, 1).is_err());
+        assert!(renderer.render_pdf(&doc, 2).is_err());
+    }
 
-    Ok(buffer)
+    #[test]
+    fn render_all_renders_every_page_in_order() {
+        let Some(mut renderer) = renderer(1.) else {
+            return;
+        };
+        let mut doc = document();
+        add_page(&mut doc, Dictionary::new(), "0 0 50 50 re f");
+        let media_box = vec![0.into(), 0.into(), 50.into(), 20.into()];
+        add_page(&mut doc, dictionary! { "MediaBox" => media_box }, "");
+        add_page(&mut doc, Dictionary::new(), "50 50 50 50 re f");
+        let pages: Vec<RgbaImage> = renderer.render_all(&doc).map(Result::unwrap).collect();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pixel(&pages[0], 25, 25), BLACK);
+        assert_eq!(pixel(&pages[0], 75, 75), WHITE);
+        assert_eq!((pages[1].width(), pages[1].height()), (50, 20));
+        assert_eq!(pixel(&pages[2], 25, 25), WHITE);
+        assert_eq!(pixel(&pages[2], 75, 75), BLACK);
+    }
+
+    #[test]
+    fn rgba_bytes_are_tightly_packed() {
+        if renderer(1.).is_none() {
+            return;
+        }
+        // Rows of 50 pixels are padded to 256 bytes on the GPU
+        let mut doc = document();
+        let media_box = vec![0.into(), 0.into(), 50.into(), 20.into()];
+        add_page(
+            &mut doc,
+            dictionary! { "MediaBox" => media_box },
+            "0 0 10 20 re f",
+        );
+        let (width, height, data) =
+            pollster::block_on(pdf_to_rgba_bytes(&doc, 1, 1., &settings())).unwrap();
+        assert_eq!((width, height, data.len()), (50, 20, 50 * 20 * 4));
+        for row in data.chunks(50 * 4) {
+            assert_eq!(row[5 * 4..6 * 4], BLACK);
+            assert_eq!(row[45 * 4..46 * 4], WHITE);
+        }
+    }
+
+    #[test]
+    fn fitted_pages_keep_their_aspect_ratio() {
+        if renderer(1.).is_none() {
+            return;
+        }
+        let mut doc = document();
+        for (w, h) in [(50, 100), (100, 50)] {
+            let media_box = vec![0.into(), 0.into(), w.into(), h.into()];
+            add_page(
+                &mut doc,
+                dictionary! { "MediaBox" => media_box },
+                "0 0 100 100 re f",
+            );
+        }
+        let fit = |page| {
+            pollster::block_on(pdf_to_rgba_image_fit(&doc, page, 40, 40, &settings())).unwrap()
+        };
+        // Portrait pages are 20 x 40 and landscape ones 40 x 20, centered
+        let portrait = fit(1);
+        assert_eq!(portrait.dimensions(), (40, 40));
+        assert_eq!(pixel(&portrait, 20, 20), BLACK);
+        assert_eq!(pixel(&portrait, 12, 2), BLACK);
+        assert_eq!(pixel(&portrait, 8, 20), WHITE);
+        assert_eq!(pixel(&portrait, 32, 20), WHITE);
+        let landscape = fit(2);
+        assert_eq!(landscape.dimensions(), (40, 40));
+        assert_eq!(pixel(&landscape, 20, 20), BLACK);
+        assert_eq!(pixel(&landscape, 2, 12), BLACK);
+        assert_eq!(pixel(&landscape, 20, 8), WHITE);
+        assert_eq!(pixel(&landscape, 20, 32), WHITE);
+    }
 }
@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+
+use kurbo::{Affine, BezPath, Stroke};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use peniko::{BlendMode, BrushRef, Fill, Image};
+
+use crate::canvas::Canvas;
+
+/// The optional content groups, or layers, that the document's default
+/// configuration hides. Usage-based visibility, e.g. for printing, and
+/// visibility expressions (`/VE`) aren't taken into account.
+#[derive(Debug, Default)]
+pub struct OptionalContent {
+    hidden: HashSet<ObjectId>,
+}
+
+impl OptionalContent {
+    pub fn new(doc: &Document) -> Self {
+        let Ok(properties) = doc
+            .catalog()
+            .and_then(|catalog| doc.get_dict_in_dict(catalog, b"OCProperties"))
+        else {
+            return Self::default();
+        };
+        let Ok(default) = doc.get_dict_in_dict(properties, b"D") else {
+            return Self::default();
+        };
+        let groups = |dict: &Dictionary, key: &[u8]| -> HashSet<ObjectId> {
+            dict.get(key)
+                .and_then(|groups| doc.dereference(groups))
+                .and_then(|(_, groups)| groups.as_array())
+                .map(|groups| {
+                    groups
+                        .iter()
+                        .filter_map(|g| g.as_reference().ok())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let hidden = match default.get(b"BaseState").and_then(Object::as_name) {
+            Ok(b"OFF") => {
+                let on = groups(default, b"ON");
+                groups(properties, b"OCGs")
+                    .into_iter()
+                    .filter(|group| !on.contains(group))
+                    .collect()
+            }
+            _ => groups(default, b"OFF"),
+        };
+        OptionalContent { hidden }
+    }
+
+    /// Whether content belonging to `oc`, an optional content group or a
+    /// membership dictionary, is shown.
+    pub fn visible(&self, doc: &Document, oc: &Object) -> bool {
+        let shown = |group: &Object| {
+            !group
+                .as_reference()
+                .is_ok_and(|id| self.hidden.contains(&id))
+        };
+        let Ok((_, Object::Dictionary(dict))) = doc.dereference(oc) else {
+            return true;
+        };
+        if dict.get(b"Type").and_then(Object::as_name).ok() != Some(b"OCMD") {
+            return shown(oc);
+        }
+
+        let Ok(groups) = dict.get(b"OCGs") else {
+            return true;
+        };
+        let groups: Vec<bool> = match doc.dereference(groups) {
+            Ok((_, Object::Array(groups))) => groups.iter().map(shown).collect(),
+            _ => vec![shown(groups)],
+        };
+        if groups.is_empty() {
+            return true;
+        }
+        match dict.get(b"P").and_then(Object::as_name) {
+            Ok(b"AllOn") => groups.iter().all(|on| *on),
+            Ok(b"AnyOff") => groups.iter().any(|on| !on),
+            Ok(b"AllOff") => groups.iter().all(|on| !on),
+            _ => groups.iter().any(|on| *on),
+        }
+    }
+}
+
+/// Passes layers through to another canvas but paints nothing, for content
+/// that's hidden but may still set up clips popped after it ends.
+pub struct Hidden<'a>(pub &'a mut dyn Canvas);
+
+impl Canvas for Hidden<'_> {
+    fn fill(&mut self, _rule: Fill, _transform: Affine, _brush: BrushRef<'_>, _shape: &BezPath) {}
+
+    fn stroke(
+        &mut self,
+        _style: &Stroke,
+        _transform: Affine,
+        _brush: BrushRef<'_>,
+        _shape: &BezPath,
+    ) {
+    }
+
+    fn push_layer(&mut self, blend: BlendMode, alpha: f32, transform: Affine, clip: &BezPath) {
+        self.0.push_layer(blend, alpha, transform, clip);
+    }
+
+    fn pop_layer(&mut self) {
+        self.0.pop_layer();
+    }
+
+    fn draw_image(&mut self, _image: &Image, _transform: Affine) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+    use crate::testing::*;
+
+    // A document with two groups, A and B, and the default configuration
+    // `default` makes from them
+    fn layered(
+        default: impl FnOnce(ObjectId, ObjectId) -> Dictionary,
+    ) -> (Document, ObjectId, ObjectId) {
+        let mut doc = document();
+        let a = doc.add_object(dictionary! { "Type" => "OCG", "Name" => "A" });
+        let b = doc.add_object(dictionary! { "Type" => "OCG", "Name" => "B" });
+        let default = default(a, b);
+        doc.catalog_mut().unwrap().set(
+            "OCProperties",
+            dictionary! { "OCGs" => vec![a.into(), b.into()], "D" => default },
+        );
+        (doc, a, b)
+    }
+
+    fn b_off(_: ObjectId, b: ObjectId) -> Dictionary {
+        dictionary! { "OFF" => vec![b.into()] }
+    }
+
+    #[test]
+    fn the_default_configuration_hides_groups() {
+        let (doc, a, b) = layered(b_off);
+        let optional = OptionalContent::new(&doc);
+        assert!(optional.visible(&doc, &a.into()));
+        assert!(!optional.visible(&doc, &b.into()));
+
+        // With everything off to begin with, only the /ON groups are shown
+        let (doc, a, b) =
+            layered(|a, _| dictionary! { "BaseState" => "OFF", "ON" => vec![a.into()] });
+        let optional = OptionalContent::new(&doc);
+        assert!(optional.visible(&doc, &a.into()));
+        assert!(!optional.visible(&doc, &b.into()));
+    }
+
+    #[test]
+    fn membership_dictionaries_combine_their_groups() {
+        let (doc, a, b) = layered(b_off);
+        let optional = OptionalContent::new(&doc);
+        let membership = |policy: &str| {
+            Object::Dictionary(dictionary! {
+                "Type" => "OCMD",
+                "OCGs" => vec![a.into(), b.into()],
+                "P" => policy,
+            })
+        };
+        assert!(optional.visible(&doc, &membership("AnyOn")));
+        assert!(!optional.visible(&doc, &membership("AllOn")));
+        assert!(optional.visible(&doc, &membership("AnyOff")));
+        assert!(!optional.visible(&doc, &membership("AllOff")));
+    }
+
+    #[test]
+    fn hidden_layers_are_not_drawn() {
+        let (mut doc, a, b) = layered(b_off);
+        add_page(
+            &mut doc,
+            dictionary! {
+                "Resources" => dictionary! {
+                    "Properties" => dictionary! { "L1" => a, "L2" => b },
+                },
+            },
+            "/OC /L1 BDC 0 0 50 50 re f EMC \
+             /OC /L2 BDC 50 50 50 50 re f EMC \
+             /Artifact BMC 0 50 50 50 re f EMC",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 25, 25), BLACK);
+        assert_eq!(pixel(&image, 75, 75), WHITE);
+        assert_eq!(pixel(&image, 25, 75), BLACK);
+    }
+}
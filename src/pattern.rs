@@ -0,0 +1,284 @@
+use eyre::{Result, bail, eyre};
+use kurbo::{Affine, Rect, Shape};
+use lopdf::{Dictionary, Object};
+use peniko::Mix;
+
+use crate::{
+    CTM, Context, GraphicsState, MAX_FORM_DEPTH, Resources, State, canvas::Canvas, concat,
+    content::decode_content, draw_content, get, rect, shading::Shading,
+};
+
+// Drawing cells one at a time gets too slow past this many
+const MAX_CELLS: i64 = 20_000;
+
+/// Fills the current path with a pattern from the Pattern color space. The
+/// path clips the pattern, so even-odd paths fill as nonzero, as `W*` does.
+pub(crate) fn fill(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    state: &State,
+    resources: &Resources,
+    pattern: &Object,
+) -> Result<()> {
+    let doc = cx.doc;
+    let (_, pattern) = doc.dereference(pattern)?;
+    let dict = match pattern {
+        Object::Stream(stream) => &stream.dict,
+        other => other.as_dict()?,
+    };
+    let matrix = match dict.get(b"Matrix") {
+        Ok(matrix) => get(doc, matrix)?,
+        Err(_) => CTM::default(),
+    };
+    // Patterns are placed relative to where the content stream started
+    // rather than the current CTM
+    let ctm = concat(&state.base_ctm, &matrix);
+
+    let gs = &state.gs;
+    let area = gs.path.bounding_box().intersect(cx.scale.bounds());
+    if area.area() <= 0. {
+        return Ok(());
+    }
+    let alpha = gs.non_stroke_color.components[3];
+    let mix = if alpha < 1. { Mix::Normal } else { Mix::Clip };
+    scene.push_layer(mix.into(), alpha, Affine::IDENTITY, &gs.path);
+    let result = match dict.get(b"PatternType")?.as_i64()? {
+        1 => tiling(cx, scene, gs, resources, pattern, &ctm, area),
+        2 => shading(cx, scene, gs, dict, &ctm, area),
+        other => Err(eyre!("Unsupported pattern type {other}")),
+    };
+    scene.pop_layer();
+    result
+}
+
+// Draws a tiling pattern's cell at every step that overlaps `area`, in device space
+fn tiling(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    gs: &GraphicsState,
+    resources: &Resources,
+    pattern: &Object,
+    ctm: &CTM,
+    area: Rect,
+) -> Result<()> {
+    let doc = cx.doc;
+    if cx.depth >= MAX_FORM_DEPTH {
+        bail!("Patterns nested more than {MAX_FORM_DEPTH} deep");
+    }
+    let dict = &pattern.as_stream()?.dict;
+    let [x0, y0, x1, y1] = rect(doc.dereference(dict.get(b"BBox")?)?.1)?;
+    let bbox = Rect::new(x0 as f64, y0 as f64, x1 as f64, y1 as f64);
+    let x_step = dict.get(b"XStep")?.as_float()? as f64;
+    let y_step = dict.get(b"YStep")?.as_float()? as f64;
+    if x_step == 0. || y_step == 0. {
+        bail!("Tiling pattern with a zero step");
+    }
+
+    let to_device = cx.scale.affine(ctm);
+    if to_device.determinant().abs() <= f64::EPSILON {
+        return Ok(());
+    }
+    let covered = to_device.inverse().transform_rect_bbox(area);
+    let (columns, rows) = (
+        cells(covered.x0, covered.x1, bbox.x0, bbox.x1, x_step),
+        cells(covered.y0, covered.y1, bbox.y0, bbox.y1, y_step),
+    );
+    let count = (columns.1 - columns.0 + 1).max(0) * (rows.1 - rows.0 + 1).max(0);
+    if count > MAX_CELLS {
+        bail!("Tiling pattern needs {count} cells, more than {MAX_CELLS}");
+    }
+
+    let raw: Vec<u8> = get(doc, pattern)?;
+    let content = decode_content(&raw)?;
+    let pattern_resources;
+    let pattern_resources = match doc.get_dict_in_dict(dict, b"Resources") {
+        Ok(pattern_dict) => {
+            pattern_resources = Resources::new(doc, pattern_dict, cx.fonts);
+            &pattern_resources
+        }
+        Err(_) => resources,
+    };
+    // Uncolored patterns (PaintType 2) are painted in the current fill color
+    let uncolored = dict.get(b"PaintType").and_then(|p| p.as_i64()).ok() == Some(2);
+    let color = gs.non_stroke_color.with_alpha(1.);
+    let cx = Context {
+        depth: cx.depth + 1,
+        uncolored: cx.uncolored || uncolored,
+        ..cx
+    };
+
+    for row in rows.0..=rows.1 {
+        for column in columns.0..=columns.1 {
+            let offset = CTM {
+                e: (column as f64 * x_step) as f32,
+                f: (row as f64 * y_step) as f32,
+                ..CTM::default()
+            };
+            let mut cell = GraphicsState {
+                ctm: concat(ctm, &offset),
+                luminosity_mask: gs.luminosity_mask,
+                ..Default::default()
+            };
+            if uncolored {
+                cell.non_stroke_color = color;
+                cell.stroke_color = color;
+            }
+            scene.push_layer(
+                Mix::Clip.into(),
+                1.0,
+                cx.scale.affine(&cell.ctm),
+                &bbox.to_path(0.1),
+            );
+            let result = draw_content(
+                cx,
+                scene,
+                &mut State::new(cell),
+                pattern_resources,
+                &content.operations,
+            );
+            scene.pop_layer();
+            result?;
+        }
+    }
+    Ok(())
+}
+
+// Paints a shading pattern's shading over `area`, in device space
+fn shading(
+    cx: Context,
+    scene: &mut dyn Canvas,
+    gs: &GraphicsState,
+    dict: &Dictionary,
+    ctm: &CTM,
+    area: Rect,
+) -> Result<()> {
+    let shading: Shading = get(cx.doc, dict.get(b"Shading")?)?;
+    let transform = cx.scale.affine(ctm);
+    if transform.determinant().abs() <= f64::EPSILON {
+        return Ok(());
+    }
+    let bounds = transform.inverse().transform_rect_bbox(area);
+    shading.draw(scene, transform, bounds, gs.flatness, |color| {
+        gs.paint(color)
+    })
+}
+
+// The steps at which a cell spanning `cell_from..cell_to` overlaps `from..to`
+fn cells(from: f64, to: f64, cell_from: f64, cell_to: f64, step: f64) -> (i64, i64) {
+    let (a, b) = ((from - cell_to) / step, (to - cell_from) / step);
+    (a.min(b).ceil() as i64, a.max(b).floor() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Document, Stream, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    // A page filling its left half, and everything above y = 50, with the
+    // pattern `P1` set by `fill`
+    fn pattern_page(pattern: impl FnOnce(&mut Document) -> Object, fill: &str) -> Document {
+        let mut doc = document();
+        let pattern = pattern(&mut doc);
+        add_page(
+            &mut doc,
+            dictionary! { "Resources" => dictionary! { "Pattern" => dictionary! { "P1" => pattern } } },
+            &format!("{fill} 0 0 50 100 re 50 50 50 50 re f"),
+        );
+        doc
+    }
+
+    fn tiles(paint_type: i64, content: &str) -> impl FnOnce(&mut Document) -> Object {
+        move |doc| {
+            doc.add_object(Stream::new(
+                dictionary! {
+                    "PatternType" => 1,
+                    "PaintType" => paint_type,
+                    "TilingType" => 1,
+                    "BBox" => vec![0.into(), 0.into(), 20.into(), 20.into()],
+                    "XStep" => 20,
+                    "YStep" => 20,
+                },
+                content.as_bytes().to_vec(),
+            ))
+            .into()
+        }
+    }
+
+    #[test]
+    fn tiling_patterns_repeat_their_cell() {
+        let doc = pattern_page(tiles(1, "0 0 10 10 re f"), "/Pattern cs /P1 scn");
+        let image = render(&doc);
+        for (x, y) in [(5, 5), (25, 5), (45, 45), (5, 85), (65, 65), (85, 85)] {
+            assert_eq!(pixel(&image, x, y), BLACK, "at {x}, {y}");
+        }
+        for (x, y) in [(15, 5), (5, 15), (35, 35)] {
+            assert_eq!(pixel(&image, x, y), WHITE, "at {x}, {y}");
+        }
+        // The path clips the pattern
+        assert_eq!(pixel(&image, 65, 25), WHITE);
+    }
+
+    #[test]
+    fn uncolored_tiling_patterns_paint_in_the_fill_color() {
+        let doc = pattern_page(
+            tiles(2, "1 1 0 rg 0 0 10 10 re f"),
+            "/Pattern cs 1 0 0 /P1 scn",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 5, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 15, 5), WHITE);
+    }
+
+    #[test]
+    fn patterns_are_placed_in_the_pages_space() {
+        // Scaling the CTM scales the path but not the cells
+        let doc = pattern_page(
+            tiles(1, "0 0 10 10 re f"),
+            "/Pattern cs /P1 scn 2 0 0 2 0 0 cm",
+        );
+        let image = render(&doc);
+        assert_eq!(pixel(&image, 5, 5), BLACK);
+        assert_eq!(pixel(&image, 15, 5), WHITE);
+        assert_eq!(pixel(&image, 85, 25), BLACK);
+    }
+
+    #[test]
+    fn shading_patterns_fill_with_their_shading() {
+        let shading = |matrix: Option<f32>| {
+            move |doc: &mut Document| {
+                let mut pattern = dictionary! {
+                    "PatternType" => 2,
+                    "Shading" => gray_shading(2, &[0., 0., 100., 0.], true, 0., 1.),
+                };
+                if let Some(scale) = matrix {
+                    pattern.set(
+                        "Matrix",
+                        vec![
+                            scale.into(),
+                            0.into(),
+                            0.into(),
+                            scale.into(),
+                            0.into(),
+                            0.into(),
+                        ],
+                    );
+                }
+                doc.add_object(pattern).into()
+            }
+        };
+        let image = render(&pattern_page(shading(None), "/Pattern cs /P1 scn"));
+        let gray = |x, y| pixel(&image, x, y)[0];
+        assert!(gray(10, 50) < gray(40, 50));
+        // Pixels are shaded at their centers
+        assert!(gray(40, 50).abs_diff(103) <= 1);
+        assert!(gray(75, 75).abs_diff(193) <= 1);
+        assert_eq!(pixel(&image, 75, 25), WHITE);
+
+        // The pattern's matrix scales the shading
+        let image = render(&pattern_page(shading(Some(0.5)), "/Pattern cs /P1 scn"));
+        assert!(pixel(&image, 40, 50)[0].abs_diff(207) <= 1);
+        assert_eq!(pixel(&image, 75, 75), WHITE);
+    }
+}
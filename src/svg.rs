@@ -0,0 +1,371 @@
+use std::io::Cursor;
+
+use eyre::Result;
+use image::{ImageFormat, RgbaImage};
+use kurbo::{Affine, BezPath, Cap, Join, Stroke};
+use lopdf::Document;
+use peniko::{
+    BlendMode, BrushRef, Color, Compose, Extend, Fill, GradientKind, Image, ImageQuality, Mix,
+    color::Srgb,
+};
+
+use crate::{
+    FontCache, RasterError, RenderSettings, canvas::Canvas, dimensions, paint_page, validate_page,
+};
+
+/// Exports a 1-based page as an SVG document, one `<path>` per fill or
+/// stroke with glyphs as outlines. Sizes are in points unless the settings
+/// ask for a resolution.
+pub fn page_to_svg(
+    doc: &Document,
+    page: u32,
+    settings: &RenderSettings,
+) -> Result<String, RasterError> {
+    let size = dimensions(doc, doc.get_dictionary(validate_page(doc, page)?)?)?;
+    let scale = settings.scale(1.);
+    let width = (size.0 * scale) as u32;
+    let height = (size.1 * scale) as u32;
+
+    let mut canvas = SvgCanvas::default();
+    paint_page(
+        doc,
+        &mut canvas,
+        page,
+        width,
+        height,
+        settings,
+        &FontCache::new(settings),
+    )?;
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\">\n{}</svg>\n",
+        canvas.body
+    ))
+}
+
+#[derive(Default)]
+struct SvgCanvas {
+    body: String,
+    layers: Vec<Layer>,
+    // How many of the layers are being left out
+    hidden: usize,
+    ids: usize,
+}
+
+enum Layer {
+    Group,
+    Hidden,
+}
+
+impl SvgCanvas {
+    fn id(&mut self, prefix: &str) -> String {
+        self.ids += 1;
+        format!("{prefix}{}", self.ids)
+    }
+
+    // The value of a fill or stroke attribute, defining a gradient if needed
+    fn paint(&mut self, brush: BrushRef<'_>) -> Option<(String, f32)> {
+        match brush {
+            BrushRef::Solid(color) => Some((hex(color), color.components[3])),
+            BrushRef::Gradient(gradient) => {
+                let id = self.id("g");
+                let spread = match gradient.extend {
+                    Extend::Pad => "pad",
+                    Extend::Repeat => "repeat",
+                    Extend::Reflect => "reflect",
+                };
+                let stops: String = gradient
+                    .stops
+                    .iter()
+                    .map(|stop| {
+                        let color = stop.color.to_alpha_color::<Srgb>();
+                        format!(
+                            "<stop offset=\"{}\" stop-color=\"{}\" stop-opacity=\"{}\"/>",
+                            stop.offset,
+                            hex(color),
+                            color.components[3]
+                        )
+                    })
+                    .collect();
+                let element = match gradient.kind {
+                    GradientKind::Linear { start, end } => format!(
+                        "<linearGradient id=\"{id}\" gradientUnits=\"userSpaceOnUse\" \
+                         spreadMethod=\"{spread}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\">\
+                         {stops}</linearGradient>",
+                        start.x, start.y, end.x, end.y
+                    ),
+                    GradientKind::Radial {
+                        start_center,
+                        start_radius,
+                        end_center,
+                        end_radius,
+                    } => format!(
+                        "<radialGradient id=\"{id}\" gradientUnits=\"userSpaceOnUse\" \
+                         spreadMethod=\"{spread}\" fx=\"{}\" fy=\"{}\" fr=\"{start_radius}\" \
+                         cx=\"{}\" cy=\"{}\" r=\"{end_radius}\">{stops}</radialGradient>",
+                        start_center.x, start_center.y, end_center.x, end_center.y
+                    ),
+                    GradientKind::Sweep { .. } => return None,
+                };
+                self.body.push_str(&format!("<defs>{element}</defs>\n"));
+                Some((format!("url(#{id})"), 1.))
+            }
+            BrushRef::Image(_) => None,
+        }
+    }
+}
+
+impl Canvas for SvgCanvas {
+    fn fill(&mut self, rule: Fill, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        if self.hidden > 0 {
+            return;
+        }
+        let Some((fill, opacity)) = self.paint(brush) else {
+            return;
+        };
+        let mut attributes = format!("fill=\"{fill}\"");
+        if opacity < 1. {
+            attributes.push_str(&format!(" fill-opacity=\"{opacity}\""));
+        }
+        if rule == Fill::EvenOdd {
+            attributes.push_str(" fill-rule=\"evenodd\"");
+        }
+        self.body.push_str(&format!(
+            "<path d=\"{}\" {attributes}{}/>\n",
+            shape.to_svg(),
+            matrix(transform)
+        ));
+    }
+
+    fn stroke(&mut self, style: &Stroke, transform: Affine, brush: BrushRef<'_>, shape: &BezPath) {
+        if self.hidden > 0 {
+            return;
+        }
+        let Some((stroke, opacity)) = self.paint(brush) else {
+            return;
+        };
+        let mut attributes = format!(
+            "fill=\"none\" stroke=\"{stroke}\" stroke-width=\"{}\" stroke-miterlimit=\"{}\"",
+            style.width, style.miter_limit
+        );
+        if opacity < 1. {
+            attributes.push_str(&format!(" stroke-opacity=\"{opacity}\""));
+        }
+        attributes.push_str(match style.start_cap {
+            Cap::Butt => "",
+            Cap::Round => " stroke-linecap=\"round\"",
+            Cap::Square => " stroke-linecap=\"square\"",
+        });
+        attributes.push_str(match style.join {
+            Join::Miter => "",
+            Join::Round => " stroke-linejoin=\"round\"",
+            Join::Bevel => " stroke-linejoin=\"bevel\"",
+        });
+        if !style.dash_pattern.is_empty() {
+            let dashes: Vec<String> = style.dash_pattern.iter().map(f64::to_string).collect();
+            attributes.push_str(&format!(
+                " stroke-dasharray=\"{}\" stroke-dashoffset=\"{}\"",
+                dashes.join(" "),
+                style.dash_offset
+            ));
+        }
+        self.body.push_str(&format!(
+            "<path d=\"{}\" {attributes}{}/>\n",
+            shape.to_svg(),
+            matrix(transform)
+        ));
+    }
+
+    fn push_layer(&mut self, blend: BlendMode, alpha: f32, transform: Affine, clip: &BezPath) {
+        // SVG has no way to composite a group onto what's already been drawn
+        // other than by painting over it, so layers that do, like soft masks,
+        // are left out
+        if self.hidden > 0 || !matches!(blend.compose, Compose::SrcOver) {
+            self.hidden += 1;
+            self.layers.push(Layer::Hidden);
+            return;
+        }
+        let id = self.id("c");
+        self.body.push_str(&format!(
+            "<clipPath id=\"{id}\"><path d=\"{}\"{}/></clipPath>\n",
+            clip.to_svg(),
+            matrix(transform)
+        ));
+        let mut attributes = format!("clip-path=\"url(#{id})\"");
+        if alpha < 1. {
+            attributes.push_str(&format!(" opacity=\"{alpha}\""));
+        }
+        if let Some(mode) = mix_blend_mode(blend.mix) {
+            attributes.push_str(&format!(" style=\"mix-blend-mode:{mode}\""));
+        }
+        self.body.push_str(&format!("<g {attributes}>\n"));
+        self.layers.push(Layer::Group);
+    }
+
+    fn pop_layer(&mut self) {
+        match self.layers.pop() {
+            Some(Layer::Group) => self.body.push_str("</g>\n"),
+            Some(Layer::Hidden) => self.hidden -= 1,
+            None => {}
+        }
+    }
+
+    fn draw_image(&mut self, image: &Image, transform: Affine) {
+        if self.hidden > 0 {
+            return;
+        }
+        let Some(pixels) =
+            RgbaImage::from_raw(image.width, image.height, image.data.data().to_vec())
+        else {
+            return;
+        };
+        let mut png = Vec::new();
+        if let Err(e) = pixels.write_to(&mut Cursor::new(&mut png), ImageFormat::Png) {
+            log::warn!("Failed to encode image: {e}");
+            return;
+        }
+        let mut attributes = String::new();
+        if image.alpha < 1. {
+            attributes.push_str(&format!(" opacity=\"{}\"", image.alpha));
+        }
+        if image.quality == ImageQuality::Low {
+            attributes.push_str(" image-rendering=\"pixelated\"");
+        }
+        self.body.push_str(&format!(
+            "<image width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" \
+             href=\"data:image/png;base64,{}\"{attributes}{}/>\n",
+            image.width,
+            image.height,
+            base64(&png),
+            matrix(transform)
+        ));
+    }
+}
+
+fn matrix(transform: Affine) -> String {
+    if transform == Affine::IDENTITY {
+        return String::new();
+    }
+    let [a, b, c, d, e, f] = transform.as_coeffs();
+    format!(" transform=\"matrix({a} {b} {c} {d} {e} {f})\"")
+}
+
+fn hex(color: Color) -> String {
+    let [r, g, b, _] = color.to_rgba8().to_u8_array();
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn mix_blend_mode(mix: Mix) -> Option<&'static str> {
+    Some(match mix {
+        Mix::Normal | Mix::Clip => return None,
+        Mix::Multiply => "multiply",
+        Mix::Screen => "screen",
+        Mix::Overlay => "overlay",
+        Mix::Darken => "darken",
+        Mix::Lighten => "lighten",
+        Mix::ColorDodge => "color-dodge",
+        Mix::ColorBurn => "color-burn",
+        Mix::HardLight => "hard-light",
+        Mix::SoftLight => "soft-light",
+        Mix::Difference => "difference",
+        Mix::Exclusion => "exclusion",
+        Mix::Hue => "hue",
+        Mix::Saturation => "saturation",
+        Mix::Color => "color",
+        Mix::Luminosity => "luminosity",
+    })
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Dictionary, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn pages_are_exported_as_paths() {
+        let doc = page(
+            Dictionary::new(),
+            "1 0 0 rg 10 10 20 20 re f 0 0 1 RG 2 w 1 J [3] 0 d 0 0 m 50 50 l S",
+        );
+        let svg = page_to_svg(&doc, 1, &RenderSettings::default()).unwrap();
+        assert!(svg.starts_with(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"100\" height=\"100\" \
+             viewBox=\"0 0 100 100\">\n<path d=\"M0,0 L100,0 L100,100 L0,100 Z\" fill=\"#ffffff\"/>\n"
+        ));
+        assert!(svg.contains("<path d=\"M10,90 L30,90 L30,70 L10,70 Z\" fill=\"#ff0000\"/>"));
+        assert!(svg.contains(
+            "<path d=\"M0,0 L50,50\" fill=\"none\" stroke=\"#0000ff\" stroke-width=\"2\" \
+             stroke-miterlimit=\"10\" stroke-linecap=\"round\" stroke-dasharray=\"3\" \
+             stroke-dashoffset=\"0\" transform=\"matrix(1 0 0 -1 0 100)\"/>"
+        ));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn layers_become_clipped_groups() {
+        let doc = page(
+            dictionary! { "ExtGState" => dictionary! {
+                "GS1" => dictionary! { "BM" => "Multiply", "ca" => 0.5 },
+            } },
+            "0 0 50 50 re W n /GS1 gs 0 0 100 100 re f",
+        );
+        let settings = RenderSettings {
+            background: None,
+            ..Default::default()
+        };
+        let svg = page_to_svg(&doc, 1, &settings).unwrap();
+        assert!(svg.contains(
+            "<clipPath id=\"c1\"><path d=\"M0,100 L50,100 L50,50 L0,50 Z\"/></clipPath>"
+        ));
+        assert!(svg.contains("<g clip-path=\"url(#c1)\">"));
+        assert!(svg.contains("style=\"mix-blend-mode:multiply\""));
+        assert!(svg.contains("fill-opacity=\"0.5\""));
+        assert_eq!(svg.matches("<g ").count(), svg.matches("</g>").count());
+    }
+
+    #[test]
+    fn soft_masks_are_left_out() {
+        let svg = page_to_svg(
+            &soft_mask_page("0 0 100 100 re f"),
+            1,
+            &RenderSettings::default(),
+        )
+        .unwrap();
+        // The page's fill is kept, but not the mask's
+        assert!(svg.contains("fill=\"#000000\""));
+        assert_eq!(svg.matches("<path").count(), 3);
+        assert_eq!(svg.matches("<g ").count(), svg.matches("</g>").count());
+    }
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,479 @@
+use image::RgbaImage;
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+
+use crate::{RenderSettings, cpu::render_page_cpu};
+
+pub const WHITE: [u8; 4] = [255, 255, 255, 255];
+pub const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// A document with a catalog and an empty page tree.
+pub fn document() -> Document {
+    let mut doc = Document::with_version("1.7");
+    let pages = doc.add_object(dictionary! {
+        "Type" => "Pages",
+        "Kids" => Vec::<Object>::new(),
+        "Count" => 0,
+    });
+    let catalog = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages,
+    });
+    doc.trailer.set("Root", catalog);
+    doc
+}
+
+/// Appends a page painting `content` to a [`document`], with the entries in
+/// `page`, such as /Resources, and a 100 x 100 point /MediaBox unless it
+/// has its own.
+pub fn add_page(doc: &mut Document, mut page: Dictionary, content: &str) -> ObjectId {
+    let pages = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Pages"))
+        .and_then(Object::as_reference)
+        .expect("the document has a page tree");
+    let contents = doc.add_object(Stream::new(dictionary! {}, content.as_bytes().to_vec()));
+    page.set("Type", "Page");
+    page.set("Parent", pages);
+    page.set("Contents", contents);
+    if !page.has(b"MediaBox") {
+        page.set("MediaBox", vec![0.into(), 0.into(), 100.into(), 100.into()]);
+    }
+    let id = doc.add_object(page);
+
+    let tree = doc.get_dictionary_mut(pages).expect("the page tree exists");
+    let count = tree.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+    tree.set("Count", count + 1);
+    if let Ok(Object::Array(kids)) = tree.get_mut(b"Kids") {
+        kids.push(id.into());
+    }
+    id
+}
+
+/// A document whose only page is 100 x 100 points and paints `content`
+/// with `resources`.
+pub fn page(resources: Dictionary, content: &str) -> Document {
+    let mut doc = document();
+    add_page(&mut doc, dictionary! { "Resources" => resources }, content);
+    doc
+}
+
+/// A document whose only page sets /GS1, a luminosity soft mask that's
+/// white over the left half of the page and black elsewhere, and then
+/// paints `content`.
+pub fn soft_mask_page(content: &str) -> Document {
+    let mut doc = document();
+    let group = doc.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 100.into(), 100.into()],
+            "Group" => dictionary! { "S" => "Transparency", "CS" => "DeviceGray" },
+        },
+        b"1 g 0 0 50 100 re f".to_vec(),
+    ));
+    let gs = dictionary! {
+        "Type" => "ExtGState",
+        "SMask" => dictionary! { "Type" => "Mask", "S" => "Luminosity", "G" => group },
+    };
+    add_page(
+        &mut doc,
+        dictionary! { "Resources" => dictionary! { "ExtGState" => dictionary! { "GS1" => gs } } },
+        &format!("/GS1 gs {content}"),
+    );
+    doc
+}
+
+/// A Type3 font whose `A` is a square filling the em and whose space is
+/// half an em wide and empty. The square is declared with `d1`, so it's
+/// painted in the text's color.
+pub fn square_font(doc: &mut Document) -> ObjectId {
+    let square = doc.add_object(Stream::new(
+        dictionary! {},
+        b"1000 0 0 0 1000 1000 d1 0 0 1000 1000 re f".to_vec(),
+    ));
+    let space = doc.add_object(Stream::new(dictionary! {}, b"500 0 d0".to_vec()));
+    let mut widths: Vec<Object> = vec![0.into(); 34];
+    widths[0] = 500.into();
+    widths[33] = 1000.into();
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type3",
+        "FontMatrix" => vec![0.001.into(), 0.into(), 0.into(), 0.001.into(), 0.into(), 0.into()],
+        "FontBBox" => vec![0.into(), 0.into(), 1000.into(), 1000.into()],
+        "CharProcs" => dictionary! { "A" => square, "space" => space },
+        "Encoding" => dictionary! {
+            "Differences" => vec![
+                32.into(),
+                Object::Name(b"space".to_vec()),
+                65.into(),
+                Object::Name(b"A".to_vec()),
+            ],
+        },
+        "FirstChar" => 32,
+        "LastChar" => 65,
+        "Widths" => widths,
+    })
+}
+
+/// A document whose only page paints `content` with [`square_font`] as /F1.
+pub fn text_page(content: &str) -> Document {
+    font_page(square_font, content)
+}
+
+/// A document whose only page paints `content` with the font `font` adds
+/// as /F1.
+pub fn font_page(font: impl FnOnce(&mut Document) -> ObjectId, content: &str) -> Document {
+    let mut doc = document();
+    let font = font(&mut doc);
+    add_page(
+        &mut doc,
+        dictionary! { "Resources" => dictionary! { "Font" => dictionary! { "F1" => font } } },
+        content,
+    );
+    doc
+}
+
+/// An embedded simple TrueType font made from [`truetype_program`].
+pub fn truetype_font(doc: &mut Document) -> ObjectId {
+    let program = truetype_program();
+    let file = doc.add_object(Stream::new(
+        dictionary! { "Length1" => program.len() as i64 },
+        program,
+    ));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Squares",
+        "Flags" => 32,
+        "FontFile2" => file,
+    });
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "TrueType",
+        "BaseFont" => "Squares",
+        "FirstChar" => 65,
+        "LastChar" => 66,
+        "Widths" => vec![1000.into(), 600.into()],
+        "FontDescriptor" => descriptor,
+    })
+}
+
+/// An embedded Type1 font made from [`type1_program`], using the
+/// program's built-in encoding.
+pub fn type1_font(doc: &mut Document) -> ObjectId {
+    let (program, length1) = type1_program();
+    let file = doc.add_object(Stream::new(
+        dictionary! {
+            "Length1" => length1 as i64,
+            "Length2" => (program.len() - length1) as i64,
+            "Length3" => 0,
+        },
+        program,
+    ));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Squares",
+        "Flags" => 4,
+        "FontFile" => file,
+    });
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Squares",
+        "FirstChar" => 65,
+        "LastChar" => 65,
+        "Widths" => vec![1000.into()],
+        "FontDescriptor" => descriptor,
+    })
+}
+
+/// A Type1 font program whose `A` is a square filling the em, and the
+/// length of its cleartext part.
+pub fn type1_program() -> (Vec<u8>, usize) {
+    // Encrypts `plain` after four zero bytes, as eexec and charstrings are
+    let encrypt = |plain: &[u8], mut r: u16| -> Vec<u8> {
+        [0; 4]
+            .iter()
+            .chain(plain)
+            .map(|p| {
+                let c = p ^ (r >> 8) as u8;
+                r = (c as u16)
+                    .wrapping_add(r)
+                    .wrapping_mul(52845)
+                    .wrapping_add(22719);
+                c
+            })
+            .collect()
+    };
+    // 0 1000 hsbw 0 0 rmoveto 1000 0 rlineto 0 1000 rlineto -1000 0 rlineto
+    // closepath endchar
+    let square = [
+        139, 250, 124, 13, 139, 139, 21, 250, 124, 139, 5, 139, 250, 124, 5, 254, 124, 139, 5, 9,
+        14,
+    ];
+    let notdef = [139, 139, 13, 14];
+
+    let clear = b"%!FontType1-1.0: Squares\n/Encoding 256 array\n\
+        0 1 255 {1 index exch /.notdef put} for\ndup 65 /A put\nreadonly def\n\
+        currentfile eexec\n";
+    let mut private = b"dup /Private 8 dict dup begin /lenIV 4 def\n\
+        2 index /CharStrings 2 dict dup begin\n"
+        .to_vec();
+    for (name, charstring) in [("/.notdef", &notdef[..]), ("/A", &square[..])] {
+        let charstring = encrypt(charstring, 4330);
+        private.extend(format!("{name} {} RD ", charstring.len()).bytes());
+        private.extend(charstring);
+        private.extend(b" ND\n");
+    }
+    private.extend(b"end\n");
+
+    let mut program = clear.to_vec();
+    program.extend(encrypt(&private, 55665));
+    (program, clear.len())
+}
+
+/// An embedded simple font made from [`cff_program`], as a bare CFF
+/// `/FontFile3`.
+pub fn cff_font(doc: &mut Document) -> ObjectId {
+    let file = doc.add_object(Stream::new(
+        dictionary! { "Subtype" => "Type1C" },
+        cff_program(),
+    ));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Squares",
+        "Flags" => 4,
+        "FontFile3" => file,
+    });
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Squares",
+        "FirstChar" => 65,
+        "LastChar" => 65,
+        "Widths" => vec![1000.into()],
+        "FontDescriptor" => descriptor,
+    })
+}
+
+/// A CFF font program with the standard encoding whose `A`, glyph 1, is a
+/// square filling the em.
+pub fn cff_program() -> Vec<u8> {
+    // An INDEX of `items`, with one byte offsets
+    let index = |items: &[&[u8]]| -> Vec<u8> {
+        let mut index = (items.len() as u16).to_be_bytes().to_vec();
+        if items.is_empty() {
+            return index;
+        }
+        index.push(1);
+        let mut offset = 1;
+        index.push(offset);
+        for item in items {
+            offset += item.len() as u8;
+            index.push(offset);
+        }
+        index.extend(items.concat());
+        index
+    };
+    // A DICT operand in its five byte form, so offsets don't change its size
+    let number = |n: usize| -> Vec<u8> {
+        let mut number = vec![29];
+        number.extend((n as i32).to_be_bytes());
+        number
+    };
+    let notdef: &[u8] = &[14];
+    // 1000 0 0 rmoveto 1000 0 rlineto 0 1000 rlineto -1000 0 rlineto endchar,
+    // its width given before the move
+    let square: &[u8] = &[
+        250, 124, 139, 139, 21, 250, 124, 139, 5, 139, 250, 124, 5, 254, 124, 139, 5, 14,
+    ];
+    let charstrings = index(&[notdef, square]);
+    // nominalWidthX 0
+    let private = [139, 21];
+
+    let names = index(&[b"Squares"]);
+    let top = |charset_at, charstrings_at, private_at| {
+        let mut top = number(charset_at);
+        top.push(15);
+        top.extend(number(charstrings_at));
+        top.push(17);
+        top.extend(number(private.len()));
+        top.extend(number(private_at));
+        top.push(18);
+        top
+    };
+    // The header, names, top DICT and the empty string and subroutine INDEXes
+    let charset_at = 4 + names.len() + index(&[&top(0, 0, 0)]).len() + 2 + 2;
+    // Glyph 1 is the standard string `A`
+    let charset = [0, 0, 34];
+    let charstrings_at = charset_at + charset.len();
+    let private_at = charstrings_at + charstrings.len();
+
+    let mut cff = vec![1, 0, 4, 1];
+    cff.extend(names);
+    cff.extend(index(&[&top(charset_at, charstrings_at, private_at)]));
+    // No strings and no global subroutines
+    cff.extend(index(&[]));
+    cff.extend(index(&[]));
+    cff.extend(charset);
+    cff.extend(charstrings);
+    cff.extend(private);
+    cff
+}
+
+/// An embedded Identity-H composite font over [`truetype_program`], whose
+/// CIDs are its glyph ids, with the CIDFont entries in `descendant`, such
+/// as /W and /DW.
+pub fn cid_font(doc: &mut Document, mut descendant: Dictionary) -> ObjectId {
+    let program = truetype_program();
+    let file = doc.add_object(Stream::new(dictionary! {}, program));
+    let descriptor = doc.add_object(dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => "Squares",
+        "Flags" => 4,
+        "FontFile2" => file,
+    });
+    descendant.set("Type", "Font");
+    descendant.set("Subtype", "CIDFontType2");
+    descendant.set("BaseFont", "Squares");
+    descendant.set("FontDescriptor", descriptor);
+    let descendant = doc.add_object(descendant);
+    doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => "Squares",
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => vec![descendant.into()],
+    })
+}
+
+/// A TrueType font program 1000 units to the em whose `A`, glyph 1, is a
+/// square filling the em, and whose `B`, glyph 2, is the em's left half
+/// with an advance of 600 units.
+pub fn truetype_program() -> Vec<u8> {
+    let be16 = |values: &[i32]| -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| (*v as u16).to_be_bytes())
+            .collect()
+    };
+    // A simple glyph of one contour through `points`, all on the curve
+    let glyph = |points: &[(i32, i32)]| -> Vec<u8> {
+        let (xs, ys): (Vec<i32>, Vec<i32>) = points.iter().copied().unzip();
+        let mut glyph = be16(&[
+            1,
+            *xs.iter().min().unwrap(),
+            *ys.iter().min().unwrap(),
+            *xs.iter().max().unwrap(),
+            *ys.iter().max().unwrap(),
+            points.len() as i32 - 1,
+            0,
+        ]);
+        glyph.extend(std::iter::repeat_n(1, points.len()));
+        let deltas = |values: &[i32]| {
+            let mut last = 0;
+            let deltas: Vec<i32> = values
+                .iter()
+                .map(|v| std::mem::replace(&mut last, *v))
+                .zip(values)
+                .map(|(previous, v)| v - previous)
+                .collect();
+            be16(&deltas)
+        };
+        glyph.extend(deltas(&xs));
+        glyph.extend(deltas(&ys));
+        glyph
+    };
+    let glyphs = [
+        Vec::new(),
+        glyph(&[(0, 0), (0, 1000), (1000, 1000), (1000, 0)]),
+        glyph(&[(0, 0), (0, 1000), (500, 1000), (500, 0)]),
+    ];
+    let mut glyf = Vec::new();
+    let mut loca = Vec::new();
+    for glyph in &glyphs {
+        loca.extend((glyf.len() as u32).to_be_bytes());
+        glyf.extend(glyph);
+    }
+    loca.extend((glyf.len() as u32).to_be_bytes());
+
+    let mut head = be16(&[1, 0, 1, 0, 0, 0, 0x5F0F, 0x3CF5, 0, 1000]);
+    head.extend([0; 16]);
+    head.extend(be16(&[0, 0, 1000, 1000, 0, 8, 2, 1, 0]));
+    let mut hhea = be16(&[1, 0, 800, -200, 0, 1000, 0, 0, 1000, 1, 0, 0]);
+    hhea.extend(be16(&[0, 0, 0, 0, 0, glyphs.len() as i32]));
+    let maxp = be16(&[0, 0x5000, glyphs.len() as i32]);
+    let hmtx = be16(&[500, 0, 1000, 0, 600, 0]);
+    // A format 4 Unicode cmap mapping A and B to glyphs 1 and 2
+    let mut cmap = be16(&[0, 1, 3, 1, 0, 12]);
+    cmap.extend(be16(&[4, 32, 0, 4, 4, 1, 0]));
+    cmap.extend(be16(&[66, 0xFFFF, 0, 65, 0xFFFF, -64, 1, 0, 0]));
+
+    let mut tables = [
+        (b"cmap", cmap),
+        (b"glyf", glyf),
+        (b"head", head),
+        (b"hhea", hhea),
+        (b"hmtx", hmtx),
+        (b"loca", loca),
+        (b"maxp", maxp),
+    ];
+    let mut font = be16(&[1, 0, tables.len() as i32, 64, 2, 48]);
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, data) in &mut tables {
+        data.resize(data.len().next_multiple_of(4), 0);
+        font.extend(tag.as_slice());
+        font.extend([0; 4]);
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in tables {
+        font.extend(data);
+    }
+    font
+}
+
+/// A DeviceGray shading of `shading_type`, 2 for axial or 3 for radial,
+/// going from `c0` to `c1` between `coords`.
+pub fn gray_shading(
+    shading_type: i64,
+    coords: &[f32],
+    extend: bool,
+    c0: f32,
+    c1: f32,
+) -> Dictionary {
+    dictionary! {
+        "ShadingType" => shading_type,
+        "ColorSpace" => "DeviceGray",
+        "Coords" => coords.iter().map(|c| Object::Real(*c)).collect::<Vec<_>>(),
+        "Extend" => vec![extend.into(), extend.into()],
+        "Function" => dictionary! {
+            "FunctionType" => 2,
+            "Domain" => vec![0.into(), 1.into()],
+            "C0" => vec![c0.into()],
+            "C1" => vec![c1.into()],
+            "N" => 1,
+        },
+    }
+}
+
+/// Renders the first page at one pixel per point on the CPU, over white and
+/// without antialiasing so edges land on whole pixels.
+pub fn render(doc: &Document) -> RgbaImage {
+    render_with(doc, &RenderSettings::default())
+}
+
+/// Renders the first page like [`render`], but with `settings`.
+pub fn render_with(doc: &Document, settings: &RenderSettings) -> RgbaImage {
+    let settings = RenderSettings {
+        anti_alias: false,
+        ..settings.clone()
+    };
+    render_page_cpu(doc, 1, 1., &settings).expect("the page renders")
+}
+
+/// The pixel `x` points right of and `y` points up from the bottom left of a
+/// page rendered at one pixel per point.
+pub fn pixel(image: &RgbaImage, x: u32, y: u32) -> [u8; 4] {
+    image.get_pixel(x, image.height() - 1 - y).0
+}
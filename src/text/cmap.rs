@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+enum Token {
+    Hex(Vec<u8>),
+    Word(String),
+    ArrayStart,
+    ArrayEnd,
+}
+
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'%' => {
+                while i < data.len() && data[i] != b'\n' && data[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'<' if data.get(i + 1) == Some(&b'<') => i += 2,
+            b'>' if data.get(i + 1) == Some(&b'>') => i += 2,
+            b'<' => {
+                let end = data[i..]
+                    .iter()
+                    .position(|b| *b == b'>')
+                    .map_or(data.len(), |p| p + i);
+                let digits: Vec<u8> = data[i + 1..end]
+                    .iter()
+                    .filter_map(|b| (*b as char).to_digit(16).map(|d| d as u8))
+                    .collect();
+                tokens.push(Token::Hex(
+                    digits
+                        .chunks(2)
+                        .map(|d| d[0] << 4 | d.get(1).unwrap_or(&0))
+                        .collect(),
+                ));
+                i = end + 1;
+            }
+            b'[' => {
+                tokens.push(Token::ArrayStart);
+                i += 1;
+            }
+            b']' => {
+                tokens.push(Token::ArrayEnd);
+                i += 1;
+            }
+            b if b.is_ascii_whitespace() => i += 1,
+            _ => {
+                let start = i;
+                while i < data.len()
+                    && !b"<>[]%".contains(&data[i])
+                    && !data[i].is_ascii_whitespace()
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Word(
+                    String::from_utf8_lossy(&data[start..i]).into_owned(),
+                ));
+            }
+        }
+    }
+    tokens
+}
+
+fn code(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |code, b| code << 8 | *b as u32)
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|b| u16::from_be_bytes([b[0], *b.get(1).unwrap_or(&0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Parses the bfchar and bfrange sections of a /ToUnicode CMap into a map
+/// from character codes to the text they represent.
+pub fn parse_to_unicode(data: &[u8]) -> HashMap<u32, String> {
+    let mut map = HashMap::new();
+    let tokens = tokenize(data);
+    let mut rest = &tokens[..];
+    while let [token, tail @ ..] = rest {
+        rest = tail;
+        match token {
+            Token::Word(w) if w == "beginbfchar" => {
+                while let [Token::Hex(src), Token::Hex(dst), tail @ ..] = rest {
+                    map.insert(code(src), utf16(dst));
+                    rest = tail;
+                }
+            }
+            Token::Word(w) if w == "beginbfrange" => loop {
+                match rest {
+                    [Token::Hex(lo), Token::Hex(hi), Token::Hex(dst), tail @ ..] => {
+                        let (lo, hi) = (code(lo), code(hi));
+                        for (i, src) in (lo..=hi.min(lo.saturating_add(0xFFFF))).enumerate() {
+                            // Only the last byte of the destination is incremented
+                            let mut dst = dst.clone();
+                            if let Some(last) = dst.len().checked_sub(1) {
+                                dst[last] = dst[last].wrapping_add(i as u8);
+                            }
+                            map.insert(src, utf16(&dst));
+                        }
+                        rest = tail;
+                    }
+                    [Token::Hex(lo), Token::Hex(_), Token::ArrayStart, tail @ ..] => {
+                        let mut src = code(lo);
+                        rest = tail;
+                        while let [Token::Hex(dst), tail @ ..] = rest {
+                            map.insert(src, utf16(dst));
+                            src += 1;
+                            rest = tail;
+                        }
+                        if let [Token::ArrayEnd, tail @ ..] = rest {
+                            rest = tail;
+                        }
+                    }
+                    _ => break,
+                }
+            },
+            _ => {}
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bfchar_and_bfrange_map_codes_to_text() {
+        let map = parse_to_unicode(
+            b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap\n\
+              1 begincodespacerange <0000> <FFFF> endcodespacerange\n\
+              2 beginbfchar <0001> <0058> <0002> <00660069> endbfchar\n\
+              2 beginbfrange <0010> <0012> <0061> <0020> <0021> [<0031> <0032>] endbfrange\n\
+              endcmap",
+        );
+        assert_eq!(map[&1], "X");
+        assert_eq!(map[&2], "fi");
+        assert_eq!(map[&0x10], "a");
+        assert_eq!(map[&0x12], "c");
+        assert_eq!(map[&0x21], "2");
+        assert_eq!(map.len(), 7);
+    }
+}
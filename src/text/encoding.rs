@@ -0,0 +1,1336 @@
+use std::collections::HashMap;
+
+use lopdf::{Dictionary, Document, Object};
+
+/// Glyph names for Adobe StandardEncoding, indexed by code; empty where undefined.
+pub const STANDARD_ENCODING: [&str; 256] = [
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "space",
+    "exclam",
+    "quotedbl",
+    "numbersign",
+    "dollar",
+    "percent",
+    "ampersand",
+    "quoteright",
+    "parenleft",
+    "parenright",
+    "asterisk",
+    "plus",
+    "comma",
+    "hyphen",
+    "period",
+    "slash",
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "colon",
+    "semicolon",
+    "less",
+    "equal",
+    "greater",
+    "question",
+    "at",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "bracketleft",
+    "backslash",
+    "bracketright",
+    "asciicircum",
+    "underscore",
+    "quoteleft",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "braceleft",
+    "bar",
+    "braceright",
+    "asciitilde",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "exclamdown",
+    "cent",
+    "sterling",
+    "fraction",
+    "yen",
+    "florin",
+    "section",
+    "currency",
+    "quotesingle",
+    "quotedblleft",
+    "guillemotleft",
+    "guilsinglleft",
+    "guilsinglright",
+    "fi",
+    "fl",
+    "",
+    "endash",
+    "dagger",
+    "daggerdbl",
+    "periodcentered",
+    "",
+    "paragraph",
+    "bullet",
+    "quotesinglbase",
+    "quotedblbase",
+    "quotedblright",
+    "guillemotright",
+    "ellipsis",
+    "perthousand",
+    "",
+    "questiondown",
+    "",
+    "grave",
+    "acute",
+    "circumflex",
+    "tilde",
+    "macron",
+    "breve",
+    "dotaccent",
+    "dieresis",
+    "",
+    "ring",
+    "cedilla",
+    "",
+    "hungarumlaut",
+    "ogonek",
+    "caron",
+    "emdash",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "AE",
+    "",
+    "ordfeminine",
+    "",
+    "",
+    "",
+    "",
+    "Lslash",
+    "Oslash",
+    "OE",
+    "ordmasculine",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "ae",
+    "",
+    "",
+    "",
+    "dotlessi",
+    "",
+    "",
+    "lslash",
+    "oslash",
+    "oe",
+    "germandbls",
+    "",
+    "",
+    "",
+    "",
+];
+
+/// Glyph names for WinAnsiEncoding, indexed by code; empty where undefined.
+pub const WIN_ANSI_ENCODING: [&str; 256] = [
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "space",
+    "exclam",
+    "quotedbl",
+    "numbersign",
+    "dollar",
+    "percent",
+    "ampersand",
+    "quotesingle",
+    "parenleft",
+    "parenright",
+    "asterisk",
+    "plus",
+    "comma",
+    "hyphen",
+    "period",
+    "slash",
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "colon",
+    "semicolon",
+    "less",
+    "equal",
+    "greater",
+    "question",
+    "at",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "bracketleft",
+    "backslash",
+    "bracketright",
+    "asciicircum",
+    "underscore",
+    "grave",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "braceleft",
+    "bar",
+    "braceright",
+    "asciitilde",
+    "bullet",
+    "Euro",
+    "bullet",
+    "quotesinglbase",
+    "florin",
+    "quotedblbase",
+    "ellipsis",
+    "dagger",
+    "daggerdbl",
+    "circumflex",
+    "perthousand",
+    "Scaron",
+    "guilsinglleft",
+    "OE",
+    "bullet",
+    "Zcaron",
+    "bullet",
+    "bullet",
+    "quoteleft",
+    "quoteright",
+    "quotedblleft",
+    "quotedblright",
+    "bullet",
+    "endash",
+    "emdash",
+    "tilde",
+    "trademark",
+    "scaron",
+    "guilsinglright",
+    "oe",
+    "bullet",
+    "zcaron",
+    "Ydieresis",
+    "space",
+    "exclamdown",
+    "cent",
+    "sterling",
+    "currency",
+    "yen",
+    "brokenbar",
+    "section",
+    "dieresis",
+    "copyright",
+    "ordfeminine",
+    "guillemotleft",
+    "logicalnot",
+    "hyphen",
+    "registered",
+    "macron",
+    "degree",
+    "plusminus",
+    "twosuperior",
+    "threesuperior",
+    "acute",
+    "mu",
+    "paragraph",
+    "periodcentered",
+    "cedilla",
+    "onesuperior",
+    "ordmasculine",
+    "guillemotright",
+    "onequarter",
+    "onehalf",
+    "threequarters",
+    "questiondown",
+    "Agrave",
+    "Aacute",
+    "Acircumflex",
+    "Atilde",
+    "Adieresis",
+    "Aring",
+    "AE",
+    "Ccedilla",
+    "Egrave",
+    "Eacute",
+    "Ecircumflex",
+    "Edieresis",
+    "Igrave",
+    "Iacute",
+    "Icircumflex",
+    "Idieresis",
+    "Eth",
+    "Ntilde",
+    "Ograve",
+    "Oacute",
+    "Ocircumflex",
+    "Otilde",
+    "Odieresis",
+    "multiply",
+    "Oslash",
+    "Ugrave",
+    "Uacute",
+    "Ucircumflex",
+    "Udieresis",
+    "Yacute",
+    "Thorn",
+    "germandbls",
+    "agrave",
+    "aacute",
+    "acircumflex",
+    "atilde",
+    "adieresis",
+    "aring",
+    "ae",
+    "ccedilla",
+    "egrave",
+    "eacute",
+    "ecircumflex",
+    "edieresis",
+    "igrave",
+    "iacute",
+    "icircumflex",
+    "idieresis",
+    "eth",
+    "ntilde",
+    "ograve",
+    "oacute",
+    "ocircumflex",
+    "otilde",
+    "odieresis",
+    "divide",
+    "oslash",
+    "ugrave",
+    "uacute",
+    "ucircumflex",
+    "udieresis",
+    "yacute",
+    "thorn",
+    "ydieresis",
+];
+
+/// Glyph names for MacRomanEncoding, indexed by code; empty where undefined.
+pub const MAC_ROMAN_ENCODING: [&str; 256] = [
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "",
+    "space",
+    "exclam",
+    "quotedbl",
+    "numbersign",
+    "dollar",
+    "percent",
+    "ampersand",
+    "quotesingle",
+    "parenleft",
+    "parenright",
+    "asterisk",
+    "plus",
+    "comma",
+    "hyphen",
+    "period",
+    "slash",
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "colon",
+    "semicolon",
+    "less",
+    "equal",
+    "greater",
+    "question",
+    "at",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "bracketleft",
+    "backslash",
+    "bracketright",
+    "asciicircum",
+    "underscore",
+    "grave",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "braceleft",
+    "bar",
+    "braceright",
+    "asciitilde",
+    "",
+    "Adieresis",
+    "Aring",
+    "Ccedilla",
+    "Eacute",
+    "Ntilde",
+    "Odieresis",
+    "Udieresis",
+    "aacute",
+    "agrave",
+    "acircumflex",
+    "adieresis",
+    "atilde",
+    "aring",
+    "ccedilla",
+    "eacute",
+    "egrave",
+    "ecircumflex",
+    "edieresis",
+    "iacute",
+    "igrave",
+    "icircumflex",
+    "idieresis",
+    "ntilde",
+    "oacute",
+    "ograve",
+    "ocircumflex",
+    "odieresis",
+    "otilde",
+    "uacute",
+    "ugrave",
+    "ucircumflex",
+    "udieresis",
+    "dagger",
+    "degree",
+    "cent",
+    "sterling",
+    "section",
+    "bullet",
+    "paragraph",
+    "germandbls",
+    "registered",
+    "copyright",
+    "trademark",
+    "acute",
+    "dieresis",
+    "notequal",
+    "AE",
+    "Oslash",
+    "infinity",
+    "plusminus",
+    "lessequal",
+    "greaterequal",
+    "yen",
+    "mu",
+    "partialdiff",
+    "summation",
+    "product",
+    "pi",
+    "integral",
+    "ordfeminine",
+    "ordmasculine",
+    "Omega",
+    "ae",
+    "oslash",
+    "questiondown",
+    "exclamdown",
+    "logicalnot",
+    "radical",
+    "florin",
+    "approxequal",
+    "Delta",
+    "guillemotleft",
+    "guillemotright",
+    "ellipsis",
+    "space",
+    "Agrave",
+    "Atilde",
+    "Otilde",
+    "OE",
+    "oe",
+    "endash",
+    "emdash",
+    "quotedblleft",
+    "quotedblright",
+    "quoteleft",
+    "quoteright",
+    "divide",
+    "lozenge",
+    "ydieresis",
+    "Ydieresis",
+    "fraction",
+    "currency",
+    "guilsinglleft",
+    "guilsinglright",
+    "fi",
+    "fl",
+    "daggerdbl",
+    "periodcentered",
+    "quotesinglbase",
+    "quotedblbase",
+    "perthousand",
+    "Acircumflex",
+    "Ecircumflex",
+    "Aacute",
+    "Edieresis",
+    "Egrave",
+    "Iacute",
+    "Icircumflex",
+    "Idieresis",
+    "Igrave",
+    "Oacute",
+    "Ocircumflex",
+    "apple",
+    "Ograve",
+    "Uacute",
+    "Ucircumflex",
+    "Ugrave",
+    "dotlessi",
+    "circumflex",
+    "tilde",
+    "macron",
+    "breve",
+    "dotaccent",
+    "ring",
+    "cedilla",
+    "hungarumlaut",
+    "ogonek",
+    "caron",
+];
+
+// The Adobe Glyph List entries for the names the standard encodings and
+// CFF fonts use, sorted by name
+const GLYPH_UNICODE: [(&str, u16); 394] = [
+    ("A", 0x0041),
+    ("AE", 0x00C6),
+    ("AEsmall", 0xF7E6),
+    ("Aacute", 0x00C1),
+    ("Aacutesmall", 0xF7E1),
+    ("Acircumflex", 0x00C2),
+    ("Acircumflexsmall", 0xF7E2),
+    ("Acutesmall", 0xF7B4),
+    ("Adieresis", 0x00C4),
+    ("Adieresissmall", 0xF7E4),
+    ("Agrave", 0x00C0),
+    ("Agravesmall", 0xF7E0),
+    ("Aring", 0x00C5),
+    ("Aringsmall", 0xF7E5),
+    ("Asmall", 0xF761),
+    ("Atilde", 0x00C3),
+    ("Atildesmall", 0xF7E3),
+    ("B", 0x0042),
+    ("Brevesmall", 0xF6F4),
+    ("Bsmall", 0xF762),
+    ("C", 0x0043),
+    ("Caronsmall", 0xF6F5),
+    ("Ccedilla", 0x00C7),
+    ("Ccedillasmall", 0xF7E7),
+    ("Cedillasmall", 0xF7B8),
+    ("Circumflexsmall", 0xF6F6),
+    ("Csmall", 0xF763),
+    ("D", 0x0044),
+    ("Delta", 0x2206),
+    ("Dieresissmall", 0xF7A8),
+    ("Dotaccentsmall", 0xF6F7),
+    ("Dsmall", 0xF764),
+    ("E", 0x0045),
+    ("Eacute", 0x00C9),
+    ("Eacutesmall", 0xF7E9),
+    ("Ecircumflex", 0x00CA),
+    ("Ecircumflexsmall", 0xF7EA),
+    ("Edieresis", 0x00CB),
+    ("Edieresissmall", 0xF7EB),
+    ("Egrave", 0x00C8),
+    ("Egravesmall", 0xF7E8),
+    ("Esmall", 0xF765),
+    ("Eth", 0x00D0),
+    ("Ethsmall", 0xF7F0),
+    ("Euro", 0x20AC),
+    ("F", 0x0046),
+    ("Fsmall", 0xF766),
+    ("G", 0x0047),
+    ("Gravesmall", 0xF760),
+    ("Gsmall", 0xF767),
+    ("H", 0x0048),
+    ("Hsmall", 0xF768),
+    ("Hungarumlautsmall", 0xF6F8),
+    ("I", 0x0049),
+    ("Iacute", 0x00CD),
+    ("Iacutesmall", 0xF7ED),
+    ("Icircumflex", 0x00CE),
+    ("Icircumflexsmall", 0xF7EE),
+    ("Idieresis", 0x00CF),
+    ("Idieresissmall", 0xF7EF),
+    ("Igrave", 0x00CC),
+    ("Igravesmall", 0xF7EC),
+    ("Ismall", 0xF769),
+    ("J", 0x004A),
+    ("Jsmall", 0xF76A),
+    ("K", 0x004B),
+    ("Ksmall", 0xF76B),
+    ("L", 0x004C),
+    ("Lslash", 0x0141),
+    ("Lslashsmall", 0xF6F9),
+    ("Lsmall", 0xF76C),
+    ("M", 0x004D),
+    ("Macronsmall", 0xF7AF),
+    ("Msmall", 0xF76D),
+    ("N", 0x004E),
+    ("Nsmall", 0xF76E),
+    ("Ntilde", 0x00D1),
+    ("Ntildesmall", 0xF7F1),
+    ("O", 0x004F),
+    ("OE", 0x0152),
+    ("OEsmall", 0xF6FA),
+    ("Oacute", 0x00D3),
+    ("Oacutesmall", 0xF7F3),
+    ("Ocircumflex", 0x00D4),
+    ("Ocircumflexsmall", 0xF7F4),
+    ("Odieresis", 0x00D6),
+    ("Odieresissmall", 0xF7F6),
+    ("Ogoneksmall", 0xF6FB),
+    ("Ograve", 0x00D2),
+    ("Ogravesmall", 0xF7F2),
+    ("Omega", 0x2126),
+    ("Oslash", 0x00D8),
+    ("Oslashsmall", 0xF7F8),
+    ("Osmall", 0xF76F),
+    ("Otilde", 0x00D5),
+    ("Otildesmall", 0xF7F5),
+    ("P", 0x0050),
+    ("Psmall", 0xF770),
+    ("Q", 0x0051),
+    ("Qsmall", 0xF771),
+    ("R", 0x0052),
+    ("Ringsmall", 0xF6FC),
+    ("Rsmall", 0xF772),
+    ("S", 0x0053),
+    ("Scaron", 0x0160),
+    ("Scaronsmall", 0xF6FD),
+    ("Ssmall", 0xF773),
+    ("T", 0x0054),
+    ("Thorn", 0x00DE),
+    ("Thornsmall", 0xF7FE),
+    ("Tildesmall", 0xF6FE),
+    ("Tsmall", 0xF774),
+    ("U", 0x0055),
+    ("Uacute", 0x00DA),
+    ("Uacutesmall", 0xF7FA),
+    ("Ucircumflex", 0x00DB),
+    ("Ucircumflexsmall", 0xF7FB),
+    ("Udieresis", 0x00DC),
+    ("Udieresissmall", 0xF7FC),
+    ("Ugrave", 0x00D9),
+    ("Ugravesmall", 0xF7F9),
+    ("Usmall", 0xF775),
+    ("V", 0x0056),
+    ("Vsmall", 0xF776),
+    ("W", 0x0057),
+    ("Wsmall", 0xF777),
+    ("X", 0x0058),
+    ("Xsmall", 0xF778),
+    ("Y", 0x0059),
+    ("Yacute", 0x00DD),
+    ("Yacutesmall", 0xF7FD),
+    ("Ydieresis", 0x0178),
+    ("Ydieresissmall", 0xF7FF),
+    ("Ysmall", 0xF779),
+    ("Z", 0x005A),
+    ("Zcaron", 0x017D),
+    ("Zcaronsmall", 0xF6FF),
+    ("Zsmall", 0xF77A),
+    ("a", 0x0061),
+    ("aacute", 0x00E1),
+    ("acircumflex", 0x00E2),
+    ("acute", 0x00B4),
+    ("adieresis", 0x00E4),
+    ("ae", 0x00E6),
+    ("agrave", 0x00E0),
+    ("ampersand", 0x0026),
+    ("ampersandsmall", 0xF726),
+    ("apple", 0xF8FF),
+    ("approxequal", 0x2248),
+    ("aring", 0x00E5),
+    ("asciicircum", 0x005E),
+    ("asciitilde", 0x007E),
+    ("asterisk", 0x002A),
+    ("asuperior", 0xF6E9),
+    ("at", 0x0040),
+    ("atilde", 0x00E3),
+    ("b", 0x0062),
+    ("backslash", 0x005C),
+    ("bar", 0x007C),
+    ("braceleft", 0x007B),
+    ("braceright", 0x007D),
+    ("bracketleft", 0x005B),
+    ("bracketright", 0x005D),
+    ("breve", 0x02D8),
+    ("brokenbar", 0x00A6),
+    ("bsuperior", 0xF6EA),
+    ("bullet", 0x2022),
+    ("c", 0x0063),
+    ("caron", 0x02C7),
+    ("ccedilla", 0x00E7),
+    ("cedilla", 0x00B8),
+    ("cent", 0x00A2),
+    ("centinferior", 0xF6DF),
+    ("centoldstyle", 0xF7A2),
+    ("centsuperior", 0xF6E0),
+    ("circumflex", 0x02C6),
+    ("colon", 0x003A),
+    ("colonmonetary", 0x20A1),
+    ("comma", 0x002C),
+    ("commainferior", 0xF6E1),
+    ("commasuperior", 0xF6E2),
+    ("copyright", 0x00A9),
+    ("currency", 0x00A4),
+    ("d", 0x0064),
+    ("dagger", 0x2020),
+    ("daggerdbl", 0x2021),
+    ("degree", 0x00B0),
+    ("dieresis", 0x00A8),
+    ("divide", 0x00F7),
+    ("dollar", 0x0024),
+    ("dollarinferior", 0xF6E3),
+    ("dollaroldstyle", 0xF724),
+    ("dollarsuperior", 0xF6E4),
+    ("dotaccent", 0x02D9),
+    ("dotlessi", 0x0131),
+    ("dsuperior", 0xF6EB),
+    ("e", 0x0065),
+    ("eacute", 0x00E9),
+    ("ecircumflex", 0x00EA),
+    ("edieresis", 0x00EB),
+    ("egrave", 0x00E8),
+    ("eight", 0x0038),
+    ("eightinferior", 0x2088),
+    ("eightoldstyle", 0xF738),
+    ("eightsuperior", 0x2078),
+    ("ellipsis", 0x2026),
+    ("emdash", 0x2014),
+    ("endash", 0x2013),
+    ("equal", 0x003D),
+    ("esuperior", 0xF6EC),
+    ("eth", 0x00F0),
+    ("exclam", 0x0021),
+    ("exclamdown", 0x00A1),
+    ("exclamdownsmall", 0xF7A1),
+    ("exclamsmall", 0xF721),
+    ("f", 0x0066),
+    ("ff", 0xFB00),
+    ("ffi", 0xFB03),
+    ("ffl", 0xFB04),
+    ("fi", 0xFB01),
+    ("figuredash", 0x2012),
+    ("five", 0x0035),
+    ("fiveeighths", 0x215D),
+    ("fiveinferior", 0x2085),
+    ("fiveoldstyle", 0xF735),
+    ("fivesuperior", 0x2075),
+    ("fl", 0xFB02),
+    ("florin", 0x0192),
+    ("four", 0x0034),
+    ("fourinferior", 0x2084),
+    ("fouroldstyle", 0xF734),
+    ("foursuperior", 0x2074),
+    ("fraction", 0x2044),
+    ("g", 0x0067),
+    ("germandbls", 0x00DF),
+    ("grave", 0x0060),
+    ("greater", 0x003E),
+    ("greaterequal", 0x2265),
+    ("guillemotleft", 0x00AB),
+    ("guillemotright", 0x00BB),
+    ("guilsinglleft", 0x2039),
+    ("guilsinglright", 0x203A),
+    ("h", 0x0068),
+    ("hungarumlaut", 0x02DD),
+    ("hyphen", 0x002D),
+    ("hypheninferior", 0xF6E5),
+    ("hyphensuperior", 0xF6E6),
+    ("i", 0x0069),
+    ("iacute", 0x00ED),
+    ("icircumflex", 0x00EE),
+    ("idieresis", 0x00EF),
+    ("igrave", 0x00EC),
+    ("infinity", 0x221E),
+    ("integral", 0x222B),
+    ("isuperior", 0xF6ED),
+    ("j", 0x006A),
+    ("k", 0x006B),
+    ("l", 0x006C),
+    ("less", 0x003C),
+    ("lessequal", 0x2264),
+    ("logicalnot", 0x00AC),
+    ("lozenge", 0x25CA),
+    ("lslash", 0x0142),
+    ("lsuperior", 0xF6EE),
+    ("m", 0x006D),
+    ("macron", 0x00AF),
+    ("minus", 0x2212),
+    ("msuperior", 0xF6EF),
+    ("mu", 0x00B5),
+    ("multiply", 0x00D7),
+    ("n", 0x006E),
+    ("nine", 0x0039),
+    ("nineinferior", 0x2089),
+    ("nineoldstyle", 0xF739),
+    ("ninesuperior", 0x2079),
+    ("notequal", 0x2260),
+    ("nsuperior", 0x207F),
+    ("ntilde", 0x00F1),
+    ("numbersign", 0x0023),
+    ("o", 0x006F),
+    ("oacute", 0x00F3),
+    ("ocircumflex", 0x00F4),
+    ("odieresis", 0x00F6),
+    ("oe", 0x0153),
+    ("ogonek", 0x02DB),
+    ("ograve", 0x00F2),
+    ("one", 0x0031),
+    ("onedotenleader", 0x2024),
+    ("oneeighth", 0x215B),
+    ("onefitted", 0xF6DC),
+    ("onehalf", 0x00BD),
+    ("oneinferior", 0x2081),
+    ("oneoldstyle", 0xF731),
+    ("onequarter", 0x00BC),
+    ("onesuperior", 0x00B9),
+    ("onethird", 0x2153),
+    ("ordfeminine", 0x00AA),
+    ("ordmasculine", 0x00BA),
+    ("oslash", 0x00F8),
+    ("osuperior", 0xF6F0),
+    ("otilde", 0x00F5),
+    ("p", 0x0070),
+    ("paragraph", 0x00B6),
+    ("parenleft", 0x0028),
+    ("parenleftinferior", 0x208D),
+    ("parenleftsuperior", 0x207D),
+    ("parenright", 0x0029),
+    ("parenrightinferior", 0x208E),
+    ("parenrightsuperior", 0x207E),
+    ("partialdiff", 0x2202),
+    ("percent", 0x0025),
+    ("period", 0x002E),
+    ("periodcentered", 0x00B7),
+    ("periodinferior", 0xF6E7),
+    ("periodsuperior", 0xF6E8),
+    ("perthousand", 0x2030),
+    ("pi", 0x03C0),
+    ("plus", 0x002B),
+    ("plusminus", 0x00B1),
+    ("product", 0x220F),
+    ("q", 0x0071),
+    ("question", 0x003F),
+    ("questiondown", 0x00BF),
+    ("questiondownsmall", 0xF7BF),
+    ("questionsmall", 0xF73F),
+    ("quotedbl", 0x0022),
+    ("quotedblbase", 0x201E),
+    ("quotedblleft", 0x201C),
+    ("quotedblright", 0x201D),
+    ("quoteleft", 0x2018),
+    ("quoteright", 0x2019),
+    ("quotesinglbase", 0x201A),
+    ("quotesingle", 0x0027),
+    ("r", 0x0072),
+    ("radical", 0x221A),
+    ("registered", 0x00AE),
+    ("ring", 0x02DA),
+    ("rsuperior", 0xF6F1),
+    ("rupiah", 0xF6DD),
+    ("s", 0x0073),
+    ("scaron", 0x0161),
+    ("section", 0x00A7),
+    ("semicolon", 0x003B),
+    ("seven", 0x0037),
+    ("seveneighths", 0x215E),
+    ("seveninferior", 0x2087),
+    ("sevenoldstyle", 0xF737),
+    ("sevensuperior", 0x2077),
+    ("six", 0x0036),
+    ("sixinferior", 0x2086),
+    ("sixoldstyle", 0xF736),
+    ("sixsuperior", 0x2076),
+    ("slash", 0x002F),
+    ("space", 0x0020),
+    ("ssuperior", 0xF6F2),
+    ("sterling", 0x00A3),
+    ("summation", 0x2211),
+    ("t", 0x0074),
+    ("thorn", 0x00FE),
+    ("three", 0x0033),
+    ("threeeighths", 0x215C),
+    ("threeinferior", 0x2083),
+    ("threeoldstyle", 0xF733),
+    ("threequarters", 0x00BE),
+    ("threequartersemdash", 0xF6DE),
+    ("threesuperior", 0x00B3),
+    ("tilde", 0x02DC),
+    ("trademark", 0x2122),
+    ("tsuperior", 0xF6F3),
+    ("two", 0x0032),
+    ("twodotenleader", 0x2025),
+    ("twoinferior", 0x2082),
+    ("twooldstyle", 0xF732),
+    ("twosuperior", 0x00B2),
+    ("twothirds", 0x2154),
+    ("u", 0x0075),
+    ("uacute", 0x00FA),
+    ("ucircumflex", 0x00FB),
+    ("udieresis", 0x00FC),
+    ("ugrave", 0x00F9),
+    ("underscore", 0x005F),
+    ("v", 0x0076),
+    ("w", 0x0077),
+    ("x", 0x0078),
+    ("y", 0x0079),
+    ("yacute", 0x00FD),
+    ("ydieresis", 0x00FF),
+    ("yen", 0x00A5),
+    ("z", 0x007A),
+    ("zcaron", 0x017E),
+    ("zero", 0x0030),
+    ("zeroinferior", 0x2080),
+    ("zerooldstyle", 0xF730),
+    ("zerosuperior", 0x2070),
+];
+
+/// Reads an encoding's `/Differences` array, `[code name1 name2 ... code ...]`,
+/// into the glyph name it gives each code.
+pub fn differences(array: &[Object]) -> HashMap<u8, Vec<u8>> {
+    let mut names = HashMap::new();
+    let mut code = 0;
+    for item in array {
+        match item {
+            Object::Integer(next) => code = *next,
+            Object::Name(name) => {
+                if let Ok(code) = u8::try_from(code) {
+                    names.insert(code, name.clone());
+                }
+                code += 1;
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// The names a base encoding like `/WinAnsiEncoding` gives each code.
+pub fn base_encoding(name: &[u8]) -> Option<HashMap<u8, Vec<u8>>> {
+    let table = match name {
+        b"StandardEncoding" => &STANDARD_ENCODING,
+        b"WinAnsiEncoding" => &WIN_ANSI_ENCODING,
+        b"MacRomanEncoding" => &MAC_ROMAN_ENCODING,
+        _ => return None,
+    };
+    Some(
+        table
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| !name.is_empty())
+            .map(|(code, name)| (code as u8, name.as_bytes().to_vec()))
+            .collect(),
+    )
+}
+
+/// Reads a simple font's `/Encoding`, a base encoding with `/Differences`
+/// applied, into the glyph name it gives each code. Codes it leaves out,
+/// which is all of them without an `/Encoding`, keep the font program's
+/// built-in encoding.
+pub fn font_encoding(doc: &Document, font: &Dictionary) -> HashMap<u8, Vec<u8>> {
+    let Ok((_, encoding)) = font.get(b"Encoding").and_then(|e| doc.dereference(e)) else {
+        return HashMap::new();
+    };
+    match encoding {
+        Object::Name(name) => base_encoding(name).unwrap_or_default(),
+        Object::Dictionary(encoding) => {
+            let mut names = encoding
+                .get(b"BaseEncoding")
+                .and_then(Object::as_name)
+                .ok()
+                .and_then(base_encoding)
+                .unwrap_or_default();
+            if let Ok(array) = encoding
+                .get(b"Differences")
+                .and_then(|d| doc.dereference(d))
+                .and_then(|(_, d)| d.as_array())
+            {
+                names.extend(differences(array));
+            }
+            names
+        }
+        _ => HashMap::new(),
+    }
+}
+
+/// The character a glyph name stands for, from the Adobe Glyph List or a
+/// `uniXXXX` or `uXXXX` name. Suffixes like `.sc` are ignored.
+pub fn glyph_unicode(name: &str) -> Option<char> {
+    let name = name.split('.').next()?;
+    if let Ok(i) = GLYPH_UNICODE.binary_search_by_key(&name, |(name, _)| name) {
+        return char::from_u32(GLYPH_UNICODE[i].1 as u32);
+    }
+    let hex = match name.strip_prefix("uni") {
+        Some(hex) if hex.len() == 4 => hex,
+        _ => name
+            .strip_prefix('u')
+            .filter(|hex| (4..=6).contains(&hex.len()))?,
+    };
+    char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn differences_name_codes_from_each_number() {
+        let array = [
+            65.into(),
+            "A".into(),
+            "B".into(),
+            200.into(),
+            "eacute".into(),
+            255.into(),
+            "ydieresis".into(),
+            "past".into(),
+        ];
+        let names = differences(&array);
+        assert_eq!(names.len(), 4);
+        assert_eq!(names[&65], b"A");
+        assert_eq!(names[&66], b"B");
+        assert_eq!(names[&200], b"eacute");
+        assert_eq!(names[&255], b"ydieresis");
+    }
+
+    #[test]
+    fn differences_apply_over_the_base_encoding() {
+        let mut doc = Document::new();
+        let encoding = doc.add_object(dictionary! {
+            "BaseEncoding" => "WinAnsiEncoding",
+            "Differences" => vec![65.into(), "Alpha".into()],
+        });
+        let names = font_encoding(&doc, &dictionary! { "Encoding" => encoding });
+        assert_eq!(names[&65], b"Alpha");
+        assert_eq!(names[&66], b"B");
+        assert_eq!(names[&0x80], b"Euro");
+        let mac = font_encoding(&doc, &dictionary! { "Encoding" => "MacRomanEncoding" });
+        assert_eq!(mac[&0x80], b"Adieresis");
+        assert!(font_encoding(&doc, &Dictionary::new()).is_empty());
+    }
+
+    #[test]
+    fn glyph_names_stand_for_characters() {
+        assert_eq!(glyph_unicode("eacute"), Some('é'));
+        assert_eq!(glyph_unicode("A.sc"), Some('A'));
+        assert_eq!(glyph_unicode("uni20AC"), Some('€'));
+        assert_eq!(glyph_unicode("u1F600"), Some('😀'));
+        assert_eq!(glyph_unicode("g123"), None);
+    }
+
+    #[test]
+    fn glyphs_are_found_by_their_encoded_name() {
+        let doc = font_page(
+            |doc| {
+                let font = type1_font(doc);
+                let encoding = dictionary! { "Differences" => vec![66.into(), "A".into()] };
+                let dict = doc.get_dictionary_mut(font).unwrap();
+                dict.set("Encoding", encoding);
+                dict.set("LastChar", 66);
+                dict.set("Widths", vec![1000.into(), 1000.into()]);
+                font
+            },
+            "BT /F1 10 Tf 10 10 Td (B) Tj ET",
+        );
+        assert_eq!(pixel(&render(&doc), 15, 15), BLACK);
+    }
+}
@@ -1,14 +1,160 @@
 use eyre::{Result, bail, eyre};
-use lopdf::{Document, Object, ObjectId};
-use owned_ttf_parser::OwnedFace;
-use std::fmt;
+use kurbo::BezPath;
+use lopdf::{Dictionary, Document, Object};
+use owned_ttf_parser::{AsFaceRef, GlyphId, OutlineBuilder, OwnedFace, PlatformId, cff};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    rc::Rc,
+};
 
-use crate::{FromPDF, get};
+use super::{
+    cmap::parse_to_unicode,
+    encoding::{font_encoding, glyph_unicode},
+    substitute::substitute_font,
+    type1::Type1Font,
+    type3::Type3Font,
+};
+use crate::{FromPDF, RasterError, get};
+
+pub enum FontProgram {
+    OpenType(OwnedFace),
+    Type1(Type1Font),
+    /// A bare CFF font program (`/FontFile3` of subtype Type1C or CIDFontType0C).
+    Cff(Vec<u8>),
+    /// Glyphs drawn by content streams rather than outlines.
+    Type3(Type3Font),
+}
+
+impl FontProgram {
+    pub fn units_per_em(&self) -> u16 {
+        match self {
+            FontProgram::OpenType(face) => face.as_face_ref().units_per_em(),
+            FontProgram::Type1(_) | FontProgram::Type3(_) => 1000,
+            FontProgram::Cff(_) => self
+                .cff()
+                .map(|table| table.matrix().sx)
+                .filter(|sx| *sx > 0.)
+                .map(|sx| (1. / sx).round() as u16)
+                .unwrap_or(1000),
+        }
+    }
+
+    pub fn outline_glyph(&self, id: GlyphId, builder: &mut dyn OutlineBuilder) -> Option<()> {
+        match self {
+            FontProgram::OpenType(face) => {
+                face.as_face_ref().outline_glyph(id, builder).map(|_| ())
+            }
+            FontProgram::Type1(font) => font.outline_glyph(id, builder).map(|_| ()),
+            FontProgram::Cff(_) => self.cff()?.outline(id, builder).ok().map(|_| ()),
+            FontProgram::Type3(_) => None,
+        }
+    }
+
+    fn cff(&self) -> Option<cff::Table<'_>> {
+        match self {
+            FontProgram::Cff(data) => cff::Table::parse(data),
+            _ => None,
+        }
+    }
+
+    /// Looks a glyph up by its PostScript name, or in TrueType fonts without
+    /// names, by the character the name stands for.
+    pub fn glyph_index_by_name(&self, name: &str) -> Option<GlyphId> {
+        match self {
+            FontProgram::OpenType(face) => {
+                let face = face.as_face_ref();
+                face.glyph_index_by_name(name)
+                    .or_else(|| face.glyph_index(glyph_unicode(name)?))
+            }
+            FontProgram::Type1(font) => font.glyph_index_by_name(name),
+            FontProgram::Cff(_) => self.cff()?.glyph_index_by_name(name),
+            FontProgram::Type3(_) => None,
+        }
+    }
+
+    /// The glyph's advance width in 1000 unit text space.
+    pub fn advance(&self, id: GlyphId) -> Option<f32> {
+        match self {
+            FontProgram::OpenType(face) => {
+                let face = face.as_face_ref();
+                let advance = face.glyph_hor_advance(id)? as f32;
+                Some(advance * 1000. / face.units_per_em() as f32)
+            }
+            FontProgram::Type1(font) => font.outline_glyph(id, &mut NoOutline),
+            FontProgram::Cff(_) => {
+                let advance = self.cff()?.glyph_width(id)? as f32;
+                Some(advance * 1000. / self.units_per_em() as f32)
+            }
+            FontProgram::Type3(_) => None,
+        }
+    }
+}
+
+struct NoOutline;
+
+struct PathOutline(BezPath);
+
+impl OutlineBuilder for PathOutline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.0.move_to((x as f64, y as f64));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.0.line_to((x as f64, y as f64));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.0.quad_to((x1 as f64, y1 as f64), (x as f64, y as f64));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.0.curve_to(
+            (x1 as f64, y1 as f64),
+            (x2 as f64, y2 as f64),
+            (x as f64, y as f64),
+        );
+    }
+
+    fn close(&mut self) {
+        self.0.close_path();
+    }
+}
+
+impl OutlineBuilder for NoOutline {
+    fn move_to(&mut self, _x: f32, _y: f32) {}
+    fn line_to(&mut self, _x: f32, _y: f32) {}
+    fn quad_to(&mut self, _x1: f32, _y1: f32, _x: f32, _y: f32) {}
+    fn curve_to(&mut self, _x1: f32, _y1: f32, _x2: f32, _y2: f32, _x: f32, _y: f32) {}
+    fn close(&mut self) {}
+}
 
 pub struct Font {
     pub name: String,
-    pub font: OwnedFace,
-    pub widths: Vec<f32>,
+    pub font: FontProgram,
+    pub widths: HashMap<u16, f32>,
+    pub default_width: f32,
+    pub composite: bool,
+    /// Maps character codes to glyph ids; `None` means codes are glyph ids.
+    pub glyph_map: Option<Vec<u16>>,
+    pub to_unicode: HashMap<u32, String>,
+    /// Set for composite fonts written top to bottom (`/WMode 1`).
+    pub vertical: Option<VerticalMetrics>,
+    /// Outlines in font units, built the first time each glyph is shown.
+    glyph_cache: RefCell<HashMap<u16, Option<Rc<BezPath>>>>,
+    /// How many times an outline has been asked for.
+    glyph_lookups: Cell<usize>,
+}
+
+/// A vertical font's `/W2` and `/DW2`, in thousandths of an em.
+pub struct VerticalMetrics {
+    /// Each CID's vertical advance and the position vector from its
+    /// horizontal origin to its vertical one, `[w1y vx vy]`
+    metrics: HashMap<u16, [f32; 3]>,
+    /// The `vy` and `w1y` of CIDs `/W2` leaves out
+    default: [f32; 2],
 }
 
 impl fmt::Debug for Font {
@@ -17,32 +163,478 @@ impl fmt::Debug for Font {
     }
 }
 
-impl<'a> FromPDF for Font {
+impl FromPDF for Font {
     fn from_pdf(doc: &Document, root: &Object) -> Result<Self> {
+        Font::load(doc, root, &HashMap::new())
+    }
+}
+
+impl Font {
+    /// Loads a font dictionary, standing in the files `substitutions` names,
+    /// by /BaseFont or standard 14 name, for fonts that aren't embedded.
+    pub fn load(
+        doc: &Document,
+        root: &Object,
+        substitutions: &HashMap<String, PathBuf>,
+    ) -> Result<Self> {
         let font = root.as_dict()?;
-        let descendant_fonts: Vec<ObjectId> = get(doc, font.get(b"DescendantFonts")?)?;
-        let descendent_font = doc.get_dictionary(match descendant_fonts[..] {
-            [id] => id,
-            _ => Err(eyre!("expected one DescendantFont"))?,
-        })?;
-        let descriptor =
-            doc.get_dictionary(descendent_font.get(b"FontDescriptor")?.as_reference()?)?;
-
-        let widths: Vec<f32> = match &descendent_font.get(b"W")?.as_array()?[..] {
-            [Object::Integer(0), Object::Array(ws)] => ws
-                .iter()
-                .map(|i| i.as_float().map_err(|e| eyre!("{e:?}")))
-                .collect::<Result<Vec<_>>>()?,
-            _ => bail!("Expected [0 [widths..]]"),
+        let mut loaded = match font.get(b"Subtype")?.as_name()? {
+            b"Type0" => composite_font(doc, font, substitutions)?,
+            b"TrueType" | b"Type1" | b"MMType1" => simple_font(doc, font, substitutions)?,
+            b"Type3" => type3_font(doc, font)?,
+            other => bail!(RasterError::UnsupportedFont(
+                String::from_utf8_lossy(other).into_owned()
+            )),
         };
+        // The CMap overrides the text simple fonts' encodings give codes
+        if let Ok(to_unicode) = font.get(b"ToUnicode") {
+            let cmap: Vec<u8> = get(doc, to_unicode)?;
+            loaded.to_unicode.extend(parse_to_unicode(&cmap));
+        }
+        Ok(loaded)
+    }
+}
+
+fn composite_font(
+    doc: &Document,
+    font: &Dictionary,
+    substitutions: &HashMap<String, PathBuf>,
+) -> Result<Font> {
+    let descendant_fonts = doc
+        .dereference(font.get(b"DescendantFonts")?)?
+        .1
+        .as_array()?;
+    let descendant_font = match &descendant_fonts[..] {
+        [descendant] => doc.dereference(descendant)?.1.as_dict()?,
+        _ => bail!("expected one DescendantFont"),
+    };
+    let descriptor = doc.get_dict_in_dict(descendant_font, b"FontDescriptor")?;
+    let vertical = writing_mode(doc, font)? == 1;
+
+    let widths = match descendant_font.get(b"W") {
+        Ok(w) => parse_widths(doc, w)?,
+        Err(_) => HashMap::new(),
+    };
+    let default_width = match descendant_font.get(b"DW") {
+        Ok(dw) => dw.as_float()?,
+        Err(_) => 1000.,
+    };
+
+    let name: String = get(doc, descriptor.get(b"FontName")?)?;
+
+    let (font, glyph_map) = match embedded_program(doc, descriptor)? {
+        Some(program) => {
+            let glyph_map = match descendant_font.get(b"CIDToGIDMap") {
+                Ok(Object::Name(_)) | Err(_) => cid_glyph_map(&program),
+                Ok(map) => {
+                    let map: Vec<u8> = get(doc, map)?;
+                    Some(
+                        map.chunks_exact(2)
+                            .map(|gid| u16::from_be_bytes([gid[0], gid[1]]))
+                            .collect(),
+                    )
+                }
+            };
+            (program, glyph_map)
+        }
+        None => {
+            let flags = match descriptor.get(b"Flags") {
+                Ok(flags) => flags.as_i64()?,
+                Err(_) => 0,
+            };
+            let face = load_font(substitute_font(&name, flags, substitutions)?)?;
+            // A stand-in's glyphs are found by the text each code represents
+            let glyph_map = match font.get(b"ToUnicode") {
+                Ok(cmap) => Some(unicode_glyph_map(&face, &get::<Vec<u8>>(doc, cmap)?)),
+                Err(_) => None,
+            };
+            (FontProgram::OpenType(face), glyph_map)
+        }
+    };
+
+    let vertical = if vertical {
+        Some(VerticalMetrics {
+            metrics: match descendant_font.get(b"W2") {
+                Ok(w2) => parse_vertical_widths(doc, w2)?,
+                Err(_) => HashMap::new(),
+            },
+            default: match descendant_font.get(b"DW2") {
+                Ok(dw2) => match get::<Vec<f32>>(doc, dw2)?[..] {
+                    [vy, w1y] => [vy, w1y],
+                    _ => bail!("Expected /DW2 [vy w1y]"),
+                },
+                Err(_) => [880., -1000.],
+            },
+        })
+    } else {
+        None
+    };
 
-        let content: Vec<u8> = get(doc, descriptor.get(b"FontFile2")?)?;
+    Ok(Font {
+        name,
+        font,
+        widths,
+        default_width,
+        composite: true,
+        glyph_map,
+        to_unicode: HashMap::new(),
+        vertical,
+        glyph_cache: RefCell::default(),
+        glyph_lookups: Cell::default(),
+    })
+}
+
+fn simple_font(
+    doc: &Document,
+    font: &Dictionary,
+    substitutions: &HashMap<String, PathBuf>,
+) -> Result<Font> {
+    let descriptor = doc.get_dict_in_dict(font, b"FontDescriptor").ok();
+
+    let mut widths = simple_widths(doc, font)?;
+    let default_width = match descriptor.and_then(|d| d.get(b"MissingWidth").ok()) {
+        Some(missing) => missing.as_float()?,
+        None => 0.,
+    };
+
+    let name: String = match descriptor.and_then(|d| d.get(b"FontName").ok()) {
+        Some(name) => get(doc, name)?,
+        None => get(doc, font.get(b"BaseFont")?)?,
+    };
 
-        let font = load_font(content)?;
+    let program = match descriptor {
+        Some(descriptor) => embedded_program(doc, descriptor)?,
+        None => None,
+    };
+    let program = match program {
+        Some(program) => program,
+        None => {
+            let flags = match descriptor.and_then(|d| d.get(b"Flags").ok()) {
+                Some(flags) => flags.as_i64()?,
+                None => 0,
+            };
+            FontProgram::OpenType(load_font(substitute_font(&name, flags, substitutions)?)?)
+        }
+    };
 
-        let name = get(doc, descriptor.get(b"FontName")?)?;
+    // Codes the /Encoding names are looked up by name, and the rest through
+    // the program's own encoding
+    let encoding = font_encoding(doc, font);
+    let named = |code: u8| {
+        let name = std::str::from_utf8(encoding.get(&code)?).ok()?;
+        program.glyph_index_by_name(name).map(|id| id.0)
+    };
+    let glyph_map: Vec<u16> = (0..=255)
+        .map(|code| {
+            named(code).unwrap_or_else(|| match &program {
+                FontProgram::OpenType(face) => simple_glyph_id(face, code),
+                FontProgram::Type1(font) => font.glyph_index(code).map(|id| id.0).unwrap_or(0),
+                FontProgram::Cff(_) => program
+                    .cff()
+                    .and_then(|table| table.glyph_index(code))
+                    .map(|id| id.0)
+                    .unwrap_or(0),
+                FontProgram::Type3(_) => code as u16,
+            })
+        })
+        .collect();
 
-        Ok(Font { name, font, widths })
+    // The standard 14 fonts may omit /Widths, so take them from the program
+    if widths.is_empty() {
+        for (code, id) in glyph_map.iter().enumerate() {
+            if let Some(advance) = program.advance(GlyphId(*id)) {
+                widths.insert(code as u16, advance);
+            }
+        }
+    }
+
+    let to_unicode = encoding
+        .iter()
+        .filter_map(|(code, name)| {
+            let text = glyph_unicode(std::str::from_utf8(name).ok()?)?;
+            Some((*code as u32, text.to_string()))
+        })
+        .collect();
+
+    Ok(Font {
+        name,
+        font: program,
+        widths,
+        default_width,
+        composite: false,
+        glyph_map: Some(glyph_map),
+        to_unicode,
+        vertical: None,
+        glyph_cache: RefCell::default(),
+        glyph_lookups: Cell::default(),
+    })
+}
+
+fn type3_font(doc: &Document, font: &Dictionary) -> Result<Font> {
+    let program = Type3Font::parse(doc, font)?;
+    // Widths are in glyph space, so scale them to the 1000 unit text space
+    // other fonts use
+    let scale = program.matrix.a * 1000.;
+    let widths = simple_widths(doc, font)?
+        .into_iter()
+        .map(|(code, w)| (code, w * scale))
+        .collect();
+    let name = match font.get(b"Name") {
+        Ok(name) => get(doc, name)?,
+        Err(_) => "Type3".to_string(),
+    };
+
+    Ok(Font {
+        name,
+        font: FontProgram::Type3(program),
+        widths,
+        default_width: 0.,
+        composite: false,
+        glyph_map: None,
+        to_unicode: HashMap::new(),
+        vertical: None,
+        glyph_cache: RefCell::default(),
+        glyph_lookups: Cell::default(),
+    })
+}
+
+// Reads a simple font's /Widths, which start at /FirstChar
+fn simple_widths(doc: &Document, font: &Dictionary) -> Result<HashMap<u16, f32>> {
+    let first_char = match font.get(b"FirstChar") {
+        Ok(first_char) => first_char.as_i64()?,
+        Err(_) => 0,
+    };
+    Ok(match font.get(b"Widths") {
+        Ok(widths) => get::<Vec<f32>>(doc, widths)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, w)| ((first_char + i as i64) as u16, w))
+            .collect(),
+        Err(_) => HashMap::new(),
+    })
+}
+
+// Loads the font program embedded in a descriptor, if there is one we can read
+fn embedded_program(doc: &Document, descriptor: &Dictionary) -> Result<Option<FontProgram>> {
+    if let Ok(file) = descriptor.get(b"FontFile2") {
+        let content: Vec<u8> = get(doc, file)?;
+        return Ok(Some(FontProgram::OpenType(load_font(content)?)));
+    }
+    if let Ok(file) = descriptor.get(b"FontFile3") {
+        let stream = doc.dereference(file)?.1.as_stream()?;
+        let content: Vec<u8> = get(doc, file)?;
+        return Ok(Some(match stream.dict.get(b"Subtype")?.as_name()? {
+            b"OpenType" => FontProgram::OpenType(load_font(content)?),
+            b"Type1C" | b"CIDFontType0C" => {
+                cff::Table::parse(&content).ok_or_else(|| eyre!("Could not parse CFF font"))?;
+                FontProgram::Cff(content)
+            }
+            other => bail!("Unsupported FontFile3 {}", String::from_utf8_lossy(other)),
+        }));
+    }
+    if let Ok(file) = descriptor.get(b"FontFile") {
+        let stream = doc.dereference(file)?.1.as_stream()?;
+        let length1 = stream.dict.get(b"Length1")?.as_i64()? as usize;
+        let content: Vec<u8> = get(doc, file)?;
+        return Ok(Some(FontProgram::Type1(Type1Font::parse(
+            &content, length1,
+        )?)));
+    }
+    Ok(None)
+}
+
+// CID-keyed CFF fonts map CIDs to glyphs through their charset; otherwise an
+// /Identity or missing CIDToGIDMap means CIDs are glyph ids.
+fn cid_glyph_map(program: &FontProgram) -> Option<Vec<u16>> {
+    let table = program.cff()?;
+    let cids: Vec<(u16, u16)> = (0..table.number_of_glyphs())
+        .filter_map(|gid| Some((table.glyph_cid(GlyphId(gid))?, gid)))
+        .collect();
+    let max_cid = cids.iter().map(|(cid, _)| *cid).max()?;
+    let mut map = vec![0; max_cid as usize + 1];
+    for (cid, gid) in cids {
+        map[cid as usize] = gid;
+    }
+    Some(map)
+}
+
+// Maps each code a /ToUnicode CMap gives text for to the glyph for the
+// text's first character
+fn unicode_glyph_map(face: &OwnedFace, to_unicode: &[u8]) -> Vec<u16> {
+    let to_unicode = parse_to_unicode(to_unicode);
+    let codes = to_unicode.keys().filter(|code| **code <= u16::MAX as u32);
+    let mut map = vec![0; codes.max().map_or(0, |max| *max as usize + 1)];
+    for (code, text) in to_unicode {
+        let glyph = text
+            .chars()
+            .next()
+            .and_then(|c| face.as_face_ref().glyph_index(c));
+        if let (Some(slot), Some(glyph)) = (map.get_mut(code as usize), glyph) {
+            *slot = glyph.0;
+        }
+    }
+    map
+}
+
+// Simple TrueType fonts are looked up through whichever cmap subtable they
+// carry: symbolic fonts map codes into 0xF000..0xF0FF, Mac Roman tables use the
+// code directly, and Unicode tables are treated as Latin-1.
+fn simple_glyph_id(face: &OwnedFace, code: u8) -> u16 {
+    let Some(cmap) = face.as_face_ref().tables().cmap else {
+        return code as u16;
+    };
+    let code = code as u32;
+    cmap.subtables
+        .into_iter()
+        .find_map(
+            |subtable| match (subtable.platform_id, subtable.encoding_id) {
+                (PlatformId::Windows, 0) => subtable
+                    .glyph_index(0xF000 + code)
+                    .or_else(|| subtable.glyph_index(code)),
+                (PlatformId::Macintosh, 0) => subtable.glyph_index(code),
+                _ if subtable.is_unicode() => subtable.glyph_index(code),
+                _ => None,
+            },
+        )
+        .map(|id| id.0)
+        .unwrap_or(0)
+}
+
+// A composite font's writing mode comes from its CMap: the predefined
+// vertical ones end in -V, and embedded ones say so with /WMode
+fn writing_mode(doc: &Document, font: &Dictionary) -> Result<i64> {
+    let Ok((_, encoding)) = font.get(b"Encoding").and_then(|e| doc.dereference(e)) else {
+        return Ok(0);
+    };
+    Ok(match encoding {
+        Object::Name(name) => name.ends_with(b"-V") as i64,
+        Object::Stream(stream) => match stream.dict.get(b"WMode") {
+            Ok(mode) => mode.as_i64()?,
+            Err(_) => 0,
+        },
+        _ => 0,
+    })
+}
+
+// Parses a CIDFont /W2 array, made up of `c [w1y vx vy ...]` and
+// `cFirst cLast w1y vx vy` entries
+fn parse_vertical_widths(doc: &Document, w2: &Object) -> Result<HashMap<u16, [f32; 3]>> {
+    let mut metrics = HashMap::new();
+    let mut rest = &doc.dereference(w2)?.1.as_array()?[..];
+    loop {
+        match rest {
+            [] => break,
+            [first, Object::Array(ms), tail @ ..] => {
+                let first = first.as_i64()?;
+                for (i, m) in ms.chunks_exact(3).enumerate() {
+                    let m = [m[0].as_float()?, m[1].as_float()?, m[2].as_float()?];
+                    metrics.insert((first + i as i64) as u16, m);
+                }
+                rest = tail;
+            }
+            [first, last, w1y, vx, vy, tail @ ..] => {
+                let m = [w1y.as_float()?, vx.as_float()?, vy.as_float()?];
+                for cid in first.as_i64()?.max(0)..=last.as_i64()?.min(u16::MAX as i64) {
+                    metrics.insert(cid as u16, m);
+                }
+                rest = tail;
+            }
+            other => bail!("Malformed /W2 entry {:?}", other),
+        }
+    }
+    Ok(metrics)
+}
+
+// Parses a CIDFont /W array, made up of `c [w1 w2 ...]` and `cFirst cLast w` entries
+fn parse_widths(doc: &Document, w: &Object) -> Result<HashMap<u16, f32>> {
+    let mut widths = HashMap::new();
+    let mut rest = &doc.dereference(w)?.1.as_array()?[..];
+    loop {
+        match rest {
+            [] => break,
+            [first, Object::Array(ws), tail @ ..] => {
+                let first = first.as_i64()?;
+                for (i, w) in ws.iter().enumerate() {
+                    widths.insert((first + i as i64) as u16, w.as_float()?);
+                }
+                rest = tail;
+            }
+            [first, last, w, tail @ ..] => {
+                let w = w.as_float()?;
+                for cid in first.as_i64()?.max(0)..=last.as_i64()?.min(u16::MAX as i64) {
+                    widths.insert(cid as u16, w);
+                }
+                rest = tail;
+            }
+            other => bail!("Malformed /W entry {:?}", other),
+        }
+    }
+    Ok(widths)
+}
+
+impl Font {
+    pub fn width(&self, cid: u16) -> f32 {
+        self.widths.get(&cid).copied().unwrap_or(self.default_width)
+    }
+
+    /// For vertical fonts, a glyph's vertical advance, normally negative, and
+    /// its position vector, as `[w1y vx vy]`.
+    pub fn vertical_metrics(&self, cid: u16) -> Option<[f32; 3]> {
+        let vertical = self.vertical.as_ref()?;
+        Some(vertical.metrics.get(&cid).copied().unwrap_or_else(|| {
+            let [vy, w1y] = vertical.default;
+            [w1y, self.width(cid) / 2., vy]
+        }))
+    }
+
+    /// Splits a shown string into character codes and their length in bytes.
+    pub fn codes(&self, bytes: &[u8]) -> Vec<(u16, usize)> {
+        if self.composite {
+            bytes
+                .chunks_exact(2)
+                .map(|b| (u16::from_be_bytes([b[0], b[1]]), 2))
+                .collect()
+        } else {
+            bytes.iter().map(|b| (*b as u16, 1)).collect()
+        }
+    }
+
+    /// The text a character code represents, from /ToUnicode or, for codes
+    /// of simple fonts without an entry there, the glyph name the encoding
+    /// gives it, or failing that reading the code as Latin-1.
+    pub fn unicode(&self, code: u16) -> Option<String> {
+        match self.to_unicode.get(&(code as u32)) {
+            Some(text) => Some(text.clone()),
+            None if !self.composite => Some(char::from(code as u8).to_string()),
+            None => None,
+        }
+    }
+
+    /// The glyph's outline in font units, or `None` if it has no outline.
+    pub fn glyph_path(&self, id: GlyphId) -> Option<Rc<BezPath>> {
+        self.glyph_lookups.set(self.glyph_lookups.get() + 1);
+        self.glyph_cache
+            .borrow_mut()
+            .entry(id.0)
+            .or_insert_with(|| {
+                let mut outline = PathOutline(BezPath::new());
+                self.font.outline_glyph(id, &mut outline)?;
+                Some(Rc::new(outline.0))
+            })
+            .clone()
+    }
+
+    /// How many outlines have been asked for, and how many of those had to
+    /// be built rather than found in the cache.
+    pub fn glyph_stats(&self) -> (usize, usize) {
+        (self.glyph_lookups.get(), self.glyph_cache.borrow().len())
+    }
+
+    pub fn glyph_id(&self, code: u16) -> GlyphId {
+        match &self.glyph_map {
+            Some(map) => GlyphId(map.get(code as usize).copied().unwrap_or(0)),
+            None => GlyphId(code),
+        }
     }
 }
 
@@ -51,3 +643,111 @@ pub fn load_font(data: Vec<u8>) -> Result<OwnedFace> {
 
     Ok(o)
 }
+
+#[cfg(test)]
+mod tests {
+    use kurbo::{Rect, Shape};
+    use lopdf::{ObjectId, dictionary};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn glyph_outlines_are_cached() {
+        let mut doc = document();
+        let font = truetype_font(&mut doc);
+        let font = Font::from_pdf(&doc, doc.get_object(font).unwrap()).unwrap();
+        let first = font.glyph_path(GlyphId(1)).unwrap();
+        let again = font.glyph_path(GlyphId(1)).unwrap();
+        assert!(Rc::ptr_eq(&first, &again));
+        assert!(font.glyph_path(GlyphId(0)).is_none());
+        assert_eq!(font.glyph_stats(), (3, 2));
+    }
+
+    #[test]
+    fn glyph_outlines_are_in_font_units() {
+        let mut doc = document();
+        let font = truetype_font(&mut doc);
+        let font = Font::from_pdf(&doc, doc.get_object(font).unwrap()).unwrap();
+        let half = font.glyph_path(GlyphId(2)).unwrap();
+        assert_eq!(half.bounding_box(), Rect::new(0., 0., 500., 1000.));
+    }
+
+    #[test]
+    fn descendant_fonts_and_descriptors_can_be_inline() {
+        let mut doc = document();
+        let font = cid_font(&mut doc, dictionary! { "DW" => 600 });
+        let dict = doc.get_dictionary(font).unwrap();
+        let descendant = dict.get(b"DescendantFonts").unwrap().as_array().unwrap()[0]
+            .as_reference()
+            .unwrap();
+        let mut descendant = doc.get_dictionary(descendant).unwrap().clone();
+        let descriptor = descendant
+            .get(b"FontDescriptor")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let descriptor = doc.get_dictionary(descriptor).unwrap().clone();
+        descendant.set("FontDescriptor", descriptor);
+        let mut dict = dict.clone();
+        dict.set("DescendantFonts", vec![descendant.into()]);
+
+        let font = Font::from_pdf(&doc, &dict.into()).unwrap();
+        assert_eq!(font.width(1), 600.);
+        assert!(font.glyph_path(GlyphId(1)).is_some());
+    }
+
+    fn vertical_font(doc: &mut Document, descendant: Dictionary) -> ObjectId {
+        let font = cid_font(doc, descendant);
+        let dict = doc.get_dictionary_mut(font).unwrap();
+        dict.set("Encoding", "Identity-V");
+        font
+    }
+
+    #[test]
+    fn vertical_metrics_come_from_w2_and_dw2() {
+        let mut doc = document();
+        let w2 = vec![
+            1.into(),
+            vec![(-500).into(), 250.into(), 900.into()].into(),
+            5.into(),
+            6.into(),
+            (-700).into(),
+            300.into(),
+            800.into(),
+        ];
+        let descendant = dictionary! {
+            "W" => vec![2.into(), vec![600.into()].into()],
+            "W2" => w2,
+            "DW2" => vec![880.into(), (-1000).into()],
+        };
+        let font = vertical_font(&mut doc, descendant);
+        let font = Font::from_pdf(&doc, doc.get_object(font).unwrap()).unwrap();
+        assert_eq!(font.vertical_metrics(1), Some([-500., 250., 900.]));
+        assert_eq!(font.vertical_metrics(6), Some([-700., 300., 800.]));
+        // Others are centered horizontally
+        assert_eq!(font.vertical_metrics(2), Some([-1000., 300., 880.]));
+
+        let horizontal = cid_font(&mut doc, Dictionary::new());
+        let horizontal = Font::from_pdf(&doc, doc.get_object(horizontal).unwrap()).unwrap();
+        assert_eq!(horizontal.vertical_metrics(1), None);
+    }
+
+    #[test]
+    fn vertical_text_runs_down_the_page() {
+        let doc = font_page(
+            |doc| vertical_font(doc, Dictionary::new()),
+            "BT /F1 10 Tf 50 80 Td <00010001> Tj ET",
+        );
+        let image = render(&doc);
+        // Each square is centered on the line, its top 0.88 em above where
+        // its position vector ends
+        for y in [76, 66] {
+            assert_eq!(pixel(&image, 50, y), BLACK, "at {y}");
+            assert_eq!(pixel(&image, 44, y), WHITE, "at {y}");
+            assert_eq!(pixel(&image, 56, y), WHITE, "at {y}");
+        }
+        assert_eq!(pixel(&image, 50, 60), WHITE);
+        assert_eq!(pixel(&image, 50, 82), WHITE);
+    }
+}
@@ -1,123 +1,229 @@
+pub mod cmap;
+pub mod encoding;
 pub mod font;
+pub mod substitute;
+pub mod type1;
+pub mod type3;
 
 use eyre::{Result, eyre};
-use kurbo::BezPath;
-use lopdf::Object;
-use owned_ttf_parser::{AsFaceRef, OutlineBuilder};
+use kurbo::{Affine, Stroke};
+use lopdf::{Object, content::Operation};
 use peniko::Fill;
-use vello::Scene;
 
-use crate::{Coord, DeviceScale, GraphicsState, RenderSettings, TextState, transform_from};
+use crate::{
+    CTM, DeviceScale, GraphicsState, RenderSettings, Resources, TextState, canvas::Canvas, concat,
+};
+use font::FontProgram;
 
 const TEXT_SCALE: f32 = 1000.;
 
-struct FontPath<'a> {
-    pub path: &'a mut BezPath,
-    units_per_em: u16,
-    ts: TextState,
-    scale: &'a DeviceScale,
+/// What's left to do for an operator once [`text_operation`] has made its
+/// changes to the text state.
+pub(crate) enum TextOperation<'a> {
+    /// Nothing; it only set or moved the text state.
+    State,
+    /// Show `glyphs`, the operand of a text showing operator.
+    Show(&'a [Object]),
+    /// It isn't a text operator.
+    Other,
 }
 
-impl<'a> FontPath<'a> {
-    fn tx(&mut self, Coord { x, y }: &Coord) -> Coord {
-        transform_from(
-            &Coord {
-                x: (x / self.units_per_em as f32 * self.ts.size)
-                    + (self.ts.position / TEXT_SCALE * self.ts.size),
-                y: (y) / self.units_per_em as f32 * self.ts.size,
-            },
-            &self.ts.matrix,
-            self.scale,
-        )
+/// Applies a text state or positioning operator to `ts`, along with the
+/// line moves and spacing the `'` and `"` showing operators start with, so
+/// rendering and text extraction follow text the same way. Ending a text
+/// object is left to the caller, since its clipping is drawn.
+pub(crate) fn text_operation<'a>(
+    ts: &mut TextState,
+    resources: &Resources,
+    op: &'a Operation,
+) -> Result<TextOperation<'a>> {
+    match (op.operator.as_str(), &op.operands[..]) {
+        ("BT", []) => ts.set_matrix(CTM::default()),
+        ("Tm", [a, b, c, d, e, f]) => ts.set_matrix(CTM {
+            a: a.as_float()?,
+            b: b.as_float()?,
+            c: c.as_float()?,
+            d: d.as_float()?,
+            e: e.as_float()?,
+            f: f.as_float()?,
+        }),
+        ("Tf", [Object::Name(n), size]) => {
+            if let Some(font) = resources.fonts.get(n) {
+                ts.font = Some(font.clone());
+                ts.set_size(size.as_float()?);
+            }
+        }
+        ("Td", [tx, ty]) => ts.next_line(tx.as_float()?, ty.as_float()?),
+        ("TD", [tx, ty]) => {
+            let ty = ty.as_float()?;
+            ts.leading = -ty;
+            ts.next_line(tx.as_float()?, ty);
+        }
+        ("TL", [leading]) => ts.leading = leading.as_float()?,
+        ("T*", []) => ts.next_line(0., -ts.leading),
+        ("Tc", [spacing]) => ts.char_spacing = spacing.as_float()?,
+        ("Tw", [spacing]) => ts.word_spacing = spacing.as_float()?,
+        ("Tz", [h_scale]) => ts.h_scale = h_scale.as_float()?,
+        ("Ts", [rise]) => ts.rise = rise.as_float()?,
+        ("Tr", [mode]) => ts.render_mode = mode.as_i64()?.clamp(0, 7) as u8,
+        ("TJ", [text]) => return Ok(TextOperation::Show(text.as_array()?)),
+        ("Tj", [text @ Object::String(..)]) => {
+            return Ok(TextOperation::Show(std::slice::from_ref(text)));
+        }
+        ("'", [text @ Object::String(..)]) => {
+            ts.next_line(0., -ts.leading);
+            return Ok(TextOperation::Show(std::slice::from_ref(text)));
+        }
+        ("\"", [aw, ac, text @ Object::String(..)]) => {
+            ts.word_spacing = aw.as_float()?;
+            ts.char_spacing = ac.as_float()?;
+            ts.next_line(0., -ts.leading);
+            return Ok(TextOperation::Show(std::slice::from_ref(text)));
+        }
+        _ => return Ok(TextOperation::Other),
     }
+    Ok(TextOperation::State)
 }
 
-impl OutlineBuilder for FontPath<'_> {
-    fn move_to(&mut self, x: f32, y: f32) {
-        let xy = self.tx(&Coord { x, y });
-        self.path.move_to((xy.x as f64, xy.y as f64));
-    }
-
-    fn line_to(&mut self, x: f32, y: f32) {
-        let xy = self.tx(&Coord { x, y });
-        self.path.line_to((xy.x as f64, xy.y as f64));
-    }
-
-    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
-        let xy1 = self.tx(&Coord { x: x1, y: y1 });
-        let xy = self.tx(&Coord { x, y });
-        self.path
-            .quad_to((xy1.x as f64, xy1.y as f64), (xy.x as f64, xy.y as f64));
+// Converts a text space distance to the glyph space units `position` is kept in
+pub(crate) fn to_position(ts: &TextState, distance: f32) -> f32 {
+    if ts.size == 0. {
+        0.
+    } else {
+        distance * TEXT_SCALE / ts.size
     }
+}
 
-    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
-        let xy1 = self.tx(&Coord { x: x1, y: y1 });
-        let xy2 = self.tx(&Coord { x: x2, y: y2 });
-        let xy = self.tx(&Coord { x, y });
-        self.path.curve_to(
-            (xy1.x as f64, xy1.y as f64),
-            (xy2.x as f64, xy2.y as f64),
-            (xy.x as f64, xy.y as f64),
-        );
-    }
+/// How far showing a glyph `width` thousandths of an em wide moves the text
+/// position, in the same units. This is the PDF spec's glyph displacement
+/// `tx = ((w0 - Tj / 1000) * Tfs + Tc + Tw) * Th` divided by `Tfs / 1000`,
+/// where the `Tj` adjustment is left to the numbers in a `TJ` array, which
+/// move the position back by `Tj` on their own, and `Th` is applied when
+/// the position is mapped to text space.
+pub(crate) fn advance(ts: &TextState, width: f32, code: u16, code_len: usize) -> f32 {
+    // Word spacing only applies to the single-byte code 32, so it never
+    // affects two-byte CID codes
+    let word_spacing = if code_len == 1 && code == 32 {
+        ts.word_spacing
+    } else {
+        0.
+    };
+    width + to_position(ts, ts.char_spacing + word_spacing)
+}
 
-    fn close(&mut self) {
-        self.path.close_path()
-    }
+// Maps glyph space at the current text position to text space. Vertical
+// fonts keep `position` as the distance down from the line's start, and
+// place each glyph so its position vector `v` ends there.
+fn glyph_transform(ts: &TextState, units_per_em: u16, v: Option<[f32; 2]>) -> Affine {
+    let glyph_scale = (ts.size / units_per_em as f32) as f64;
+    let h_scale = (ts.h_scale / 100.) as f64;
+    let (x, y) = match v {
+        None => (ts.position, 0.),
+        Some([vx, vy]) => (-vx, -(ts.position + vy)),
+    };
+    Affine::new([
+        glyph_scale * h_scale,
+        0.,
+        0.,
+        glyph_scale,
+        (x / TEXT_SCALE * ts.size) as f64 * h_scale,
+        (y / TEXT_SCALE * ts.size + ts.rise) as f64,
+    ])
 }
 
+/// Runs a Type3 glyph procedure in the given state, returning the advance it
+/// sets in glyph space.
+pub type CharProc<'a> =
+    dyn FnMut(&mut dyn Canvas, &GraphicsState, &Object) -> Result<Option<f32>> + 'a;
+
+/// Shows `glyphs`, the operand of a text showing operator. Type3 glyphs are
+/// handed to `char_proc` along with the graphics state to run them in, whose
+/// CTM maps glyph space to the device.
 pub fn draw_text(
     scale: &DeviceScale,
-    scene: &mut Scene,
+    scene: &mut dyn Canvas,
     gs: &mut GraphicsState,
     glyphs: &[Object],
     _render_settings: &RenderSettings,
+    char_proc: &mut CharProc,
 ) -> Result<()> {
-    let ts = gs
-        .text_state
-        .as_mut()
-        .ok_or_else(|| eyre!("no font state"))?;
-    let font = ts.font.as_ref().ok_or_else(|| eyre!("no font sent"))?;
+    let text_to_user = Affine::from(&gs.text_state.matrix);
+    let user_to_device = scale.affine(&gs.ctm);
+    let (fill, stroke) = (gs.paint(gs.non_stroke_color), gs.paint(gs.stroke_color));
+    let font = (gs.text_state.font.clone()).ok_or_else(|| eyre!("no font sent"))?;
 
-    let units_per_em = font.font.as_face_ref().units_per_em();
+    let units_per_em = font.font.units_per_em();
 
     for glyph in glyphs {
         match glyph {
             Object::String(bytes, _) => {
-                let glyph_ids = bytes
-                    .chunks_exact(2)
-                    .into_iter()
-                    .map(|b| u16::from_be_bytes([b[0], b[1]]));
-
-                for glyph_idx in glyph_ids {
-                    let glyph_id = owned_ttf_parser::GlyphId(glyph_idx);
-
-                    let width: f32 = *font.widths.get(glyph_id.0 as usize).unwrap_or(&0.);
-                    let mut path = FontPath {
-                        path: &mut BezPath::new(),
-                        units_per_em,
-                        ts: ts.clone(),
-                        scale,
-                    };
-
-                    match font.font.as_face_ref().outline_glyph(glyph_id, &mut path) {
-                        Some(_) => {
-                            use kurbo::Affine;
-                            scene.fill(
-                                Fill::EvenOdd,
-                                Affine::IDENTITY,
-                                gs.non_stroke_color,
-                                None,
-                                &*path.path,
-                            );
+                for (code, code_len) in font.codes(bytes) {
+                    let glyph_id = font.glyph_id(code);
+
+                    let mut width = font.width(code);
+                    let vertical = font.vertical_metrics(code);
+                    let v = vertical.map(|[_, vx, vy]| [vx, vy]);
+
+                    if let FontProgram::Type3(type3) = &font.font {
+                        let invisible = matches!(gs.text_state.render_mode, 3 | 7);
+                        let proc = type3.char_procs.get(&code).filter(|_| !invisible);
+                        if let Some(proc) = proc {
+                            let to_user = text_to_user
+                                * glyph_transform(&gs.text_state, 1, None)
+                                * Affine::from(&type3.matrix);
+                            let glyph_gs = GraphicsState {
+                                ctm: concat(&gs.ctm, &CTM::from(to_user)),
+                                ..gs.clone()
+                            };
+                            if let Some(advance) = char_proc(scene, &glyph_gs, proc)? {
+                                width = advance * type3.matrix.a * TEXT_SCALE;
+                            }
+                        }
+                    } else if let Some(glyph) = font.glyph_path(glyph_id) {
+                        let ts = &mut gs.text_state;
+                        let to_user = text_to_user * glyph_transform(ts, units_per_em, v);
+                        let transform = user_to_device * to_user;
+                        let mode = ts.render_mode;
+                        if matches!(mode, 0 | 2 | 4 | 6) {
+                            scene.fill(Fill::EvenOdd, transform, fill.into(), &glyph);
+                        }
+                        if matches!(mode, 1 | 2 | 5 | 6) {
+                            // Stroke in user space so the pen is shaped by the CTM
+                            // alone, as it is for paths, except for hairlines, which
+                            // are a device pixel wide
+                            let (pen, to_device, outline) = match gs.line_width > 0. {
+                                true => (
+                                    Stroke::new(gs.line_width as f64),
+                                    user_to_device,
+                                    to_user * &*glyph,
+                                ),
+                                false => (Stroke::new(1.), Affine::IDENTITY, transform * &*glyph),
+                            };
+                            scene.stroke(&pen, to_device, stroke.into(), &outline);
+                        }
+                        if mode >= 4 {
+                            ts.clip_path.extend(&(transform * &*glyph));
                         }
-                        _ => (),
                     }
 
-                    ts.position += width;
+                    // Vertical advances point down, the way `position` grows
+                    if let Some([w1y, ..]) = vertical {
+                        width = -w1y;
+                    }
+                    let ts = &mut gs.text_state;
+                    ts.position += advance(ts, width, code, code_len);
+                }
+            }
+            // TJ adjustments are in thousandths of an em, like widths, and
+            // move vertical text down rather than back
+            o => {
+                if let Ok(adjustment) = o.as_float() {
+                    match font.vertical {
+                        Some(_) => gs.text_state.position += adjustment,
+                        None => gs.text_state.position -= adjustment,
+                    }
                 }
             }
-            o => o.as_float().ok().iter().for_each(|s| ts.position -= s),
         }
     }
 
@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use eyre::{Result, eyre};
+
+/// System font files tried, in order, in place of each of the standard 14 fonts.
+///
+/// A file named in
+/// [`RenderSettings::font_substitutions`](crate::RenderSettings::font_substitutions)
+/// is used first, then the first of these installed under [`FONT_DIRS`],
+/// then, with the `bundled-fonts` feature, the fonts in [`BUNDLED`].
+/// Without any of them the text is skipped and the rest of the page drawn.
+pub const STANDARD_14: &[(&str, &[&str])] = &[
+    (
+        "Helvetica",
+        &[
+            "LiberationSans-Regular.ttf",
+            "Arial.ttf",
+            "arial.ttf",
+            "DejaVuSans.ttf",
+        ],
+    ),
+    (
+        "Helvetica-Bold",
+        &[
+            "LiberationSans-Bold.ttf",
+            "Arial Bold.ttf",
+            "arialbd.ttf",
+            "DejaVuSans-Bold.ttf",
+        ],
+    ),
+    (
+        "Helvetica-Oblique",
+        &[
+            "LiberationSans-Italic.ttf",
+            "Arial Italic.ttf",
+            "ariali.ttf",
+            "DejaVuSans-Oblique.ttf",
+        ],
+    ),
+    (
+        "Helvetica-BoldOblique",
+        &[
+            "LiberationSans-BoldItalic.ttf",
+            "Arial Bold Italic.ttf",
+            "arialbi.ttf",
+            "DejaVuSans-BoldOblique.ttf",
+        ],
+    ),
+    (
+        "Times-Roman",
+        &[
+            "LiberationSerif-Regular.ttf",
+            "Times New Roman.ttf",
+            "times.ttf",
+            "DejaVuSerif.ttf",
+        ],
+    ),
+    (
+        "Times-Bold",
+        &[
+            "LiberationSerif-Bold.ttf",
+            "Times New Roman Bold.ttf",
+            "timesbd.ttf",
+            "DejaVuSerif-Bold.ttf",
+        ],
+    ),
+    (
+        "Times-Italic",
+        &[
+            "LiberationSerif-Italic.ttf",
+            "Times New Roman Italic.ttf",
+            "timesi.ttf",
+            "DejaVuSerif-Italic.ttf",
+        ],
+    ),
+    (
+        "Times-BoldItalic",
+        &[
+            "LiberationSerif-BoldItalic.ttf",
+            "Times New Roman Bold Italic.ttf",
+            "timesbi.ttf",
+            "DejaVuSerif-BoldItalic.ttf",
+        ],
+    ),
+    (
+        "Courier",
+        &[
+            "LiberationMono-Regular.ttf",
+            "Courier New.ttf",
+            "cour.ttf",
+            "DejaVuSansMono.ttf",
+        ],
+    ),
+    (
+        "Courier-Bold",
+        &[
+            "LiberationMono-Bold.ttf",
+            "Courier New Bold.ttf",
+            "courbd.ttf",
+            "DejaVuSansMono-Bold.ttf",
+        ],
+    ),
+    (
+        "Courier-Oblique",
+        &[
+            "LiberationMono-Italic.ttf",
+            "Courier New Italic.ttf",
+            "couri.ttf",
+            "DejaVuSansMono-Oblique.ttf",
+        ],
+    ),
+    (
+        "Courier-BoldOblique",
+        &[
+            "LiberationMono-BoldItalic.ttf",
+            "Courier New Bold Italic.ttf",
+            "courbi.ttf",
+            "DejaVuSansMono-BoldOblique.ttf",
+        ],
+    ),
+    ("Symbol", &["Symbol.ttf", "symbol.ttf", "DejaVuSans.ttf"]),
+    ("ZapfDingbats", &["ZapfDingbats.ttf", "DejaVuSans.ttf"]),
+];
+
+/// Directories searched, recursively, for the files named in [`STANDARD_14`].
+pub const FONT_DIRS: &[&str] = &[
+    "/usr/share/fonts",
+    "/usr/local/share/fonts",
+    "/Library/Fonts",
+    "/System/Library/Fonts",
+    "C:\\Windows\\Fonts",
+];
+
+/// Fonts compiled in with the `bundled-fonts` feature, used in place of a
+/// standard 14 font when none of its files is installed. DejaVu Sans, Serif
+/// and Sans Mono stand in for every style of Helvetica, Times and Courier,
+/// and Sans for Symbol and ZapfDingbats.
+#[cfg(feature = "bundled-fonts")]
+pub const BUNDLED: &[(&str, &[u8])] = &[
+    ("Times", include_bytes!("../../fonts/DejaVuSerif.ttf")),
+    ("Courier", include_bytes!("../../fonts/DejaVuSansMono.ttf")),
+    ("", include_bytes!("../../fonts/DejaVuSans.ttf")),
+];
+
+// FontDescriptor /Flags bits
+const FIXED_PITCH: i64 = 1 << 0;
+const SERIF: i64 = 1 << 1;
+const ITALIC: i64 = 1 << 6;
+const FORCE_BOLD: i64 = 1 << 18;
+
+/// Loads a font file to stand in for a font that isn't embedded, looking
+/// it up by /BaseFont and then standard 14 name in `substitutions` before
+/// searching [`FONT_DIRS`] and falling back to [`BUNDLED`].
+pub fn substitute_font(
+    base_font: &str,
+    flags: i64,
+    substitutions: &HashMap<String, PathBuf>,
+) -> Result<Vec<u8>> {
+    let base_font = strip_subset(base_font);
+    let standard = standard_name(base_font, flags);
+
+    if let Some(path) = substitutions
+        .get(base_font)
+        .or_else(|| substitutions.get(standard))
+    {
+        return Ok(fs::read(path)?);
+    }
+
+    let (_, files) = STANDARD_14
+        .iter()
+        .find(|(name, _)| *name == standard)
+        .ok_or_else(|| eyre!("No substitute for {base_font}"))?;
+    let index = font_index();
+    if let Some(path) = files.iter().find_map(|file| index.get(*file)) {
+        return Ok(fs::read(path)?);
+    }
+    bundled(standard)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| eyre!("No substitute font file found for {base_font}"))
+}
+
+// The bundled font for a standard 14 font, by family prefix
+#[cfg(feature = "bundled-fonts")]
+fn bundled(standard: &str) -> Option<&'static [u8]> {
+    BUNDLED
+        .iter()
+        .find(|(family, _)| standard.starts_with(family))
+        .map(|(_, data)| *data)
+}
+
+#[cfg(not(feature = "bundled-fonts"))]
+fn bundled(_standard: &str) -> Option<&'static [u8]> {
+    None
+}
+
+// Subset fonts are named with a six letter tag, e.g. `ABCDEF+Helvetica`
+fn strip_subset(name: &str) -> &str {
+    match name.split_once('+') {
+        Some((tag, rest)) if tag.len() == 6 && tag.bytes().all(|b| b.is_ascii_uppercase()) => rest,
+        _ => name,
+    }
+}
+
+// Picks the closest standard 14 font from the name and descriptor flags
+fn standard_name(base_font: &str, flags: i64) -> &'static str {
+    if let Some((name, _)) = STANDARD_14.iter().find(|(name, _)| *name == base_font) {
+        return name;
+    }
+    let lower = base_font.to_ascii_lowercase();
+    if lower.contains("symbol") {
+        return "Symbol";
+    }
+    if lower.contains("dingbat") {
+        return "ZapfDingbats";
+    }
+
+    let bold = lower.contains("bold")
+        || lower.contains("black")
+        || lower.contains("heavy")
+        || flags & FORCE_BOLD != 0;
+    let italic = lower.contains("italic") || lower.contains("oblique") || flags & ITALIC != 0;
+
+    if lower.contains("courier") || lower.contains("mono") || flags & FIXED_PITCH != 0 {
+        match (bold, italic) {
+            (false, false) => "Courier",
+            (true, false) => "Courier-Bold",
+            (false, true) => "Courier-Oblique",
+            (true, true) => "Courier-BoldOblique",
+        }
+    } else if lower.contains("times")
+        || (flags & SERIF != 0 && !lower.contains("sans"))
+        || (lower.contains("serif") && !lower.contains("sans"))
+    {
+        match (bold, italic) {
+            (false, false) => "Times-Roman",
+            (true, false) => "Times-Bold",
+            (false, true) => "Times-Italic",
+            (true, true) => "Times-BoldItalic",
+        }
+    } else {
+        match (bold, italic) {
+            (false, false) => "Helvetica",
+            (true, false) => "Helvetica-Bold",
+            (false, true) => "Helvetica-Oblique",
+            (true, true) => "Helvetica-BoldOblique",
+        }
+    }
+}
+
+// Font files by file name, found once across FONT_DIRS
+fn font_index() -> &'static HashMap<String, PathBuf> {
+    static INDEX: OnceLock<HashMap<String, PathBuf>> = OnceLock::new();
+    INDEX.get_or_init(|| {
+        let mut index = HashMap::new();
+        for dir in FONT_DIRS {
+            scan(Path::new(dir), &mut index, 0);
+        }
+        index
+    })
+}
+
+fn scan(dir: &Path, index: &mut HashMap<String, PathBuf>, depth: usize) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth < 4 {
+                scan(&path, index, depth + 1);
+            }
+        } else if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            index.entry(name.to_string()).or_insert(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "bundled-fonts")]
+    #[test]
+    fn every_standard_font_has_a_bundled_fallback() {
+        for (name, _) in STANDARD_14 {
+            let data = bundled(name).unwrap();
+            assert!(owned_ttf_parser::Face::parse(data, 0).is_ok(), "{name}");
+        }
+        assert_eq!(bundled("Times-Bold"), Some(BUNDLED[0].1));
+        assert_eq!(bundled("Courier-Oblique"), Some(BUNDLED[1].1));
+        assert_eq!(bundled("Helvetica"), Some(BUNDLED[2].1));
+        assert_eq!(bundled("Symbol"), Some(BUNDLED[2].1));
+    }
+
+    #[test]
+    fn fonts_are_matched_to_the_closest_standard_font() {
+        assert_eq!(standard_name("Arial-BoldMT", 0), "Helvetica-Bold");
+        assert_eq!(standard_name("Georgia", SERIF | ITALIC), "Times-Italic");
+        assert_eq!(standard_name("Consolas", FIXED_PITCH), "Courier");
+        assert_eq!(strip_subset("ABCDEF+Georgia"), "Georgia");
+    }
+}
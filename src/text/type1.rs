@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+
+use eyre::{Result, bail, eyre};
+use owned_ttf_parser::{GlyphId, OutlineBuilder};
+
+use super::encoding::STANDARD_ENCODING;
+
+const EEXEC_KEY: u16 = 55665;
+const CHARSTRING_KEY: u16 = 4330;
+
+/// An embedded Type1 font program (`/FontFile`), decrypted into its charstrings.
+pub struct Type1Font {
+    /// The font's built-in encoding, mapping codes to glyph names.
+    pub encoding: Vec<Option<String>>,
+    pub glyph_names: Vec<String>,
+    charstrings: Vec<Vec<u8>>,
+    subrs: Vec<Vec<u8>>,
+    name_index: HashMap<String, u16>,
+}
+
+impl Type1Font {
+    pub fn parse(data: &[u8], length1: usize) -> Result<Self> {
+        let (clear, encrypted) = data.split_at(length1.min(data.len()));
+        let encrypted = if is_hex(encrypted) {
+            decode_hex(encrypted)
+        } else {
+            encrypted.to_vec()
+        };
+        let private = decrypt(&encrypted, EEXEC_KEY, 4);
+
+        let len_iv = find_number(&private, b"/lenIV").unwrap_or(4) as usize;
+        let subrs = parse_subrs(&private)
+            .into_iter()
+            .map(|s| decrypt(&s, CHARSTRING_KEY, len_iv))
+            .collect();
+
+        let mut glyph_names = Vec::new();
+        let mut charstrings = Vec::new();
+        for (name, charstring) in parse_charstrings(&private)? {
+            glyph_names.push(name);
+            charstrings.push(decrypt(&charstring, CHARSTRING_KEY, len_iv));
+        }
+        let name_index = glyph_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i as u16))
+            .collect();
+
+        Ok(Type1Font {
+            encoding: parse_encoding(clear),
+            glyph_names,
+            charstrings,
+            subrs,
+            name_index,
+        })
+    }
+
+    pub fn glyph_index_by_name(&self, name: &str) -> Option<GlyphId> {
+        self.name_index.get(name).copied().map(GlyphId)
+    }
+
+    pub fn glyph_index(&self, code: u8) -> Option<GlyphId> {
+        self.glyph_index_by_name(self.encoding.get(code as usize)?.as_deref()?)
+    }
+
+    /// Outlines a glyph in its 1000 unit glyph space, returning its advance width.
+    pub fn outline_glyph(&self, id: GlyphId, builder: &mut dyn OutlineBuilder) -> Option<f32> {
+        let charstring = self.charstrings.get(id.0 as usize)?;
+        let mut interpreter = Interpreter {
+            font: self,
+            builder,
+            stack: Vec::new(),
+            ps_stack: Vec::new(),
+            x: 0.,
+            y: 0.,
+            width: 0.,
+            flex: None,
+            open: false,
+            offset: (0., 0.),
+        };
+        interpreter.run(charstring, 0).ok()?;
+        interpreter.close_path();
+        Some(interpreter.width)
+    }
+}
+
+struct Interpreter<'a> {
+    font: &'a Type1Font,
+    builder: &'a mut dyn OutlineBuilder,
+    stack: Vec<f32>,
+    ps_stack: Vec<f32>,
+    x: f32,
+    y: f32,
+    width: f32,
+    flex: Option<Vec<(f32, f32)>>,
+    open: bool,
+    offset: (f32, f32),
+}
+
+impl Interpreter<'_> {
+    fn run(&mut self, charstring: &[u8], depth: usize) -> Result<bool> {
+        if depth > 10 {
+            bail!("Type1 subroutines nested too deeply");
+        }
+        let mut i = 0;
+        while i < charstring.len() {
+            let v = charstring[i];
+            i += 1;
+            match v {
+                32..=246 => self.stack.push(v as f32 - 139.),
+                247..=250 => {
+                    let w = *charstring.get(i).ok_or_else(|| eyre!("truncated"))?;
+                    i += 1;
+                    self.stack
+                        .push(((v as f32 - 247.) * 256.) + w as f32 + 108.);
+                }
+                251..=254 => {
+                    let w = *charstring.get(i).ok_or_else(|| eyre!("truncated"))?;
+                    i += 1;
+                    self.stack
+                        .push(-((v as f32 - 251.) * 256.) - w as f32 - 108.);
+                }
+                255 => {
+                    let bytes = charstring.get(i..i + 4).ok_or_else(|| eyre!("truncated"))?;
+                    i += 4;
+                    self.stack
+                        .push(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32);
+                }
+                12 => {
+                    let op = *charstring.get(i).ok_or_else(|| eyre!("truncated"))?;
+                    i += 1;
+                    if self.escape(op, depth)? {
+                        return Ok(true);
+                    }
+                }
+                op => {
+                    if self.operator(op, depth)? {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn operator(&mut self, op: u8, depth: usize) -> Result<bool> {
+        let args = std::mem::take(&mut self.stack);
+        match (op, &args[..]) {
+            // hstem, vstem: hints are ignored
+            (1 | 3, _) => {}
+            (4, [.., dy]) => self.move_by(0., *dy),
+            (5, [.., dx, dy]) => self.line_by(*dx, *dy),
+            (6, [.., dx]) => self.line_by(*dx, 0.),
+            (7, [.., dy]) => self.line_by(0., *dy),
+            (8, [.., dx1, dy1, dx2, dy2, dx3, dy3]) => {
+                self.curve_by(*dx1, *dy1, *dx2, *dy2, *dx3, *dy3)
+            }
+            (9, _) => self.close_path(),
+            (10, [rest @ .., subr]) => {
+                self.stack = rest.to_vec();
+                let subr = self
+                    .font
+                    .subrs
+                    .get(*subr as usize)
+                    .ok_or_else(|| eyre!("Missing Type1 subroutine {subr}"))?;
+                return self.run(subr, depth + 1);
+            }
+            (11, _) => {
+                self.stack = args;
+                return Ok(true);
+            }
+            (13, [.., sbx, wx]) => {
+                self.x = *sbx;
+                self.y = 0.;
+                self.width = *wx;
+            }
+            (14, _) => {
+                self.close_path();
+                return Ok(true);
+            }
+            (21, [.., dx, dy]) => self.move_by(*dx, *dy),
+            (22, [.., dx]) => self.move_by(*dx, 0.),
+            (30, [.., dy1, dx2, dy2, dx3]) => self.curve_by(0., *dy1, *dx2, *dy2, *dx3, 0.),
+            (31, [.., dx1, dx2, dy2, dy3]) => self.curve_by(*dx1, 0., *dx2, *dy2, 0., *dy3),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn escape(&mut self, op: u8, depth: usize) -> Result<bool> {
+        match op {
+            // seac: an accented character built from two standard glyphs
+            6 => {
+                let args = std::mem::take(&mut self.stack);
+                if let [.., asb, adx, ady, bchar, achar] = args[..] {
+                    self.seac_component(bchar, 0., 0., depth)?;
+                    self.seac_component(achar, adx - asb, ady, depth)?;
+                }
+                return Ok(true);
+            }
+            // sbw
+            7 => {
+                let args = std::mem::take(&mut self.stack);
+                if let [.., sbx, sby, wx, _wy] = args[..] {
+                    self.x = sbx;
+                    self.y = sby;
+                    self.width = wx;
+                }
+            }
+            // div
+            12 => {
+                let b = self.stack.pop().unwrap_or(1.);
+                let a = self.stack.pop().unwrap_or(0.);
+                self.stack.push(if b == 0. { 0. } else { a / b });
+            }
+            // callothersubr
+            16 => {
+                let n = self.stack.pop().unwrap_or(0.) as i32;
+                let count = self.stack.pop().unwrap_or(0.) as usize;
+                let split = self.stack.len().saturating_sub(count);
+                let args = self.stack.split_off(split);
+                match n {
+                    0 => {
+                        if let Some(&[_, c1, c2, p1, c3, c4, p2]) = self.flex.take().as_deref() {
+                            self.curve_to(c1, c2, p1);
+                            self.curve_to(c3, c4, p2);
+                        }
+                        if let [_, x, y] = args[..] {
+                            self.ps_stack = vec![y, x];
+                        }
+                    }
+                    1 => self.flex = Some(Vec::new()),
+                    2 => {}
+                    _ => self.ps_stack = args.into_iter().rev().collect(),
+                }
+            }
+            // pop
+            17 => {
+                if let Some(v) = self.ps_stack.pop() {
+                    self.stack.push(v);
+                }
+            }
+            // setcurrentpoint
+            33 => {
+                let args = std::mem::take(&mut self.stack);
+                if let [.., x, y] = args[..] {
+                    self.x = x;
+                    self.y = y;
+                }
+            }
+            // dotsection, vstem3, hstem3
+            _ => self.stack.clear(),
+        }
+        Ok(false)
+    }
+
+    fn seac_component(&mut self, code: f32, dx: f32, dy: f32, depth: usize) -> Result<()> {
+        let name = STANDARD_ENCODING
+            .get(code as usize)
+            .copied()
+            .ok_or_else(|| eyre!("Invalid seac code {code}"))?;
+        let id = self
+            .font
+            .glyph_index_by_name(name)
+            .ok_or_else(|| eyre!("Missing seac glyph {name}"))?;
+        let width = self.width;
+        self.stack.clear();
+        self.offset = (dx, dy);
+        let font = self.font;
+        self.run(&font.charstrings[id.0 as usize], depth + 1)?;
+        self.close_path();
+        self.offset = (0., 0.);
+        self.width = width;
+        Ok(())
+    }
+
+    fn close_path(&mut self) {
+        if self.open {
+            self.builder.close();
+            self.open = false;
+        }
+    }
+
+    fn move_by(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+        if let Some(points) = &mut self.flex {
+            points.push((self.x, self.y));
+            return;
+        }
+        if self.open {
+            self.builder.close();
+        }
+        self.builder
+            .move_to(self.x + self.offset.0, self.y + self.offset.1);
+        self.open = true;
+    }
+
+    fn line_by(&mut self, dx: f32, dy: f32) {
+        self.x += dx;
+        self.y += dy;
+        self.builder
+            .line_to(self.x + self.offset.0, self.y + self.offset.1);
+    }
+
+    fn curve_by(&mut self, dx1: f32, dy1: f32, dx2: f32, dy2: f32, dx3: f32, dy3: f32) {
+        let c1 = (self.x + dx1, self.y + dy1);
+        let c2 = (c1.0 + dx2, c1.1 + dy2);
+        let p = (c2.0 + dx3, c2.1 + dy3);
+        self.curve_to(c1, c2, p);
+    }
+
+    fn curve_to(&mut self, c1: (f32, f32), c2: (f32, f32), p: (f32, f32)) {
+        let (ox, oy) = self.offset;
+        self.builder.curve_to(
+            c1.0 + ox,
+            c1.1 + oy,
+            c2.0 + ox,
+            c2.1 + oy,
+            p.0 + ox,
+            p.1 + oy,
+        );
+        self.x = p.0;
+        self.y = p.1;
+    }
+}
+
+fn decrypt(data: &[u8], key: u16, skip: usize) -> Vec<u8> {
+    let mut r = key;
+    let plain: Vec<u8> = data
+        .iter()
+        .map(|c| {
+            let p = c ^ (r >> 8) as u8;
+            r = (*c as u16)
+                .wrapping_add(r)
+                .wrapping_mul(52845)
+                .wrapping_add(22719);
+            p
+        })
+        .collect();
+    plain.get(skip..).unwrap_or_default().to_vec()
+}
+
+fn is_hex(data: &[u8]) -> bool {
+    data.iter()
+        .take(4)
+        .all(|b| b.is_ascii_hexdigit() || b.is_ascii_whitespace())
+}
+
+fn decode_hex(data: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = data
+        .iter()
+        .filter_map(|b| (*b as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|d| d[0] << 4 | d.get(1).unwrap_or(&0))
+        .collect()
+}
+
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
+
+fn skip_whitespace(data: &[u8], mut i: usize) -> usize {
+    while i < data.len() && data[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+fn read_token(data: &[u8], i: usize) -> (&[u8], usize) {
+    let start = skip_whitespace(data, i);
+    let mut end = start;
+    while end < data.len() && !data[end].is_ascii_whitespace() {
+        end += 1;
+        if matches!(data.get(end), Some(b'/' | b'[' | b'{')) {
+            break;
+        }
+    }
+    (&data[start..end], end)
+}
+
+fn read_number(data: &[u8], i: usize) -> Option<(i64, usize)> {
+    let (token, end) = read_token(data, i);
+    Some((std::str::from_utf8(token).ok()?.parse().ok()?, end))
+}
+
+fn find_number(data: &[u8], key: &[u8]) -> Option<i64> {
+    let at = find(data, key, 0)?;
+    read_number(data, at + key.len()).map(|(n, _)| n)
+}
+
+// Reads the binary payload following `<len> RD ` (or `-|`), returning it and the end offset
+fn read_binary(data: &[u8], i: usize) -> Option<(Vec<u8>, usize)> {
+    let (len, i) = read_number(data, i)?;
+    let (_rd, i) = read_token(data, i);
+    let start = i + 1;
+    let end = start.checked_add(len as usize)?;
+    Some((data.get(start..end)?.to_vec(), end))
+}
+
+fn parse_subrs(private: &[u8]) -> Vec<Vec<u8>> {
+    let Some(at) = find(private, b"/Subrs", 0) else {
+        return Vec::new();
+    };
+    let Some((count, mut i)) = read_number(private, at + 6) else {
+        return Vec::new();
+    };
+    let mut subrs = vec![Vec::new(); count.clamp(0, 65536) as usize];
+    loop {
+        let (token, next) = read_token(private, i);
+        if token != b"dup" {
+            break;
+        }
+        let Some((index, next)) = read_number(private, next) else {
+            break;
+        };
+        let Some((subr, next)) = read_binary(private, next) else {
+            break;
+        };
+        if let Some(slot) = subrs.get_mut(index as usize) {
+            *slot = subr;
+        }
+        // Skip the trailing `NP` / `|` / `noaccess put`
+        i = next;
+        loop {
+            let (token, next) = read_token(private, i);
+            if token == b"dup" || token.is_empty() || token == b"readonly" || token == b"def" {
+                break;
+            }
+            i = next;
+        }
+    }
+    subrs
+}
+
+fn parse_charstrings(private: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let at = find(private, b"/CharStrings", 0).ok_or_else(|| eyre!("No Type1 CharStrings"))?;
+    let mut i = at + 12;
+    let mut charstrings = Vec::new();
+    // Skip `<count> dict dup begin`
+    loop {
+        let (token, next) = read_token(private, i);
+        if token.is_empty() {
+            return Ok(charstrings);
+        }
+        if token.starts_with(b"/") {
+            break;
+        }
+        i = next;
+    }
+    loop {
+        let (token, next) = read_token(private, i);
+        if !token.starts_with(b"/") {
+            break;
+        }
+        let name = String::from_utf8_lossy(&token[1..]).into_owned();
+        let Some((charstring, next)) = read_binary(private, next) else {
+            break;
+        };
+        charstrings.push((name, charstring));
+        // Skip the trailing `ND` / `|-` / `noaccess def`
+        i = next;
+        loop {
+            let (token, next) = read_token(private, i);
+            if token.starts_with(b"/") || token.is_empty() || token == b"end" {
+                break;
+            }
+            i = next;
+        }
+    }
+    Ok(charstrings)
+}
+
+fn parse_encoding(clear: &[u8]) -> Vec<Option<String>> {
+    let standard = || {
+        STANDARD_ENCODING
+            .iter()
+            .map(|name| (!name.is_empty()).then(|| name.to_string()))
+            .collect()
+    };
+    let Some(at) = find(clear, b"/Encoding", 0) else {
+        return standard();
+    };
+    let (token, mut i) = read_token(clear, at + 9);
+    if token == b"StandardEncoding" {
+        return standard();
+    }
+    let mut encoding = vec![None; 256];
+    while let Some(dup) = find(clear, b"dup", i) {
+        let Some((code, next)) = read_number(clear, dup + 3) else {
+            break;
+        };
+        let (name, next) = read_token(clear, next);
+        if let (Some(slot), Some(name)) = (encoding.get_mut(code as usize), name.strip_prefix(b"/"))
+        {
+            *slot = Some(String::from_utf8_lossy(name).into_owned());
+        }
+        i = next;
+        let (token, _) = read_token(clear, i);
+        if token != b"put" {
+            break;
+        }
+        i = read_token(clear, i).1;
+    }
+    encoding
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+use eyre::Result;
+use lopdf::{Dictionary, Document, Object};
+
+use super::encoding::{base_encoding, font_encoding};
+use crate::{CTM, get};
+
+/// A Type3 font, whose glyphs are content streams drawn in glyph space.
+pub struct Type3Font {
+    /// Maps glyph space to text space.
+    pub matrix: CTM,
+    /// The glyph procedure each character code draws.
+    pub char_procs: HashMap<u16, Object>,
+    /// Resources for the glyph procedures; older files leave these to the page.
+    pub resources: Option<Dictionary>,
+}
+
+impl Type3Font {
+    pub fn parse(doc: &Document, font: &Dictionary) -> Result<Self> {
+        let matrix = get(doc, font.get(b"FontMatrix")?)?;
+        let procs = doc.get_dict_in_dict(font, b"CharProcs")?;
+
+        // Codes name their procedures through the font's encoding
+        let mut names = base_encoding(b"StandardEncoding").unwrap_or_default();
+        names.extend(font_encoding(doc, font));
+        let char_procs = names
+            .into_iter()
+            .filter_map(|(code, name)| Some((code as u16, procs.get(&name).ok()?.clone())))
+            .collect();
+
+        Ok(Type3Font {
+            matrix,
+            char_procs,
+            resources: doc.get_dict_in_dict(font, b"Resources").ok().cloned(),
+        })
+    }
+}
@@ -0,0 +1,45 @@
+use lopdf::Document;
+use wasm_bindgen::prelude::*;
+
+use crate::{RenderSettings, offscreen::pdf_to_rgba_bytes};
+
+/// A rendered page's pixels, as RGBA rows `width * 4` bytes long.
+#[wasm_bindgen]
+pub struct RenderedPage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl RenderedPage {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Copies the pixels out, e.g. into an `ImageData`.
+    #[wasm_bindgen(getter)]
+    pub fn pixels(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+/// Renders a 1-based page of a PDF with the browser's GPU.
+#[wasm_bindgen(js_name = renderPage)]
+pub async fn render_page(bytes: Vec<u8>, page: u32, scale: f32) -> Result<RenderedPage, JsError> {
+    let doc = Document::load_mem(&bytes).map_err(|e| JsError::new(&e.to_string()))?;
+    let (width, height, pixels) = pdf_to_rgba_bytes(&doc, page, scale, &RenderSettings::default())
+        .await
+        .map_err(|e| JsError::new(&format!("{e:#}")))?;
+    Ok(RenderedPage {
+        width,
+        height,
+        pixels,
+    })
+}
@@ -0,0 +1,604 @@
+use eyre::{Result, bail, eyre};
+use lopdf::{Document, Object};
+use peniko::{Blob, Color, Image, ImageFormat, ImageQuality};
+use zune_jpeg::{
+    JpegDecoder,
+    zune_core::{colorspace::ColorSpace as JpegColorSpace, options::DecoderOptions},
+};
+
+use crate::{
+    FromPDF,
+    color::ColorSpace,
+    filter::{IMAGE_FILTERS, decode_stream},
+    get,
+};
+
+/// Decodes an image to RGBA. Image masks (`/ImageMask true`) are stencils
+/// and come out as `fill` wherever they let paint through.
+pub fn decode_image(doc: &Document, root: &Object, fill: Color) -> Result<Image> {
+    let stream = doc.dereference(root)?.1.as_stream()?;
+    let dict = &stream.dict;
+    let width = dict.get(b"Width")?.as_i64()? as u32;
+    let height = dict.get(b"Height")?.as_i64()? as u32;
+
+    let image_mask = dict
+        .get(b"ImageMask")
+        .and_then(|m| m.as_bool())
+        .unwrap_or(false);
+    let filters = stream.filters().unwrap_or_default();
+    // Only JPEG is decoded; the other codecs' data would be read as samples
+    if let Some(codec) = filters.iter().find(|f| IMAGE_FILTERS.contains(f))
+        && *codec != b"DCTDecode"
+    {
+        bail!(
+            "Unsupported image filter {}",
+            String::from_utf8_lossy(codec)
+        );
+    }
+    let decode: Option<Vec<f32>> = match dict.get(b"Decode") {
+        Ok(decode) => Some(get(doc, decode)?),
+        Err(_) => None,
+    };
+    let mut rgba = if image_mask {
+        let data: Vec<u8> = get(doc, root)?;
+        let inverted = decode.as_ref().and_then(|d| d.first()) == Some(&1.);
+        stencil(&data, width, height, inverted, fill)?
+    } else if filters.last().is_some_and(|f| *f == b"DCTDecode") {
+        let data = decode_stream(doc, stream)?;
+        decode_jpeg(&data, width, height, decode.as_deref())?
+    } else {
+        let bpc = dict.get(b"BitsPerComponent")?.as_i64()?;
+        if !matches!(bpc, 1 | 2 | 4 | 8 | 16) {
+            bail!("Unsupported image BitsPerComponent {bpc}");
+        }
+        let space = ColorSpace::from_pdf(doc, dict.get(b"ColorSpace")?)?;
+        let data: Vec<u8> = get(doc, root)?;
+        to_rgba(
+            &space,
+            &data,
+            width,
+            height,
+            bpc as usize,
+            decode.as_deref(),
+        )?
+    };
+
+    // A mask named /None (as in an ExtGState) means there isn't one
+    let smask = dict.get(b"SMask").ok().filter(|m| m.as_name().is_err());
+    if let Some(smask) = smask.filter(|_| !image_mask) {
+        apply_soft_mask(doc, smask, &mut rgba, width, height)?;
+    }
+
+    let image = Image::new(Blob::from(rgba), ImageFormat::Rgba8, width, height);
+    // Smoothing a stencil blurs its edges
+    Ok(if image_mask {
+        image.with_quality(ImageQuality::Low)
+    } else {
+        image
+    })
+}
+
+fn decode_jpeg(data: &[u8], width: u32, height: u32, decode: Option<&[f32]>) -> Result<Vec<u8>> {
+    let decoded = match decode_cmyk_jpeg(data, width, height, decode)? {
+        Some(decoded) => decoded,
+        // The decoder converts YCbCr and YCCK data to RGB for us
+        None => {
+            let mut decoded =
+                image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)?.to_rgba8();
+            if let Some(decode) = decode {
+                decode_rgb(&mut decoded, decode);
+            }
+            decoded
+        }
+    };
+    let decoded = if decoded.dimensions() != (width, height) {
+        image::imageops::resize(
+            &decoded,
+            width,
+            height,
+            image::imageops::FilterType::Triangle,
+        )
+    } else {
+        decoded
+    };
+    Ok(decoded.into_raw())
+}
+
+// The image crate assumes all CMYK JPEGs are stored inverted, as Adobe's
+// are, so these are decoded here and only inverted when they say so. They
+// must be the size the image dictionary gives, which bounds what's allocated.
+fn decode_cmyk_jpeg(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    decode: Option<&[f32]>,
+) -> Result<Option<image::RgbaImage>> {
+    let options = DecoderOptions::default()
+        .set_strict_mode(false)
+        .set_max_width(width as usize)
+        .set_max_height(height as usize);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    decoder.decode_headers()?;
+    if decoder.get_input_colorspace() != Some(JpegColorSpace::CMYK) {
+        return Ok(None);
+    }
+    let size = decoder
+        .dimensions()
+        .ok_or_else(|| eyre!("JPEG has no dimensions"))?;
+    if size != (width as usize, height as usize) {
+        bail!("JPEG is {}x{}, not {width}x{height}", size.0, size.1);
+    }
+    decoder.set_options(options.jpeg_set_out_colorspace(JpegColorSpace::CMYK));
+    let mut cmyk = decoder.decode()?;
+    if has_adobe_marker(data) {
+        cmyk.iter_mut().for_each(|c| *c = 255 - *c);
+    }
+
+    let rgba = to_rgba(&ColorSpace::DeviceCMYK, &cmyk, width, height, 8, decode)?;
+    let image = image::RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| eyre!("JPEG data doesn't match its size"))?;
+    Ok(Some(image))
+}
+
+// Maps each decoded channel onto its /Decode range, taking a gray image's
+// one range for all three
+fn decode_rgb(rgba: &mut [u8], decode: &[f32]) {
+    let ranges: Vec<[f32; 2]> = decode.chunks_exact(2).map(|d| [d[0], d[1]]).collect();
+    if !matches!(ranges.len(), 1 | 3) {
+        return;
+    }
+    for pixel in rgba.chunks_exact_mut(4) {
+        for (c, [low, high]) in pixel[..3].iter_mut().zip(ranges.iter().cycle()) {
+            *c = to_byte(low + *c as f32 / 255. * (high - low));
+        }
+    }
+}
+
+// Whether an APP14 segment from Adobe comes before the image data
+fn has_adobe_marker(data: &[u8]) -> bool {
+    let mut i = 2;
+    while let [0xFF, marker, high, low, ..] = data[i.min(data.len())..] {
+        if marker == 0xDA {
+            break;
+        }
+        let length = u16::from_be_bytes([high, low]) as usize;
+        if marker == 0xEE && data[i + 4..].starts_with(b"Adobe") {
+            return true;
+        }
+        i += 2 + length;
+    }
+    false
+}
+
+// Takes each pixel's alpha from the gray soft mask image, stretched to fit
+fn apply_soft_mask(
+    doc: &Document,
+    smask: &Object,
+    rgba: &mut [u8],
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let mask = decode_image(doc, smask, Color::BLACK)?;
+    let mask = image::RgbaImage::from_raw(mask.width, mask.height, mask.data.data().to_vec())
+        .ok_or_else(|| eyre!("Soft mask data doesn't match its size"))?;
+    let mask = if mask.dimensions() != (width, height) {
+        image::imageops::resize(&mask, width, height, image::imageops::FilterType::Triangle)
+    } else {
+        mask
+    };
+    for (pixel, alpha) in rgba.chunks_exact_mut(4).zip(mask.pixels()) {
+        pixel[3] = alpha[0];
+    }
+    Ok(())
+}
+
+// Paints `fill` where a sample is 0, or 1 when the mask's /Decode is [1 0]
+fn stencil(data: &[u8], width: u32, height: u32, inverted: bool, fill: Color) -> Result<Vec<u8>> {
+    let [r, g, b, a] = fill.to_rgba8().to_u8_array();
+    let (width, height) = (width as usize, height as usize);
+    let row = width.div_ceil(8);
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| eyre!("Image mask {width}x{height} is too large"))?;
+    if data.len() < row * height {
+        bail!("Image mask data too short for {width}x{height}");
+    }
+    let mut rgba = Vec::with_capacity(size);
+    for y in 0..height {
+        for x in 0..width {
+            let bit = data[y * row + x / 8] >> (7 - x % 8) & 1;
+            if (bit == 1) == inverted {
+                rgba.extend([r, g, b, a]);
+            } else {
+                rgba.extend([0, 0, 0, 0]);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+fn to_rgba(
+    space: &ColorSpace,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bpc: usize,
+    decode: Option<&[f32]>,
+) -> Result<Vec<u8>> {
+    let n = space.components();
+    let (width, height) = (width as usize, height as usize);
+    // Rows start on a byte boundary
+    let stride = width
+        .checked_mul(n * bpc)
+        .map(|bits| bits.div_ceil(8))
+        .ok_or_else(|| eyre!("Image {width}x{height} is too large"))?;
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| eyre!("Image {width}x{height} is too large"))?;
+    if n == 0
+        || stride
+            .checked_mul(height)
+            .is_none_or(|len| data.len() < len)
+    {
+        bail!("Image data too short for {width}x{height} {:?}", space);
+    }
+
+    // Each component maps 0..2^bpc-1 linearly onto its /Decode range, which
+    // is 0..1 by default, or the palette indices for Indexed images
+    let max = ((1 << bpc) - 1) as f32;
+    let default = match space {
+        ColorSpace::Indexed { .. } => [0., max],
+        _ => [0., 1.],
+    };
+    let ranges: Vec<[f32; 2]> = match decode {
+        Some(decode) if decode.len() >= 2 * n => decode
+            .chunks_exact(2)
+            .take(n)
+            .map(|d| [d[0], d[1]])
+            .collect(),
+        _ => vec![default; n],
+    };
+
+    let mut rgba = Vec::with_capacity(size);
+    let mut components = Vec::with_capacity(n);
+    for row in data.chunks(stride).take(height) {
+        for x in 0..width {
+            components.clear();
+            components.extend(ranges.iter().enumerate().map(|(i, [low, high])| {
+                low + sample(row, x * n + i, bpc) as f32 * (high - low) / max
+            }));
+            let [r, g, b] = space.to_rgb(&components)?;
+            rgba.extend([to_byte(r), to_byte(g), to_byte(b), 255]);
+        }
+    }
+    Ok(rgba)
+}
+
+// The `index`th sample of a row packed `bpc` bits at a time, high bits first
+fn sample(row: &[u8], index: usize, bpc: usize) -> u16 {
+    match bpc {
+        8 => row[index] as u16,
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        _ => {
+            let bit = index * bpc;
+            (row[bit / 8] >> (8 - bpc - bit % 8)) as u16 & ((1 << bpc) - 1)
+        }
+    }
+}
+
+fn to_byte(c: f32) -> u8 {
+    (c * 255.).round().clamp(0., 255.) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ExtendedColorType, codecs::jpeg::JpegEncoder};
+    use lopdf::{Dictionary, Stream, dictionary};
+
+    use super::*;
+
+    fn jpeg(pixels: &[u8], width: u32, height: u32, color: ExtendedColorType) -> Vec<u8> {
+        let mut data = Vec::new();
+        JpegEncoder::new_with_quality(&mut data, 100)
+            .encode(pixels, width, height, color)
+            .unwrap();
+        data
+    }
+
+    // Decodes an image stream, filling stencils in black
+    fn decode(dict: Dictionary, data: Vec<u8>) -> Vec<u8> {
+        let mut doc = Document::new();
+        let image = doc.add_object(Stream::new(dict, data));
+        let image = decode_image(&doc, &image.into(), Color::BLACK).unwrap();
+        image.data.data().to_vec()
+    }
+
+    fn close(a: &[u8], b: &[u8]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 2)
+    }
+
+    #[test]
+    fn dct_images_are_decoded_as_jpeg() {
+        let red = [200, 30, 30].repeat(64);
+        let data = jpeg(&red, 8, 8, ExtendedColorType::Rgb8);
+        let rgba = decode(
+            dictionary! {
+                "Width" => 8,
+                "Height" => 8,
+                "ColorSpace" => "DeviceRGB",
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+            },
+            data,
+        );
+        assert_eq!(rgba.len(), 8 * 8 * 4);
+        assert!(
+            rgba.chunks(4)
+                .all(|pixel| close(pixel, &[200, 30, 30, 255]))
+        );
+    }
+
+    fn mask(width: i64, height: i64, decode: &[i64]) -> Dictionary {
+        dictionary! {
+            "Width" => width,
+            "Height" => height,
+            "ImageMask" => true,
+            "Decode" => decode.iter().map(|d| (*d).into()).collect::<Vec<Object>>(),
+        }
+    }
+
+    #[test]
+    fn image_masks_are_stencils() {
+        let (paint, clear) = ([0, 0, 0, 255], [0; 4]);
+        let data = vec![0b0100_0000, 0b1000_0000];
+        let rgba = decode(mask(2, 2, &[0, 1]), data.clone());
+        assert_eq!(rgba, [paint, clear, clear, paint].concat());
+        let inverted = decode(mask(2, 2, &[1, 0]), data);
+        assert_eq!(inverted, [clear, paint, paint, clear].concat());
+    }
+
+    #[test]
+    fn image_masks_need_data_for_every_row() {
+        let mut doc = Document::new();
+        for (width, height) in [(9, 2), (u32::MAX as i64, u32::MAX as i64)] {
+            let image = doc.add_object(Stream::new(mask(width, height, &[0, 1]), vec![0; 3]));
+            assert!(decode_image(&doc, &image.into(), Color::BLACK).is_err());
+        }
+    }
+
+    #[test]
+    fn images_too_large_to_address_are_errors() {
+        let mut doc = Document::new();
+        for (width, height) in [(1 << 30, 1 << 31), (u32::MAX as i64, u32::MAX as i64)] {
+            let dict = dictionary! {
+                "Width" => width,
+                "Height" => height,
+                "ColorSpace" => "DeviceCMYK",
+                "BitsPerComponent" => 16,
+            };
+            let image = doc.add_object(Stream::new(dict, vec![0; 8]));
+            assert!(decode_image(&doc, &image.into(), Color::BLACK).is_err());
+        }
+    }
+
+    #[test]
+    fn smask_gives_images_alpha() {
+        let mut doc = Document::new();
+        let gray = |width: i64, data: Vec<u8>| {
+            Stream::new(
+                dictionary! {
+                    "Width" => width,
+                    "Height" => 1,
+                    "ColorSpace" => "DeviceGray",
+                    "BitsPerComponent" => 8,
+                },
+                data,
+            )
+        };
+        let image = |doc: &mut Document, smask| {
+            let mut image = gray(2, vec![0, 255]);
+            image.dict.set("SMask", doc.add_object(smask));
+            let image = doc.add_object(image);
+            let image = decode_image(doc, &image.into(), Color::BLACK).unwrap();
+            image.data.data().to_vec()
+        };
+        let rgba = image(&mut doc, gray(2, vec![255, 64]));
+        assert_eq!(rgba, [0, 0, 0, 255, 255, 255, 255, 64]);
+        // Masks of another size are stretched over the image
+        let rgba = image(&mut doc, gray(1, vec![128]));
+        assert_eq!(rgba, [0, 0, 0, 128, 255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn dct_images_are_decoded_through_their_decode_ranges() {
+        let image = |space: &str, pixels: &[u8], color, ranges: &[i64]| {
+            let dict = dictionary! {
+                "Width" => 8,
+                "Height" => 8,
+                "ColorSpace" => space,
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+                "Decode" => ranges.iter().map(|d| (*d).into()).collect::<Vec<Object>>(),
+            };
+            let rgba = decode(dict, jpeg(&pixels.repeat(64), 8, 8, color));
+            rgba[..4].to_vec()
+        };
+        let gray = image("DeviceGray", &[200], ExtendedColorType::L8, &[1, 0]);
+        assert!(close(&gray, &[55, 55, 55, 255]), "{gray:?}");
+        let rgb = image(
+            "DeviceRGB",
+            &[200, 30, 30],
+            ExtendedColorType::Rgb8,
+            &[1, 0, 0, 1, 0, 1],
+        );
+        assert!(close(&rgb, &[55, 30, 30, 255]), "{rgb:?}");
+    }
+
+    #[test]
+    fn samples_are_unpacked_at_any_depth() {
+        let image = |space: &str, bpc: i64, width: i64, data: Vec<u8>| {
+            let dict = dictionary! {
+                "Width" => width,
+                "Height" => 2,
+                "ColorSpace" => space,
+                "BitsPerComponent" => bpc,
+            };
+            decode(dict, data)
+        };
+        let (black, white) = ([0, 0, 0, 255], [255, 255, 255, 255]);
+        // Rows start on a byte boundary
+        let one = image("DeviceGray", 1, 3, vec![0b1010_0000, 0b0100_0000]);
+        assert_eq!(one, [white, black, white, black, white, black].concat());
+        let two = image("DeviceGray", 2, 2, vec![0b0001_0000, 0b1011_0000]);
+        let gray = |g| [g, g, g, 255];
+        assert_eq!(two, [gray(0), gray(85), gray(170), gray(255)].concat());
+        let four = image("DeviceRGB", 4, 1, vec![0xF0, 0x80, 0x0F, 0xF0]);
+        assert_eq!(four, [[255, 0, 136, 255], [0, 255, 255, 255]].concat());
+        let sixteen = image("DeviceGray", 16, 1, vec![0xFF, 0xFF, 0x80, 0x00]);
+        assert_eq!(sixteen, [white, gray(128)].concat());
+        // Indexed samples are palette indices
+        let palette = Object::String(vec![255, 0, 0, 0, 0, 255], lopdf::StringFormat::Hexadecimal);
+        let mut doc = Document::new();
+        let indexed = doc.add_object(Stream::new(
+            dictionary! {
+                "Width" => 2,
+                "Height" => 1,
+                "ColorSpace" => vec!["Indexed".into(), "DeviceRGB".into(), 1.into(), palette],
+                "BitsPerComponent" => 1,
+            },
+            vec![0b0100_0000],
+        ));
+        let rgba = decode_image(&doc, &indexed.into(), Color::BLACK).unwrap();
+        assert_eq!(rgba.data.data(), [255, 0, 0, 255, 0, 0, 255, 255]);
+        // Other depths aren't valid
+        let dict = dictionary! {
+            "Width" => 1,
+            "Height" => 1,
+            "ColorSpace" => "DeviceGray",
+            "BitsPerComponent" => 3,
+        };
+        let three = doc.add_object(Stream::new(dict, vec![0]));
+        assert!(decode_image(&doc, &three.into(), Color::BLACK).is_err());
+    }
+
+    // A baseline 8 x 8 CMYK JPEG stored as flat `samples`, optionally with
+    // Adobe's APP14 marker
+    fn cmyk_jpeg(samples: [u8; 4], adobe: bool) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        let mut segment = |marker: u8, body: &[u8]| {
+            data.extend([0xFF, marker]);
+            data.extend((body.len() as u16 + 2).to_be_bytes());
+            data.extend(body);
+        };
+        if adobe {
+            segment(0xEE, b"Adobe\0\x64\0\0\0\0\0");
+        }
+        segment(0xDB, &[[0].as_slice(), &[1; 64]].concat());
+        let components = (1..=4).flat_map(|id| [id, 0x11, 0]);
+        segment(
+            0xC0,
+            &[8, 0, 8, 0, 8, 4]
+                .into_iter()
+                .chain(components)
+                .collect::<Vec<_>>(),
+        );
+        // DC differences of every size have four bit codes, and the only AC
+        // code ends the block
+        let mut dc = vec![0x00, 0, 0, 0, 12];
+        dc.extend([0; 12]);
+        dc.extend(0..12);
+        segment(0xC4, &dc);
+        segment(0xC4, &[[0x10, 1].as_slice(), &[0; 15], &[0]].concat());
+        let components = (1..=4).flat_map(|id| [id, 0]);
+        let sos: Vec<u8> = [4]
+            .into_iter()
+            .chain(components)
+            .chain([0, 63, 0])
+            .collect();
+        segment(0xDA, &sos);
+
+        let mut bits = Vec::new();
+        for sample in samples {
+            // The DC coefficient of a flat block, with quantization 1
+            let value = 8 * (sample as i32 - 128);
+            let size = 32 - value.unsigned_abs().leading_zeros();
+            let coded = if value < 0 {
+                value + (1 << size) - 1
+            } else {
+                value
+            };
+            bits.extend((0..4).rev().map(|i| size >> i & 1));
+            bits.extend((0..size).rev().map(|i| coded as u32 >> i & 1));
+            bits.push(0);
+        }
+        bits.resize(bits.len().div_ceil(8) * 8, 1);
+        for byte in bits.chunks(8) {
+            let byte = byte.iter().fold(0, |byte, bit| byte << 1 | *bit as u8);
+            data.push(byte);
+            if byte == 0xFF {
+                data.push(0);
+            }
+        }
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn cmyk_jpegs_are_only_inverted_with_adobes_marker() {
+        let image = |adobe| {
+            let dict = dictionary! {
+                "Width" => 8,
+                "Height" => 8,
+                "ColorSpace" => "DeviceCMYK",
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+            };
+            decode(dict, cmyk_jpeg([0, 255, 255, 0], adobe))
+        };
+        assert!(!has_adobe_marker(&cmyk_jpeg([0; 4], false)));
+        assert!(has_adobe_marker(&cmyk_jpeg([0; 4], true)));
+        let plain = image(false);
+        assert!(close(&plain[..4], &[255, 0, 0, 255]), "{:?}", &plain[..4]);
+        let inverted = image(true);
+        assert!(
+            close(&inverted[..4], &[0, 0, 0, 255]),
+            "{:?}",
+            &inverted[..4]
+        );
+    }
+
+    #[test]
+    fn cmyk_jpegs_must_be_the_size_of_the_image() {
+        let mut doc = Document::new();
+        for size in [4, 16] {
+            let dict = dictionary! {
+                "Width" => size,
+                "Height" => size,
+                "ColorSpace" => "DeviceCMYK",
+                "BitsPerComponent" => 8,
+                "Filter" => "DCTDecode",
+            };
+            let image = doc.add_object(Stream::new(dict, cmyk_jpeg([0; 4], false)));
+            assert!(decode_image(&doc, &image.into(), Color::BLACK).is_err());
+        }
+    }
+
+    #[test]
+    fn other_image_codecs_are_unsupported() {
+        let mut doc = Document::new();
+        for filter in ["JPXDecode", "CCITTFaxDecode", "JBIG2Decode"] {
+            for mut dict in [mask(8, 1, &[0, 1]), Dictionary::new()] {
+                dict.set("Width", 8);
+                dict.set("Height", 1);
+                dict.set("ColorSpace", "DeviceGray");
+                dict.set("BitsPerComponent", 1);
+                dict.set("Filter", vec!["FlateDecode".into(), filter.into()]);
+                let image = doc.add_object(Stream::new(dict, vec![0; 8]));
+                let error = decode_image(&doc, &image.into(), Color::BLACK).unwrap_err();
+                assert!(error.to_string().contains(filter), "{error}");
+            }
+        }
+    }
+}